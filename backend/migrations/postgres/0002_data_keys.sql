@@ -0,0 +1,10 @@
+-- Per-user key for content encrypted at rest, wrapped by the master key master_key_id
+CREATE TABLE data_keys (
+    user_id BIGINT PRIMARY KEY NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    master_key_id TEXT NOT NULL,
+    wrapped_key BYTEA NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    rotated_at TIMESTAMPTZ
+);
+
+CREATE INDEX data_keys_master_key_id ON data_keys (master_key_id);
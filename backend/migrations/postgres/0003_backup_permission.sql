@@ -0,0 +1,3 @@
+-- Taking database backups is a permission of its own, granted to admins
+INSERT INTO role_permissions (role, permission)
+    SELECT name, 'manage_backups' FROM roles WHERE name = 'ADMIN';
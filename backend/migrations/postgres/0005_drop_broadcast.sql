@@ -0,0 +1,2 @@
+-- Nothing ever checked the broadcast permission
+DELETE FROM role_permissions WHERE permission = 'broadcast';
@@ -0,0 +1,42 @@
+-- Roles are now rows made of permission sets instead of a fixed enum
+CREATE TABLE roles (
+    name TEXT PRIMARY KEY NOT NULL,
+    description TEXT NOT NULL DEFAULT ''
+);
+
+CREATE TABLE role_permissions (
+    role TEXT NOT NULL REFERENCES roles (name) ON DELETE CASCADE ON UPDATE CASCADE,
+    permission TEXT NOT NULL,
+    PRIMARY KEY (role, permission)
+);
+
+INSERT INTO roles (name, description) VALUES
+    ('ADMIN', 'Full access to every administrative feature'),
+    ('USER', 'Regular user without administrative permissions');
+
+INSERT INTO role_permissions (role, permission) VALUES
+    ('ADMIN', 'manage_users'),
+    ('ADMIN', 'manage_roles'),
+    ('ADMIN', 'view_audit_log'),
+    ('ADMIN', 'read_metrics'),
+    ('ADMIN', 'broadcast');
+
+-- Any custom value that slipped into users.role becomes a regular user
+UPDATE users SET role = 'USER' WHERE role NOT IN ('ADMIN', 'USER');
+
+-- Recreate users so that role references the roles table
+CREATE TABLE users_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL,
+    email TEXT NOT NULL UNIQUE,
+    password TEXT NOT NULL,
+    role TEXT NOT NULL REFERENCES roles (name) ON UPDATE CASCADE,
+    UNIQUE (id, email)
+);
+
+INSERT INTO users_new (id, name, email, password, role)
+    SELECT id, name, email, password, role FROM users;
+
+DROP TABLE users;
+
+ALTER TABLE users_new RENAME TO users;
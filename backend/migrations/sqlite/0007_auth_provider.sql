@@ -0,0 +1,2 @@
+-- Pins a user to one authentication provider, NULL tries every configured provider
+ALTER TABLE users ADD COLUMN auth_provider TEXT;
@@ -0,0 +1,2 @@
+-- Disabled users keep their data but can neither log in nor use existing tokens
+ALTER TABLE users ADD COLUMN disabled_at DATETIME;
@@ -0,0 +1,14 @@
+-- Every client a user has connected with, kept after it disconnects
+CREATE TABLE devices (
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    client_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    platform TEXT,
+    app_version TEXT,
+    first_seen_at DATETIME NOT NULL,
+    last_seen_at DATETIME NOT NULL,
+    last_ip TEXT,
+    -- revoked clients may not reconnect under the same id
+    revoked_at DATETIME,
+    PRIMARY KEY (user_id, client_id)
+);
@@ -0,0 +1,2 @@
+-- Sessions of device-bound tokens, revoked together with their device
+ALTER TABLE sessions ADD COLUMN client_id TEXT;
@@ -0,0 +1,3 @@
+-- Guest devices are limited to one direction and stop working at expires_at
+ALTER TABLE devices ADD COLUMN access TEXT NOT NULL DEFAULT 'full';
+ALTER TABLE devices ADD COLUMN expires_at DATETIME;
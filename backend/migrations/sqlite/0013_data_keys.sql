@@ -0,0 +1,10 @@
+-- Per-user key for content encrypted at rest, wrapped by the master key master_key_id
+CREATE TABLE data_keys (
+    user_id INTEGER PRIMARY KEY NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    master_key_id TEXT NOT NULL,
+    wrapped_key BLOB NOT NULL,
+    created_at DATETIME NOT NULL,
+    rotated_at DATETIME
+);
+
+CREATE INDEX data_keys_master_key_id ON data_keys (master_key_id);
@@ -0,0 +1,6 @@
+-- Users whose expired sessions, reset links and guest devices are kept for
+-- another number of days than the server wide retention
+CREATE TABLE user_retention (
+    user_id INTEGER PRIMARY KEY NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    days INTEGER NOT NULL
+);
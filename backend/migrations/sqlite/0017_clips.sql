@@ -0,0 +1,23 @@
+-- Clips relayed between the devices of a user, content is sealed with the
+-- user's data key and the clip id as additional data
+CREATE TABLE clips (
+    id TEXT PRIMARY KEY NOT NULL,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    -- the device that sent the clip
+    client_id TEXT NOT NULL,
+    content BLOB NOT NULL,
+    created_at DATETIME NOT NULL
+);
+
+CREATE INDEX clips_user_id ON clips (user_id, created_at);
+
+-- Clips waiting for a device that was offline when they were relayed
+CREATE TABLE clip_queue (
+    clip_id TEXT NOT NULL REFERENCES clips (id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    client_id TEXT NOT NULL,
+    queued_at DATETIME NOT NULL,
+    PRIMARY KEY (clip_id, client_id)
+);
+
+CREATE INDEX clip_queue_client_id ON clip_queue (user_id, client_id);
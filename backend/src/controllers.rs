@@ -1,6 +1,13 @@
 pub mod admin;
+pub mod audit;
 pub mod auth;
+pub mod backup;
+pub mod invite;
+pub mod oidc;
+pub mod pairing;
+pub mod role;
 pub mod token;
+pub mod totp;
 pub mod user;
 pub mod websocket;
 
@@ -1,10 +1,43 @@
 use std::sync::Arc;
 
-use axum::{extract::State, Json, http::StatusCode};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{Duration, Utc};
 use serde_json::{json, Value};
-use tokio::sync::{RwLock, Mutex};
+use tokio::sync::Mutex;
 
-use crate::{error::AppError, models::{user::{UserEntity, UserCreate, Role}, auth::LoginResponse, jwt::TokenType}, AppState, service::user::{get_user_by_email, create_user}, utils::encode_token};
+use crate::{
+    error::AppError,
+    middleware::RequestMeta,
+    models::{
+        audit::{AuditAction, AuditEvent},
+        auth::{AuthProviderUpdate, LoginResponse},
+        jwt::TokenType,
+        password::{PasswordResetLink, PasswordResetRequest},
+        retention::{JobStatus, UserRetention},
+        user::{Role, UserCreate, UserDetails, UserEntity, UserListQuery, UserPage, UserUpdate},
+    },
+    service::{
+        audit,
+        password_reset::create_password_reset,
+        retention::set_user_retention,
+        role::check_assignable,
+        session::revoke_user_sessions,
+        totp::delete_totp,
+        user::{
+            count_users, create_user, delete_user, get_user_by_id, get_user_details, list_users,
+            set_user_auth_provider, set_user_disabled, update_user, validate_new_user,
+        },
+    },
+    utils::{generate_token, hash_token, login_response},
+    AppState,
+};
+
+const PASSWORD_RESET_MINUTES: i64 = 60;
+const MAX_PASSWORD_RESET_MINUTES: i64 = 7 * 24 * 60;
 
 pub struct AdminController {}
 
@@ -14,33 +47,327 @@ impl AdminController {
     }
 
     pub async fn register(
+        admin: UserEntity,
         State(state): State<Arc<Mutex<AppState>>>,
+        meta: RequestMeta,
         Json(payload): Json<UserCreate>,
     ) -> Result<(StatusCode, Json<LoginResponse>), AppError> {
-        if payload.password.is_empty() || payload.email.is_empty() || payload.name.is_empty() {
-            return Err(AppError::EmptyPayload);
+        let state = state.lock().await;
+        validate_new_user(&state.db, &state.password_policy, &payload).await?;
+        check_assignable(&state.db, &admin.role, &payload.role).await?;
+
+        let mut user = payload.clone();
+
+        let user = create_user(&state.db, &mut user, &state.hash_params).await?;
+        audit::record(
+            &state.db,
+            AuditEvent::new(AuditAction::UserCreate, &meta)
+                .actor(&admin)
+                .target(format!("user:{}", user.id))
+                .detail(format!("role {}", user.role)),
+        )
+        .await;
+
+        let response =
+            login_response(&state.db, &user, &state.keys, "User created successfully").await?;
+
+        Ok((StatusCode::CREATED, Json(response)))
+    }
+
+    pub async fn list_users(
+        State(state): State<Arc<Mutex<AppState>>>,
+        Query(filter): Query<UserListQuery>,
+    ) -> Result<Json<UserPage>, AppError> {
+        let per_page = filter.per_page.clamp(1, UserListQuery::MAX_PER_PAGE);
+        // far past the last user anyway, without overflowing the offset
+        let page = filter.page.clamp(1, i64::MAX / per_page);
+
+        let state = state.lock().await;
+        let total = count_users(&state.db, &filter).await?;
+        let users = list_users(&state.db, &filter, per_page, (page - 1) * per_page).await?;
+
+        Ok(Json(UserPage {
+            users,
+            total,
+            page,
+            per_page,
+        }))
+    }
+
+    pub async fn get_user(
+        State(state): State<Arc<Mutex<AppState>>>,
+        Path(id): Path<i64>,
+    ) -> Result<Json<UserDetails>, AppError> {
+        let state = state.lock().await;
+        let user = get_user_details(&state.db, id).await?;
+        Ok(Json(user))
+    }
+
+    pub async fn update_user(
+        admin: UserEntity,
+        State(state): State<Arc<Mutex<AppState>>>,
+        meta: RequestMeta,
+        Path(id): Path<i64>,
+        Json(payload): Json<UserUpdate>,
+    ) -> Result<Json<UserDetails>, AppError> {
+        let state = state.lock().await;
+        Self::managed_user(&state, &admin, id).await?;
+        if let Some(role) = &payload.role {
+            // nor promote someone past the caller's own role
+            check_assignable(&state.db, &admin.role, role).await?;
+        }
+        let user = update_user(&state.db, id, &payload).await?;
+        audit::record(
+            &state.db,
+            AuditEvent::new(AuditAction::UserUpdate, &meta)
+                .actor(&admin)
+                .target(format!("user:{}", id))
+                .detail(format!("{:?}", payload)),
+        )
+        .await;
+        Ok(Json(user))
+    }
+
+    /// Blocks the user from logging in and ends all their sessions and
+    /// connections.
+    pub async fn disable(
+        admin: UserEntity,
+        State(state): State<Arc<Mutex<AppState>>>,
+        meta: RequestMeta,
+        Path(id): Path<i64>,
+    ) -> Result<StatusCode, AppError> {
+        if admin.id == id {
+            return Err(AppError::CannotModifySelf);
+        }
+        let mut state = state.lock().await;
+        Self::managed_user(&state, &admin, id).await?;
+        set_user_disabled(&state.db, id, true).await?;
+        revoke_user_sessions(&state.db, id).await?;
+        state.disconnect_user(&id, "account disabled").await;
+        audit::record(
+            &state.db,
+            AuditEvent::new(AuditAction::UserDisable, &meta)
+                .actor(&admin)
+                .target(format!("user:{}", id)),
+        )
+        .await;
+        Ok(StatusCode::NO_CONTENT)
+    }
+
+    pub async fn enable(
+        admin: UserEntity,
+        State(state): State<Arc<Mutex<AppState>>>,
+        meta: RequestMeta,
+        Path(id): Path<i64>,
+    ) -> Result<StatusCode, AppError> {
+        let state = state.lock().await;
+        Self::managed_user(&state, &admin, id).await?;
+        set_user_disabled(&state.db, id, false).await?;
+        audit::record(
+            &state.db,
+            AuditEvent::new(AuditAction::UserEnable, &meta)
+                .actor(&admin)
+                .target(format!("user:{}", id)),
+        )
+        .await;
+        Ok(StatusCode::NO_CONTENT)
+    }
+
+    pub async fn delete_user(
+        admin: UserEntity,
+        State(state): State<Arc<Mutex<AppState>>>,
+        meta: RequestMeta,
+        Path(id): Path<i64>,
+    ) -> Result<StatusCode, AppError> {
+        if admin.id == id {
+            return Err(AppError::CannotModifySelf);
         }
+        let mut state = state.lock().await;
+        let user = Self::managed_user(&state, &admin, id).await?;
+        delete_user(&state.db, user.id).await?;
+        state.disconnect_user(&user.id, "account deleted").await;
+        audit::record(
+            &state.db,
+            AuditEvent::new(AuditAction::UserDelete, &meta)
+                .actor(&admin)
+                .target(format!("user:{}", user.id))
+                .detail(&user.email),
+        )
+        .await;
+        Ok(StatusCode::NO_CONTENT)
+    }
+
+    /// Revokes every session of the user, they have to log in again.
+    pub async fn logout(
+        admin: UserEntity,
+        State(state): State<Arc<Mutex<AppState>>>,
+        meta: RequestMeta,
+        Path(id): Path<i64>,
+    ) -> Result<StatusCode, AppError> {
+        let mut state = state.lock().await;
+        let user = Self::managed_user(&state, &admin, id).await?;
+        let revoked = revoke_user_sessions(&state.db, user.id).await?;
+        state.disconnect_user(&user.id, "logged out by admin").await;
+        audit::record(
+            &state.db,
+            AuditEvent::new(AuditAction::UserLogout, &meta)
+                .actor(&admin)
+                .target(format!("user:{}", user.id))
+                .detail(format!("{} sessions revoked", revoked)),
+        )
+        .await;
+        Ok(StatusCode::NO_CONTENT)
+    }
+
+    /// Removes a user's TOTP secret and recovery codes, e.g. after a lost phone.
+    pub async fn reset_totp(
+        admin: UserEntity,
+        State(state): State<Arc<Mutex<AppState>>>,
+        meta: RequestMeta,
+        Path(id): Path<i64>,
+    ) -> Result<StatusCode, AppError> {
         let state = state.lock().await;
-        let user = get_user_by_email(&state.pool, payload.email.as_str()).await?;
+        let user = Self::managed_user(&state, &admin, id).await?;
+        delete_totp(&state.db, user.id).await?;
+        audit::record(
+            &state.db,
+            AuditEvent::new(AuditAction::TotpReset, &meta)
+                .actor(&admin)
+                .target(format!("user:{}", user.id)),
+        )
+        .await;
+        Ok(StatusCode::NO_CONTENT)
+    }
+
+    /// Lifts a login backoff or lockout on the user's account.
+    pub async fn unlock(
+        admin: UserEntity,
+        State(state): State<Arc<Mutex<AppState>>>,
+        meta: RequestMeta,
+        Path(id): Path<i64>,
+    ) -> Result<StatusCode, AppError> {
+        let mut state = state.lock().await;
+        let user = Self::managed_user(&state, &admin, id).await?;
+        state.login_throttle.unlock_account(&user.email);
+        audit::record(
+            &state.db,
+            AuditEvent::new(AuditAction::AccountUnlock, &meta)
+                .actor(&admin)
+                .target(format!("user:{}", user.id)),
+        )
+        .await;
+        Ok(StatusCode::NO_CONTENT)
+    }
 
-        if let Some(data) = user {
-            return Err(AppError::UserAlreadyExits);
+    pub async fn set_auth_provider(
+        admin: UserEntity,
+        State(state): State<Arc<Mutex<AppState>>>,
+        meta: RequestMeta,
+        Path(id): Path<i64>,
+        Json(payload): Json<AuthProviderUpdate>,
+    ) -> Result<StatusCode, AppError> {
+        let state = state.lock().await;
+        Self::managed_user(&state, &admin, id).await?;
+        if let Some(provider) = &payload.provider {
+            if !state.auth_providers.contains(provider) {
+                return Err(AppError::UnknownAuthProvider);
+            }
         }
+        set_user_auth_provider(&state.db, id, payload.provider.as_deref()).await?;
+        audit::record(
+            &state.db,
+            AuditEvent::new(AuditAction::AuthProviderChange, &meta)
+                .actor(&admin)
+                .target(format!("user:{}", id))
+                .detail(payload.provider.as_deref().unwrap_or("any")),
+        )
+        .await;
+        Ok(StatusCode::NO_CONTENT)
+    }
 
-        let mut user = payload.clone();
+    /// Issues a single-use reset link for a user who forgot their password.
+    pub async fn create_password_reset(
+        admin: UserEntity,
+        State(state): State<Arc<Mutex<AppState>>>,
+        meta: RequestMeta,
+        Path(id): Path<i64>,
+        payload: Option<Json<PasswordResetRequest>>,
+    ) -> Result<(StatusCode, Json<PasswordResetLink>), AppError> {
+        let Json(payload) = payload.unwrap_or_default();
+        let expires_in = payload
+            .expires_in_minutes
+            .unwrap_or(PASSWORD_RESET_MINUTES)
+            .clamp(1, MAX_PASSWORD_RESET_MINUTES);
+
+        let state = state.lock().await;
+        let user = Self::managed_user(&state, &admin, id).await?;
 
-        let user = create_user(&state.pool, &mut user).await?;
+        let token = generate_token(32);
+        let expires_at = Utc::now() + Duration::minutes(expires_in);
+        create_password_reset(
+            &state.db,
+            user.id,
+            admin.id,
+            &hash_token(&token),
+            expires_at,
+        )
+        .await?;
+        audit::record(
+            &state.db,
+            AuditEvent::new(AuditAction::PasswordResetIssue, &meta)
+                .actor(&admin)
+                .target(format!("user:{}", user.id)),
+        )
+        .await;
 
-        let access_token = encode_token(&user, &state.keys, TokenType::AccessToken).await?;
-        let refresh_token = encode_token(&user, &state.keys, TokenType::RefreshToken).await?;
-        let response = LoginResponse {
-            message: "User created successfully".to_string(),
-            id: user.id,
-            role: user.role,
-            access_token,
-            refresh_token,
+        let link = PasswordResetLink {
+            path: format!("/reset-password?token={}", token),
+            token,
+            expires_at,
         };
+        Ok((StatusCode::CREATED, Json(link)))
+    }
 
-        Ok((StatusCode::CREATED, Json(response)))
+    /// Overrides the server wide retention for one user.
+    pub async fn set_retention(
+        admin: UserEntity,
+        State(state): State<Arc<Mutex<AppState>>>,
+        meta: RequestMeta,
+        Path(id): Path<i64>,
+        Json(payload): Json<UserRetention>,
+    ) -> Result<Json<UserRetention>, AppError> {
+        let state = state.lock().await;
+        let user = Self::managed_user(&state, &admin, id).await?;
+        set_user_retention(&state.db, user.id, &payload).await?;
+        audit::record(
+            &state.db,
+            AuditEvent::new(AuditAction::RetentionChange, &meta)
+                .actor(&admin)
+                .target(format!("user:{}", id))
+                .detail(format!("{:?} days", payload.days)),
+        )
+        .await;
+
+        Ok(Json(payload))
+    }
+
+    /// The background jobs with their last and next run.
+    pub async fn jobs(
+        State(state): State<Arc<Mutex<AppState>>>,
+    ) -> Result<Json<Vec<JobStatus>>, AppError> {
+        let state = state.lock().await;
+        Ok(Json(state.scheduler.jobs()))
+    }
+
+    /// The user `id`, as long as `admin` could assign them their role. Managing
+    /// users does not extend to accounts with more permissions than the caller.
+    async fn managed_user(
+        state: &AppState,
+        admin: &UserEntity,
+        id: i64,
+    ) -> Result<UserEntity, AppError> {
+        let user = get_user_by_id(&state.db, id).await?;
+        check_assignable(&state.db, &admin.role, &user.role).await?;
+        Ok(user)
     }
 }
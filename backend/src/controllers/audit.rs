@@ -0,0 +1,93 @@
+use std::sync::Arc;
+
+use axum::{
+    body::StreamBody,
+    extract::{Query, State},
+    http::header,
+    response::{IntoResponse, Response},
+    Json,
+};
+use futures::stream;
+use tokio::sync::Mutex;
+
+use crate::{
+    error::AppError,
+    models::audit::{AuditPage, AuditQuery},
+    service::audit::{count_audit, list_audit},
+    AppState,
+};
+
+/// Entries fetched per query while exporting.
+const EXPORT_BATCH: i64 = 1000;
+
+pub struct AuditController {}
+
+impl AuditController {
+    pub async fn list(
+        State(state): State<Arc<Mutex<AppState>>>,
+        Query(filter): Query<AuditQuery>,
+    ) -> Result<Json<AuditPage>, AppError> {
+        let per_page = filter.per_page.clamp(1, AuditQuery::MAX_PER_PAGE);
+        // far past the last entry anyway, without overflowing the offset
+        let page = filter.page.clamp(1, i64::MAX / per_page);
+
+        let state = state.lock().await;
+        let total = count_audit(&state.db, &filter).await?;
+        let entries = list_audit(&state.db, &filter, None, per_page, (page - 1) * per_page).await?;
+
+        Ok(Json(AuditPage {
+            entries,
+            total,
+            page,
+            per_page,
+        }))
+    }
+
+    /// Every matching entry as JSON Lines, newest first. Pagination parameters
+    /// are ignored.
+    pub async fn export(
+        State(state): State<Arc<Mutex<AppState>>>,
+        Query(filter): Query<AuditQuery>,
+    ) -> Result<Response, AppError> {
+        let db = state.lock().await.db.clone();
+
+        // batches continue below the last exported id, the state is None once done
+        let lines = stream::unfold(Some(None), move |before_id| {
+            let db = db.clone();
+            let filter = filter.clone();
+            async move {
+                let before_id = before_id?;
+                let batch = match list_audit(&db, &filter, before_id, EXPORT_BATCH, 0).await {
+                    Ok(batch) => batch,
+                    Err(err) => {
+                        tracing::error!("Error exporting audit log: {:?}", err);
+                        let err = std::io::Error::other("database error");
+                        return Some((Err(err), None));
+                    }
+                };
+                let next = match batch.last() {
+                    Some(last) if batch.len() as i64 == EXPORT_BATCH => Some(Some(last.id)),
+                    _ => None,
+                };
+                let lines: String = batch
+                    .iter()
+                    .filter_map(|entry| serde_json::to_string(entry).ok())
+                    .map(|line| line + "\n")
+                    .collect();
+                Some((Ok::<_, std::io::Error>(lines), next))
+            }
+        });
+
+        Ok((
+            [
+                (header::CONTENT_TYPE, "application/x-ndjson"),
+                (
+                    header::CONTENT_DISPOSITION,
+                    "attachment; filename=\"audit.jsonl\"",
+                ),
+            ],
+            StreamBody::new(lines),
+        )
+            .into_response())
+    }
+}
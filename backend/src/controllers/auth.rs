@@ -1,26 +1,35 @@
-use std::sync::Arc;
+use std::{net::SocketAddr, sync::Arc};
 
 use axum::{
-    extract::State,
+    extract::{ConnectInfo, State},
     http::StatusCode,
     response::{IntoResponse, Response},
     Extension, Json,
 };
 use jsonwebtoken::Header;
 use serde_json::{json, Value};
-use sqlx::SqlitePool;
 use tokio::sync::Mutex;
 
 use crate::{
     error::AppError,
+    middleware::RequestMeta,
     models::{
         self,
-        auth::LoginResponse,
+        audit::{AuditAction, AuditEvent},
+        auth::{LoginResponse, MfaLogin, MfaPendingResponse},
         jwt::{Claims, TokenType},
+        password::PasswordReset,
         user::{Role, UserCreate, UserEntity, UserLogin},
     },
-    service::user::{create_user, get_user_by_email},
-    utils::encode_token,
+    service::{
+        audit,
+        password_reset::{consume_password_reset, get_password_reset_user},
+        totp::{is_totp_enabled, verify_second_factor},
+        user::{
+            create_user, get_user_by_email, get_user_by_id, get_user_by_id_email, is_user_disabled,
+        },
+    },
+    utils::{decode_token, encode_token, hash_token, login_response},
     AppState,
 };
 
@@ -30,34 +39,132 @@ impl AuthController {
 
     pub async fn login(
         State(state): State<Arc<Mutex<AppState>>>,
+        meta: RequestMeta,
         Json(payload): Json<UserLogin>,
-    ) -> Result<Json<LoginResponse>, AppError> {
-        let state = state.lock().await;
-        let user = get_user_by_email(&state.pool, payload.email.as_str()).await?;
-
-        if let Some(user) = user {
-            let is_verified = user.verify_password(payload.password.as_bytes())?;
-
-            if is_verified {
-                let access_token = encode_token(&user, &state.keys, TokenType::AccessToken).await?;
-                let refresh_token =
-                    encode_token(&user, &state.keys, TokenType::RefreshToken).await?;
-
-                let response = LoginResponse {
-                    message: "User created successfully".to_string(),
-                    id: user.id,
-                    role: user.role,
-                    access_token,
-                    refresh_token,
-                };
-
-                return Ok(Json(response));
+    ) -> Result<Response, AppError> {
+        let ip = meta.ip;
+        let event = AuditEvent::new(AuditAction::Login, &meta).actor_email(&payload.email);
+        let (ctx, providers) = {
+            let mut state = state.lock().await;
+            // counted as failed until the credentials are checked
+            if let Err(err) = state.login_throttle.reserve(&payload.email, ip) {
+                audit::record(&state.db, event.failure("throttled")).await;
+                return Err(err);
             }
+            (state.auth_context(), state.auth_providers.clone())
+        };
 
+        let user = providers
+            .authenticate(&ctx, &payload.email, &payload.password)
+            .await;
+
+        let mut state = state.lock().await;
+        let user = match user {
+            Ok(Some(user)) => user,
+            Ok(None) => {
+                audit::record(&state.db, event.failure("wrong credentials")).await;
+                return Err(AppError::WrongCredential);
+            }
+            Err(err) => {
+                audit::record(&state.db, event.failure("provider unavailable")).await;
+                return Err(err);
+            }
+        };
+        let event = event.actor(&user);
+
+        // answered like a wrong password, the credentials must not be confirmed
+        if is_user_disabled(&state.db, user.id).await? {
+            audit::record(&state.db, event.failure("user disabled")).await;
             return Err(AppError::WrongCredential);
         }
 
-        Err(AppError::UserDoesNotExist)
+        state.login_throttle.release(&payload.email, ip);
+        if is_totp_enabled(&state.db, user.id).await? {
+            let mfa_token = encode_token(&user, &state.keys, TokenType::MfaPending, None).await?;
+            let response = MfaPendingResponse {
+                message: "Two-factor code required".to_string(),
+                mfa_token,
+            };
+            audit::record(&state.db, event.detail("second factor required")).await;
+            return Ok((StatusCode::ACCEPTED, Json(response)).into_response());
+        }
+
+        state.login_throttle.record_success(&payload.email);
+        let response = login_response(&state.db, &user, &state.keys, "Login successful").await?;
+        audit::record(&state.db, event).await;
+
+        Ok(Json(response).into_response())
+    }
+
+    /// Second login step for users with TOTP enabled, exchanging the short-lived
+    /// mfa token from [`Self::login`] and a TOTP or recovery code for real tokens.
+    pub async fn login_mfa(
+        State(state): State<Arc<Mutex<AppState>>>,
+        meta: RequestMeta,
+        Json(payload): Json<MfaLogin>,
+    ) -> Result<Json<LoginResponse>, AppError> {
+        let mut state = state.lock().await;
+        let claims = decode_token(payload.mfa_token.as_str(), &state.keys).await?;
+
+        match claims.token_type {
+            TokenType::MfaPending => {
+                let ip = meta.ip;
+                state.login_throttle.check(&claims.email, ip)?;
+
+                let user =
+                    get_user_by_id_email(&state.db, claims.id, claims.email.as_str()).await?;
+
+                let event = AuditEvent::new(AuditAction::LoginMfa, &meta).actor(&user);
+                // disabled since the first step, answered like the login would
+                if is_user_disabled(&state.db, user.id).await? {
+                    audit::record(&state.db, event.failure("user disabled")).await;
+                    return Err(AppError::WrongCredential);
+                }
+                if !verify_second_factor(&state.db, user.id, payload.code.as_str()).await? {
+                    state.login_throttle.record_failure(&claims.email, ip);
+                    audit::record(&state.db, event.failure("invalid code")).await;
+                    return Err(AppError::InvalidMfaCode);
+                }
+
+                state.login_throttle.record_success(&claims.email);
+                let response =
+                    login_response(&state.db, &user, &state.keys, "Login successful").await?;
+                audit::record(&state.db, event).await;
+                Ok(Json(response))
+            }
+            _ => Err(AppError::InvalidToken),
+        }
+    }
+
+    /// Consumes an admin-issued reset token. All sessions of the user are
+    /// revoked and their live connections closed.
+    pub async fn reset_password(
+        State(state): State<Arc<Mutex<AppState>>>,
+        meta: RequestMeta,
+        Json(payload): Json<PasswordReset>,
+    ) -> Result<StatusCode, AppError> {
+        let mut state = state.lock().await;
+        let token_hash = hash_token(&payload.token);
+
+        // only peeked at to check the password against the email
+        let user_id = get_password_reset_user(&state.db, &token_hash).await?;
+        let user = get_user_by_id(&state.db, user_id).await?;
+        state
+            .password_policy
+            .validate(&payload.new_password, &user.email)?;
+        let password_hash = state.hash_params.hash(payload.new_password.as_bytes())?;
+
+        let user_id = consume_password_reset(&state.db, &token_hash, &password_hash).await?;
+        let user = get_user_by_id(&state.db, user_id).await?;
+        state.disconnect_user(&user.id, "password reset").await;
+        state.login_throttle.unlock_account(&user.email);
+        audit::record(
+            &state.db,
+            AuditEvent::new(AuditAction::PasswordReset, &meta).actor(&user),
+        )
+        .await;
+
+        Ok(StatusCode::NO_CONTENT)
     }
 
     pub async fn authenticated(
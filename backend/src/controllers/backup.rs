@@ -0,0 +1,71 @@
+use std::sync::Arc;
+
+use axum::{
+    body::StreamBody,
+    extract::State,
+    http::header,
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+use futures::StreamExt;
+use tokio::sync::Mutex;
+use tokio_util::io::ReaderStream;
+
+use crate::{
+    error::AppError,
+    middleware::RequestMeta,
+    models::{
+        audit::{AuditAction, AuditEvent},
+        user::UserEntity,
+    },
+    service::{audit, backup::backup_database_to_file},
+    AppState,
+};
+
+pub struct BackupController {}
+
+impl BackupController {
+    /// A consistent snapshot of the database, restorable with `scytale restore`
+    /// into the same kind of backend.
+    pub async fn backup(
+        admin: UserEntity,
+        State(state): State<Arc<Mutex<AppState>>>,
+        meta: RequestMeta,
+    ) -> Result<Response, AppError> {
+        let db = state.lock().await.db.clone();
+        let result = backup_database_to_file(&db).await;
+        audit::record(
+            &db,
+            AuditEvent::new(AuditAction::DatabaseBackup, &meta)
+                .actor(&admin)
+                .outcome(&result),
+        )
+        .await;
+        let (file, dir) = result?;
+        let length = file
+            .metadata()
+            .await
+            .map_err(|err| AppError::Backup(format!("unable to read the backup: {}", err)))?
+            .len();
+        // the temporary directory goes once the download is over
+        let body = ReaderStream::new(file).map(move |chunk| {
+            let _ = &dir;
+            chunk
+        });
+
+        let disposition = format!(
+            "attachment; filename=\"scytale-backup-{}\"",
+            Utc::now().format("%Y%m%d%H%M%S")
+        );
+
+        Ok((
+            [
+                (header::CONTENT_TYPE, "application/octet-stream".to_string()),
+                (header::CONTENT_DISPOSITION, disposition),
+                (header::CONTENT_LENGTH, length.to_string()),
+            ],
+            StreamBody::new(body),
+        )
+            .into_response())
+    }
+}
@@ -0,0 +1,148 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{Duration, Utc};
+use tokio::sync::Mutex;
+
+use crate::{
+    error::AppError,
+    middleware::RequestMeta,
+    models::{
+        audit::{AuditAction, AuditEvent},
+        auth::LoginResponse,
+        invite::{InviteCreate, InviteCreated, InviteEntity, InviteRegister},
+        user::{UserCreate, UserEntity},
+    },
+    service::{
+        audit,
+        invite::{
+            create_invite, get_invite_role, list_outstanding_invites, release_invite,
+            revoke_invite, use_invite,
+        },
+        role::{check_assignable, role_exists},
+        user::{create_user, validate_new_user},
+    },
+    utils::{generate_token, hash_token, login_response},
+    AppState,
+};
+
+const INVITE_HOURS: i64 = 7 * 24;
+const MAX_INVITE_HOURS: i64 = 90 * 24;
+
+pub struct InviteController {}
+
+impl InviteController {
+    pub async fn create(
+        admin: UserEntity,
+        State(state): State<Arc<Mutex<AppState>>>,
+        meta: RequestMeta,
+        Json(payload): Json<InviteCreate>,
+    ) -> Result<(StatusCode, Json<InviteCreated>), AppError> {
+        if payload.max_uses < 1 {
+            return Err(AppError::EmptyPayload);
+        }
+        let expires_in = payload
+            .expires_in_hours
+            .unwrap_or(INVITE_HOURS)
+            .clamp(1, MAX_INVITE_HOURS);
+
+        let state = state.lock().await;
+        if !role_exists(&state.db, &payload.role).await? {
+            return Err(AppError::RoleDoesNotExist);
+        }
+        check_assignable(&state.db, &admin.role, &payload.role).await?;
+
+        let code = generate_token(16);
+        let invite = create_invite(
+            &state.db,
+            &hash_token(&code),
+            &payload.role,
+            payload.max_uses,
+            admin.id,
+            Utc::now() + Duration::hours(expires_in),
+        )
+        .await?;
+        audit::record(
+            &state.db,
+            AuditEvent::new(AuditAction::InviteCreate, &meta)
+                .actor(&admin)
+                .target(format!("invite:{}", invite.id))
+                .detail(format!("role {}", invite.role)),
+        )
+        .await;
+
+        Ok((StatusCode::CREATED, Json(InviteCreated { code, invite })))
+    }
+
+    pub async fn list(
+        State(state): State<Arc<Mutex<AppState>>>,
+    ) -> Result<Json<Vec<InviteEntity>>, AppError> {
+        let state = state.lock().await;
+        let invites = list_outstanding_invites(&state.db).await?;
+        Ok(Json(invites))
+    }
+
+    pub async fn revoke(
+        admin: UserEntity,
+        State(state): State<Arc<Mutex<AppState>>>,
+        meta: RequestMeta,
+        Path(id): Path<i64>,
+    ) -> Result<StatusCode, AppError> {
+        let state = state.lock().await;
+        revoke_invite(&state.db, id).await?;
+        audit::record(
+            &state.db,
+            AuditEvent::new(AuditAction::InviteRevoke, &meta)
+                .actor(&admin)
+                .target(format!("invite:{}", id)),
+        )
+        .await;
+        Ok(StatusCode::NO_CONTENT)
+    }
+
+    /// Public self-registration, the new user gets the role preset on the invite.
+    pub async fn register(
+        State(state): State<Arc<Mutex<AppState>>>,
+        meta: RequestMeta,
+        Json(payload): Json<InviteRegister>,
+    ) -> Result<(StatusCode, Json<LoginResponse>), AppError> {
+        let state = state.lock().await;
+        let code_hash = hash_token(payload.code.trim());
+
+        let mut user = UserCreate {
+            email: payload.email,
+            name: payload.name,
+            password: payload.password,
+            role: get_invite_role(&state.db, &code_hash).await?,
+        };
+        validate_new_user(&state.db, &state.password_policy, &user).await?;
+
+        // counted first so that concurrent registrations cannot exceed
+        // max_uses, and given back if the user cannot be created after all
+        user.role = use_invite(&state.db, &code_hash).await?;
+        let user = match create_user(&state.db, &mut user, &state.hash_params).await {
+            Ok(user) => user,
+            Err(err) => {
+                release_invite(&state.db, &code_hash).await?;
+                return Err(err);
+            }
+        };
+        audit::record(
+            &state.db,
+            AuditEvent::new(AuditAction::UserRegister, &meta)
+                .actor(&user)
+                .target(format!("user:{}", user.id))
+                .detail(format!("role {}", user.role)),
+        )
+        .await;
+
+        let response =
+            login_response(&state.db, &user, &state.keys, "User created successfully").await?;
+
+        Ok((StatusCode::CREATED, Json(response)))
+    }
+}
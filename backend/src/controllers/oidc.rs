@@ -0,0 +1,195 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Redirect, Response},
+    Json,
+};
+use tokio::sync::Mutex;
+
+use crate::{
+    error::AppError,
+    middleware::RequestMeta,
+    models::{
+        audit::{AuditAction, AuditEvent},
+        auth::MfaPendingResponse,
+        jwt::TokenType,
+        oidc::{IdTokenClaims, OidcCallback},
+        state::AppState,
+        user::{UserCreate, UserEntity, UserUpdate},
+    },
+    service::{
+        audit,
+        identity::{get_identity_user, link_identity, touch_identity},
+        oidc::OidcClient,
+        role::role_exists,
+        totp::is_totp_enabled,
+        user::{create_user_without_password, get_user_by_email, get_user_by_id, update_user},
+    },
+    utils::{encode_token, login_response},
+};
+
+pub struct OidcController {}
+
+impl OidcController {
+    /// Sends the browser to the identity provider.
+    pub async fn login(State(state): State<Arc<Mutex<AppState>>>) -> Result<Redirect, AppError> {
+        let client = Self::client(&state).await?;
+        let url = client.authorization_url().await?;
+        Ok(Redirect::to(&url))
+    }
+
+    /// Redirect target of the provider, answers like a password login: users
+    /// with TOTP enabled get an mfa token for `/api/login/mfa` instead.
+    pub async fn callback(
+        State(state): State<Arc<Mutex<AppState>>>,
+        meta: RequestMeta,
+        Query(callback): Query<OidcCallback>,
+    ) -> Result<Response, AppError> {
+        let client = Self::client(&state).await?;
+        let claims = match Self::exchange(&client, callback).await {
+            Ok(claims) => claims,
+            Err(err) => {
+                let event =
+                    AuditEvent::new(AuditAction::OidcLogin, &meta).failure(format!("{:?}", err));
+                audit::record(&state.lock().await.db, event).await;
+                return Err(err);
+            }
+        };
+
+        let state = state.lock().await;
+        let result = Self::resolve_user(&state, &client, &claims).await;
+        let mut event = AuditEvent::new(AuditAction::OidcLogin, &meta)
+            .target(format!("{}#{}", client.provider(), claims.sub))
+            .outcome(&result);
+        match &result {
+            Ok(user) => event = event.actor(user),
+            Err(_) => event = event.actor_email(claims.email.as_deref().unwrap_or_default()),
+        }
+        let mut user = match result {
+            Ok(user) => user,
+            Err(err) => {
+                audit::record(&state.db, event).await;
+                return Err(err);
+            }
+        };
+
+        if client.config.sync_roles {
+            if let Some(role) = client.config.role_for_groups(&claims) {
+                if role != user.role {
+                    let update = UserUpdate {
+                        email: None,
+                        name: None,
+                        role: Some(role.clone()),
+                    };
+                    match update_user(&state.db, user.id, &update).await {
+                        Ok(_) => user.role = role,
+                        // the login goes on with the current role
+                        Err(err @ (AppError::LastRoleManagerUser | AppError::RoleDoesNotExist)) => {
+                            tracing::warn!(
+                                "Not syncing role {:?} of user {}: {:?}",
+                                role,
+                                user.id,
+                                err
+                            )
+                        }
+                        Err(err) => return Err(err),
+                    }
+                }
+            }
+        }
+
+        // the provider does not stand in for the second factor
+        if is_totp_enabled(&state.db, user.id).await? {
+            let mfa_token = encode_token(&user, &state.keys, TokenType::MfaPending, None).await?;
+            let response = MfaPendingResponse {
+                message: "Two-factor code required".to_string(),
+                mfa_token,
+            };
+            audit::record(&state.db, event.detail("second factor required")).await;
+            return Ok((StatusCode::ACCEPTED, Json(response)).into_response());
+        }
+
+        audit::record(&state.db, event).await;
+        let response = login_response(&state.db, &user, &state.keys, "Login successful").await?;
+        Ok(Json(response).into_response())
+    }
+
+    async fn exchange(
+        client: &OidcClient,
+        callback: OidcCallback,
+    ) -> Result<IdTokenClaims, AppError> {
+        if let Some(error) = callback.error {
+            tracing::debug!(
+                "Identity provider refused login: {} {:?}",
+                error,
+                callback.error_description
+            );
+            return Err(AppError::OidcLoginFailed);
+        }
+        let code = callback.code.ok_or(AppError::OidcLoginFailed)?;
+        client.exchange(&code, &callback.state).await
+    }
+
+    async fn client(state: &Arc<Mutex<AppState>>) -> Result<Arc<OidcClient>, AppError> {
+        state
+            .lock()
+            .await
+            .oidc
+            .clone()
+            .ok_or(AppError::OidcNotConfigured)
+    }
+
+    /// Finds the local user of the identity: an existing link first, then a
+    /// user with the same verified email, then a newly provisioned one.
+    async fn resolve_user(
+        state: &AppState,
+        client: &OidcClient,
+        claims: &IdTokenClaims,
+    ) -> Result<UserEntity, AppError> {
+        let db = &state.db;
+        let config = &client.config;
+        let email = claims.email.as_deref();
+
+        if let Some(user_id) = get_identity_user(db, client.provider(), &claims.sub).await? {
+            touch_identity(db, client.provider(), &claims.sub, email).await?;
+            return get_user_by_id(db, user_id).await;
+        }
+
+        let existing = match email {
+            Some(email) => get_user_by_email(db, email).await?,
+            None => None,
+        };
+
+        let user = match existing {
+            Some(user) if config.link_by_email && claims.email_verified => user,
+            // never take over an account the provider has not proven ownership of
+            Some(_) => return Err(AppError::UserAlreadyExits),
+            None if config.provision => {
+                let email = email.ok_or_else(|| {
+                    AppError::OidcProvider("id token has no email claim".to_string())
+                })?;
+                let role = config
+                    .role_for_groups(claims)
+                    .unwrap_or_else(|| config.default_role.clone());
+                if !role_exists(db, &role).await? {
+                    return Err(AppError::RoleDoesNotExist);
+                }
+
+                // login only works through the provider until a password is set
+                let user = UserCreate {
+                    email: email.to_string(),
+                    name: claims.name.clone().unwrap_or_else(|| email.to_string()),
+                    password: String::new(),
+                    role,
+                };
+                create_user_without_password(db, &user).await?
+            }
+            None => return Err(AppError::OidcUserNotProvisioned),
+        };
+
+        link_identity(db, client.provider(), &claims.sub, user.id, email).await?;
+        Ok(user)
+    }
+}
@@ -0,0 +1,137 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+use tokio::sync::Mutex;
+
+use crate::{
+    error::AppError,
+    middleware::RequestMeta,
+    models::{
+        audit::{AuditAction, AuditEvent},
+        device::DeviceRegistration,
+        pairing::{PairingApprove, PairingClaim, PairingDevice, PairingStarted},
+        state::AppState,
+        user::UserEntity,
+    },
+    service::{
+        audit,
+        device::{check_new_device, register_new_device},
+        user::get_user_by_id,
+    },
+    utils::device_login_response,
+};
+
+pub struct PairingController {}
+
+impl PairingController {
+    /// Called by the new device, which shows the returned code until a logged
+    /// in device approves it.
+    pub async fn start(
+        State(state): State<Arc<Mutex<AppState>>>,
+        meta: RequestMeta,
+        Json(device): Json<DeviceRegistration>,
+    ) -> Result<(StatusCode, Json<PairingStarted>), AppError> {
+        if device.id.trim().is_empty() || device.name.trim().is_empty() {
+            return Err(AppError::EmptyPayload);
+        }
+
+        let started = state.lock().await.pairings.start(device, meta.ip)?;
+        Ok((StatusCode::CREATED, Json(started)))
+    }
+
+    /// Called by a logged in device of the user who lets the device showing
+    /// `code` in on their account. Answers `202 Accepted` with the device to
+    /// confirm while no `client_id` is given.
+    pub async fn approve(
+        user: UserEntity,
+        State(state): State<Arc<Mutex<AppState>>>,
+        meta: RequestMeta,
+        Json(payload): Json<PairingApprove>,
+    ) -> Result<(StatusCode, Json<PairingDevice>), AppError> {
+        let mut state = state.lock().await;
+        let device = Self::review(&mut state, user.id, &payload, &meta).await?;
+        let status = if device.confirmed {
+            StatusCode::OK
+        } else {
+            StatusCode::ACCEPTED
+        };
+        Ok((status, Json(device)))
+    }
+
+    /// Shows the device behind the code, or lets it in once the user sent back
+    /// its client id. Shared with the `pair_approve` WebSocket message.
+    pub(crate) async fn review(
+        state: &mut AppState,
+        uid: i64,
+        payload: &PairingApprove,
+        meta: &RequestMeta,
+    ) -> Result<PairingDevice, AppError> {
+        let result = Self::approve_pairing(state, payload, uid, meta).await;
+
+        let mut event = AuditEvent::new(AuditAction::DevicePair, meta).outcome(&result);
+        event.actor_id = Some(uid);
+        if let Ok(device) = &result {
+            event = event.target(format!("client:{}", device.client_id));
+            if !device.confirmed {
+                event = event.detail("awaiting confirmation");
+            }
+        }
+        audit::record(&state.db, event).await;
+
+        result
+    }
+
+    /// Polled by the new device. Answers `202 Accepted` while the code is not
+    /// approved yet and the tokens of the approving user once it is, after
+    /// which the pairing is gone.
+    pub async fn token(
+        State(state): State<Arc<Mutex<AppState>>>,
+        meta: RequestMeta,
+        Json(payload): Json<PairingClaim>,
+    ) -> Result<Response, AppError> {
+        let mut state = state.lock().await;
+        let (user_id, device) = match state.pairings.claim(&payload.pairing_id)? {
+            Some(approved) => approved,
+            None => {
+                return Ok(
+                    (StatusCode::ACCEPTED, Json(json!({ "status": "pending" }))).into_response()
+                )
+            }
+        };
+
+        let user = get_user_by_id(&state.db, user_id).await?;
+        register_new_device(&state.db, user.id, &device, meta.ip).await?;
+        let response = device_login_response(
+            &state.db,
+            &user,
+            &state.keys,
+            &device.id,
+            "Pairing successful",
+        )
+        .await?;
+
+        Ok(Json(response).into_response())
+    }
+
+    async fn approve_pairing(
+        state: &mut AppState,
+        payload: &PairingApprove,
+        uid: i64,
+        meta: &RequestMeta,
+    ) -> Result<PairingDevice, AppError> {
+        let device = match &payload.client_id {
+            Some(client_id) => state
+                .pairings
+                .approve(&payload.code, client_id, uid, meta.ip)?,
+            None => state.pairings.offer(&payload.code, uid, meta.ip)?,
+        };
+        check_new_device(&state.db, uid, &device.id).await?;
+        Ok(PairingDevice::new(device, payload.client_id.is_some()))
+    }
+}
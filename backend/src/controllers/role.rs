@@ -0,0 +1,85 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use tokio::sync::Mutex;
+
+use crate::{
+    error::AppError,
+    middleware::RequestMeta,
+    models::{
+        audit::{AuditAction, AuditEvent},
+        role::{RoleEntity, RoleUpsert},
+        user::{Role, UserEntity},
+    },
+    service::{
+        audit,
+        role::{delete_role, get_role, list_roles, upsert_role},
+    },
+    AppState,
+};
+
+pub struct RoleController {}
+
+impl RoleController {
+    pub async fn list(
+        State(state): State<Arc<Mutex<AppState>>>,
+    ) -> Result<Json<Vec<RoleEntity>>, AppError> {
+        let state = state.lock().await;
+        let roles = list_roles(&state.db).await?;
+        Ok(Json(roles))
+    }
+
+    pub async fn get(
+        State(state): State<Arc<Mutex<AppState>>>,
+        Path(name): Path<String>,
+    ) -> Result<Json<RoleEntity>, AppError> {
+        let state = state.lock().await;
+        let role = get_role(&state.db, &Role::new(name)).await?;
+        Ok(Json(role))
+    }
+
+    pub async fn upsert(
+        admin: UserEntity,
+        State(state): State<Arc<Mutex<AppState>>>,
+        meta: RequestMeta,
+        Path(name): Path<String>,
+        Json(payload): Json<RoleUpsert>,
+    ) -> Result<Json<RoleEntity>, AppError> {
+        if name.trim().is_empty() {
+            return Err(AppError::EmptyPayload);
+        }
+        let state = state.lock().await;
+        let role = upsert_role(&state.db, &Role::new(name), &payload).await?;
+        audit::record(
+            &state.db,
+            AuditEvent::new(AuditAction::RoleUpdate, &meta)
+                .actor(&admin)
+                .target(format!("role:{}", role.name))
+                .detail(format!("{:?}", role.permissions)),
+        )
+        .await;
+        Ok(Json(role))
+    }
+
+    pub async fn delete(
+        admin: UserEntity,
+        State(state): State<Arc<Mutex<AppState>>>,
+        meta: RequestMeta,
+        Path(name): Path<String>,
+    ) -> Result<StatusCode, AppError> {
+        let state = state.lock().await;
+        delete_role(&state.db, &Role::new(name.clone())).await?;
+        audit::record(
+            &state.db,
+            AuditEvent::new(AuditAction::RoleDelete, &meta)
+                .actor(&admin)
+                .target(format!("role:{}", name)),
+        )
+        .await;
+        Ok(StatusCode::NO_CONTENT)
+    }
+}
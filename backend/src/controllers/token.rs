@@ -8,18 +8,25 @@ use axum::{
 };
 use jsonwebtoken::Header;
 use serde_json::{json, Value};
-use sqlx::SqlitePool;
 use tokio::sync::Mutex;
 
 use crate::{
     error::AppError,
+    middleware::RequestMeta,
     models::{
         self,
+        audit::{AuditAction, AuditEvent},
         jwt::{Claims, TokeRefresh, TokenType},
         user::{Role, UserCreate, UserEntity, UserLogin},
     },
-    service::user::{create_user, get_user_by_email, get_user_by_id_email},
-    utils::{decode_token, encode_token},
+    service::{
+        audit,
+        session::{extend_session, is_session_active},
+        user::{
+            create_user, get_user_by_email, get_user_by_id, get_user_by_id_email, is_user_disabled,
+        },
+    },
+    utils::{decode_token, encode_device_token},
     AppState,
 };
 pub struct TokenController {}
@@ -31,26 +38,65 @@ impl TokenController {
 
     pub async fn refresh(
         State(state): State<Arc<Mutex<AppState>>>,
+        meta: RequestMeta,
         Json(payload): Json<TokeRefresh>,
     ) -> Result<Json<Value>, AppError> {
         let state = state.lock().await;
-        let claims = decode_token(payload.refresh_token.as_str(), &state.keys).await?;
+        let result = Self::refresh_tokens(&state, &payload.refresh_token).await;
+
+        let mut event = AuditEvent::new(AuditAction::TokenRefresh, &meta).outcome(&result);
+        if let Ok((user, _)) = &result {
+            event = event.actor(user);
+        }
+        audit::record(&state.db, event).await;
+
+        result.map(|(_, tokens)| Json(tokens))
+    }
+
+    async fn refresh_tokens(
+        state: &AppState,
+        refresh_token: &str,
+    ) -> Result<(UserEntity, Value), AppError> {
+        let claims = decode_token(refresh_token, &state.keys).await?;
         match claims.token_type {
-            TokenType::AccessToken => Err(AppError::NotRefreshToken),
+            TokenType::AccessToken | TokenType::MfaPending => Err(AppError::NotRefreshToken),
             TokenType::RefreshToken => {
-                let user =
-                    get_user_by_id_email(&state.pool, claims.id, claims.email.as_str()).await?;
+                // by id only, the email in the claims is stale after a profile update
+                let user = get_user_by_id(&state.db, claims.id).await?;
+
+                let session_id = claims.sid.clone().ok_or(AppError::InvalidToken)?;
+                if !is_session_active(&state.db, &session_id, user.id).await? {
+                    return Err(AppError::InvalidToken);
+                }
+                if is_user_disabled(&state.db, user.id).await? {
+                    return Err(AppError::UserDisabled);
+                }
+                extend_session(&state.db, &session_id, state.keys.token_lifetime).await?;
 
-                let access_token = encode_token(&user, &state.keys, TokenType::AccessToken).await?;
-                let refresh_token =
-                    encode_token(&user, &state.keys, TokenType::RefreshToken).await?;
+                let access_token = encode_device_token(
+                    &user,
+                    &state.keys,
+                    TokenType::AccessToken,
+                    Some(&session_id),
+                    claims.cid.as_deref(),
+                )
+                .await?;
+                let refresh_token = encode_device_token(
+                    &user,
+                    &state.keys,
+                    TokenType::RefreshToken,
+                    Some(&session_id),
+                    claims.cid.as_deref(),
+                )
+                .await?;
 
-                Ok(Json(json!({
+                let tokens = json!({
                     "id": user.id,
                     "role": user.role,
                     "access_token": access_token,
                     "refresh_token": refresh_token
-                })))
+                });
+                Ok((user, tokens))
             }
         }
     }
@@ -0,0 +1,113 @@
+use std::sync::Arc;
+
+use axum::{extract::State, http::StatusCode, Json};
+use chrono::Utc;
+use tokio::sync::Mutex;
+
+use crate::{
+    error::AppError,
+    middleware::RequestMeta,
+    models::{
+        audit::{AuditAction, AuditEvent},
+        totp::{
+            generate_recovery_codes, hash_recovery_code, RecoveryCodesResponse, TotpCode,
+            TotpEnrollResponse, TotpEntity,
+        },
+        user::UserEntity,
+    },
+    service::{
+        audit,
+        totp::{delete_totp, enable_totp, get_totp, set_pending_totp, verify_second_factor},
+    },
+    AppState,
+};
+
+pub struct TotpController {}
+
+impl TotpController {
+    /// Starts enrollment with a new pending secret, TOTP is only enforced once
+    /// the secret has been confirmed with [`Self::confirm`].
+    pub async fn enroll(
+        user: UserEntity,
+        State(state): State<Arc<Mutex<AppState>>>,
+    ) -> Result<Json<TotpEnrollResponse>, AppError> {
+        let state = state.lock().await;
+
+        if let Some(totp) = get_totp(&state.db, user.id).await? {
+            if totp.enabled {
+                return Err(AppError::MfaAlreadyEnabled);
+            }
+        }
+
+        let totp = TotpEntity {
+            user_id: user.id,
+            secret: TotpEntity::generate_secret(),
+            enabled: false,
+            last_used_step: 0,
+        };
+        set_pending_totp(&state.db, user.id, &totp.secret).await?;
+
+        Ok(Json(TotpEnrollResponse {
+            otpauth_uri: totp.totp(&user.email)?.get_url(),
+            secret: totp.secret,
+        }))
+    }
+
+    pub async fn confirm(
+        user: UserEntity,
+        State(state): State<Arc<Mutex<AppState>>>,
+        meta: RequestMeta,
+        Json(payload): Json<TotpCode>,
+    ) -> Result<Json<RecoveryCodesResponse>, AppError> {
+        let state = state.lock().await;
+
+        let totp = get_totp(&state.db, user.id)
+            .await?
+            .ok_or(AppError::MfaNotEnabled)?;
+        if totp.enabled {
+            return Err(AppError::MfaAlreadyEnabled);
+        }
+
+        let step = totp
+            .verify(&payload.code, Utc::now().timestamp() as u64)?
+            .ok_or(AppError::InvalidMfaCode)?;
+
+        let recovery_codes = generate_recovery_codes();
+        let hashes = recovery_codes
+            .iter()
+            .map(|code| hash_recovery_code(code))
+            .collect::<Vec<_>>();
+        enable_totp(&state.db, user.id, step, &hashes).await?;
+        audit::record(
+            &state.db,
+            AuditEvent::new(AuditAction::TotpEnable, &meta).actor(&user),
+        )
+        .await;
+
+        Ok(Json(RecoveryCodesResponse { recovery_codes }))
+    }
+
+    /// Wrong codes count towards the account's login throttle, like those of
+    /// the second login step.
+    pub async fn disable(
+        user: UserEntity,
+        State(state): State<Arc<Mutex<AppState>>>,
+        meta: RequestMeta,
+        Json(payload): Json<TotpCode>,
+    ) -> Result<StatusCode, AppError> {
+        let mut state = state.lock().await;
+        state.login_throttle.check(&user.email, meta.ip)?;
+        let event = AuditEvent::new(AuditAction::TotpDisable, &meta).actor(&user);
+
+        if !verify_second_factor(&state.db, user.id, &payload.code).await? {
+            state.login_throttle.record_failure(&user.email, meta.ip);
+            audit::record(&state.db, event.failure("invalid code")).await;
+            return Err(AppError::InvalidMfaCode);
+        }
+        state.login_throttle.record_success(&user.email);
+        delete_totp(&state.db, user.id).await?;
+        audit::record(&state.db, event).await;
+
+        Ok(StatusCode::NO_CONTENT)
+    }
+}
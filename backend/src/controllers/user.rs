@@ -1,32 +1,481 @@
-use std::sync::Arc;
+use std::{net::IpAddr, sync::Arc};
 
-use axum::{extract::State, Json};
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{Duration, SubsecRound, Utc};
 use serde_json::{json, Value};
 use tokio::sync::Mutex;
 
 use crate::{
     error::AppError,
-    models::user::{Client, UserEntity},
+    middleware::{AuthenticatedClient, RequestMeta},
+    models::{
+        audit::{AuditAction, AuditEvent},
+        auth::LoginResponse,
+        device::{
+            Device, DeviceAccess, DeviceEntity, DeviceRegistration, DeviceRename, GuestCreate,
+            GuestCreated, MAX_GUEST_MINUTES,
+        },
+        export::{ProfileUpdate, Reauthentication},
+        jwt::{Claims, TokenType},
+        password::PasswordChange,
+        retention::UserRetention,
+        user::{UserDetails, UserEntity, UserUpdate},
+    },
+    repository::Database,
+    service::{
+        audit,
+        device::{
+            create_guest_device, list_devices, register_new_device, rename_device, revoke_device,
+        },
+        export::export_user_data,
+        retention::{get_user_retention, set_user_retention},
+        session::{
+            create_session_until, list_user_sessions, revoke_device_sessions,
+            revoke_other_sessions, revoke_user_sessions,
+        },
+        totp::{is_totp_enabled, verify_second_factor},
+        user::{delete_user, get_user_auth_provider, update_user, update_user_password},
+    },
+    utils::{device_login_response, encode_claims, generate_token, zip_archive},
     AppState,
 };
 
+/// How recent the login of a user without a local password has to be to stand
+/// in for the password.
+const FRESH_LOGIN_MINUTES: i64 = 5;
+
 pub struct UserController {}
 
 impl UserController {
+    /// Every device the user has connected with, online or not.
     pub async fn get_clients(
         State(state): State<Arc<Mutex<AppState>>>,
         user: UserEntity,
-    ) -> Result<Json<Vec<Client>>, AppError> {
+    ) -> Result<Json<Vec<Device>>, AppError> {
+        let state = state.lock().await;
+        let online = state.users.get(&user.id);
+        let devices = list_devices(&state.db, user.id)
+            .await?
+            .into_iter()
+            .map(|device| Device {
+                online: online.is_some_and(|clients| clients.contains_key(&device.client_id)),
+                device,
+            })
+            .collect();
+        Ok(Json(devices))
+    }
+
+    /// Registers the device the caller runs on and hands out tokens bound to
+    /// it. Needs a token of a plain login and a client id that is not taken
+    /// yet, a device cannot mint tokens for another one.
+    pub async fn register_client(
+        State(state): State<Arc<Mutex<AppState>>>,
+        client: AuthenticatedClient,
+        meta: RequestMeta,
+        Json(device): Json<DeviceRegistration>,
+    ) -> Result<Json<LoginResponse>, AppError> {
+        if client.client_id.is_some() {
+            return Err(AppError::DeviceMismatch);
+        }
+        if device.id.trim().is_empty() || device.name.trim().is_empty() {
+            return Err(AppError::EmptyPayload);
+        }
+
+        let user = client.user;
+        let state = state.lock().await;
+        let event = AuditEvent::new(AuditAction::DeviceRegister, &meta)
+            .actor(&user)
+            .target(format!("client:{}", device.id));
+        let result = register_new_device(&state.db, user.id, &device, meta.ip).await;
+        audit::record(&state.db, event.outcome(&result)).await;
+        result?;
+
+        let response = device_login_response(
+            &state.db,
+            &user,
+            &state.keys,
+            &device.id,
+            "Device registered",
+        )
+        .await?;
+        Ok(Json(response))
+    }
+
+    /// Time-limited device that can only receive or only send clips, its token
+    /// is good for `/api/ws` alone.
+    pub async fn create_guest(
+        State(state): State<Arc<Mutex<AppState>>>,
+        user: UserEntity,
+        meta: RequestMeta,
+        Json(payload): Json<GuestCreate>,
+    ) -> Result<(StatusCode, Json<GuestCreated>), AppError> {
+        let name = payload.name.trim();
+        if name.is_empty() {
+            return Err(AppError::EmptyPayload);
+        }
+        if payload.access == DeviceAccess::Full {
+            return Err(AppError::InvalidGuest(
+                "guests can only receive or send".to_string(),
+            ));
+        }
+        let minutes = payload.minutes.unwrap_or(60);
+        if !(1..=MAX_GUEST_MINUTES).contains(&minutes) {
+            return Err(AppError::InvalidGuest(format!(
+                "guest access lasts between 1 and {} minutes",
+                MAX_GUEST_MINUTES
+            )));
+        }
+
+        let state = state.lock().await;
+        let client_id = format!("guest-{}", generate_token(12));
+        // whole seconds like the token, Postgres would round the nanoseconds away
+        let expires_at = (Utc::now() + Duration::minutes(minutes)).trunc_subsecs(0);
+        let device = create_guest_device(
+            &state.db,
+            user.id,
+            &client_id,
+            name,
+            payload.access,
+            expires_at,
+        )
+        .await?;
+
+        let session_id =
+            create_session_until(&state.db, user.id, Some(&client_id), expires_at).await?;
+        let claims = Claims::from(
+            &user,
+            TokenType::AccessToken,
+            Some(&session_id),
+            state.keys.token_lifetime,
+        )
+        .bound_to(Some(&client_id))
+        .guest(expires_at);
+        let token = encode_claims(&claims, &state.keys)?;
+
+        let event = AuditEvent::new(AuditAction::GuestCreate, &meta)
+            .actor(&user)
+            .target(format!("client:{}", client_id))
+            .detail(format!("{:?} for {} minutes", payload.access, minutes));
+        audit::record(&state.db, event).await;
+
+        Ok((
+            StatusCode::CREATED,
+            Json(GuestCreated {
+                client_id,
+                name: device.name,
+                access: device.access,
+                token,
+                expires_at,
+            }),
+        ))
+    }
+
+    pub async fn rename_client(
+        State(state): State<Arc<Mutex<AppState>>>,
+        user: UserEntity,
+        meta: RequestMeta,
+        Path(client_id): Path<String>,
+        Json(payload): Json<DeviceRename>,
+    ) -> Result<Json<DeviceEntity>, AppError> {
+        let name = payload.name.trim();
+        if name.is_empty() {
+            return Err(AppError::EmptyPayload);
+        }
+
+        let mut state = state.lock().await;
+        let device = rename_device(&state.db, user.id, &client_id, name).await?;
+        if let Some((client, _)) = state.get_client(&user.id, &client_id).await {
+            client.name = device.name.clone();
+        }
+        let event = AuditEvent::new(AuditAction::DeviceRename, &meta)
+            .actor(&user)
+            .target(format!("client:{}", client_id))
+            .detail(format!("name {:?}", device.name));
+        audit::record(&state.db, event).await;
+
+        Ok(Json(device))
+    }
+
+    /// Blocks the client id from reconnecting and closes its connection.
+    pub async fn revoke_client(
+        State(state): State<Arc<Mutex<AppState>>>,
+        user: UserEntity,
+        meta: RequestMeta,
+        Path(client_id): Path<String>,
+    ) -> Result<Json<DeviceEntity>, AppError> {
+        let mut state = state.lock().await;
+        let device = revoke_device(&state.db, user.id, &client_id).await?;
+        revoke_device_sessions(&state.db, user.id, &client_id).await?;
+        state
+            .disconnect_client(&user.id, &client_id, "device revoked")
+            .await;
+        let event = AuditEvent::new(AuditAction::DeviceRevoke, &meta)
+            .actor(&user)
+            .target(format!("client:{}", client_id));
+        audit::record(&state.db, event).await;
+
+        Ok(Json(device))
+    }
+
+    /// Self-service password change, the current password is required and
+    /// wrong guesses count towards the account's login throttle. Users without
+    /// a local password set their first one after a fresh login instead. Every
+    /// other session of the user is ended once the password is changed.
+    pub async fn change_password(
+        client: AuthenticatedClient,
+        State(state): State<Arc<Mutex<AppState>>>,
+        meta: RequestMeta,
+        Json(payload): Json<PasswordChange>,
+    ) -> Result<StatusCode, AppError> {
+        let user = client.user;
+        let event = AuditEvent::new(AuditAction::PasswordChange, &meta).actor(&user);
+        let (db, hash_params, password_policy) = {
+            let mut state = state.lock().await;
+            if get_user_auth_provider(&state.db, &user.email)
+                .await?
+                .is_some_and(|provider| provider != "password")
+            {
+                audit::record(&state.db, event.failure("password managed externally")).await;
+                return Err(AppError::PasswordManagedExternally);
+            }
+
+            if user.has_password() {
+                // counted as failed until the current password is checked
+                state.login_throttle.reserve(&user.email, meta.ip)?;
+            }
+            (
+                state.db.clone(),
+                state.hash_params.clone(),
+                state.password_policy.clone(),
+            )
+        };
+
+        if user.has_password() {
+            let mut verified = user
+                .verify_password(payload.current_password.as_bytes())
+                .unwrap_or(false);
+            if verified && is_totp_enabled(&db, user.id).await? {
+                verified = match &payload.code {
+                    Some(code) => verify_second_factor(&db, user.id, code).await?,
+                    None => false,
+                };
+            }
+
+            // the failure reserved above stays counted, wrong codes included
+            if !verified {
+                audit::record(&db, event.failure("wrong current password")).await;
+                return Err(AppError::WrongCredential);
+            }
+            {
+                let mut state = state.lock().await;
+                state.login_throttle.release(&user.email, meta.ip);
+                state.login_throttle.record_success(&user.email);
+            }
+        } else if !Self::is_fresh_login(&db, user.id, client.session_id.as_deref()).await? {
+            audit::record(&db, event.failure("no fresh login")).await;
+            return Err(AppError::FreshLoginRequired);
+        }
+
+        password_policy.validate(&payload.new_password, &user.email)?;
+        let password_hash = hash_params.hash(payload.new_password.as_bytes())?;
+
+        update_user_password(&db, user.id, &password_hash).await?;
+        match &client.session_id {
+            Some(session_id) => revoke_other_sessions(&db, user.id, session_id).await?,
+            None => revoke_user_sessions(&db, user.id).await?,
+        };
+        state
+            .lock()
+            .await
+            .disconnect_other_clients(&user.id, client.client_id.as_deref(), "password changed")
+            .await;
+        audit::record(&db, event).await;
+
+        Ok(StatusCode::NO_CONTENT)
+    }
+
+    /// Name and email change, the user has to confirm with their password.
+    pub async fn update_profile(
+        client: AuthenticatedClient,
+        State(state): State<Arc<Mutex<AppState>>>,
+        meta: RequestMeta,
+        Json(payload): Json<ProfileUpdate>,
+    ) -> Result<Json<UserDetails>, AppError> {
+        let user = &client.user;
+        let event = AuditEvent::new(AuditAction::ProfileUpdate, &meta).actor(user);
+        Self::reauthenticate(&state, &client, &payload.reauth, meta.ip, event.clone()).await?;
+
+        let update = UserUpdate {
+            email: payload.email,
+            name: payload.name,
+            role: None,
+        };
+        let state = state.lock().await;
+        let details = update_user(&state.db, user.id, &update).await?;
+        let event = event.detail(format!("email {:?} name {:?}", update.email, update.name));
+        audit::record(&state.db, event).await;
+
+        Ok(Json(details))
+    }
+
+    pub async fn get_retention(
+        user: UserEntity,
+        State(state): State<Arc<Mutex<AppState>>>,
+    ) -> Result<Json<UserRetention>, AppError> {
+        let state = state.lock().await;
+        let retention = get_user_retention(&state.db, user.id).await?;
+        Ok(Json(retention))
+    }
+
+    /// How long the user's expired sessions, reset links and guest devices
+    /// are kept, instead of the server wide retention.
+    pub async fn set_retention(
+        user: UserEntity,
+        State(state): State<Arc<Mutex<AppState>>>,
+        meta: RequestMeta,
+        Json(payload): Json<UserRetention>,
+    ) -> Result<Json<UserRetention>, AppError> {
         let state = state.lock().await;
-        let clients = state.users.get(&user.id);
-        if let Some(clients) = clients {
-            let c = clients
-                .clone()
-                .iter()
-                .map(|(_, (client, _))| client.clone())
-                .collect::<Vec<_>>();
-            return Ok(Json(c));
-        }
-        Ok(Json([].to_vec()))
+        set_user_retention(&state.db, user.id, &payload).await?;
+        audit::record(
+            &state.db,
+            AuditEvent::new(AuditAction::RetentionChange, &meta)
+                .actor(&user)
+                .target(format!("user:{}", user.id))
+                .detail(format!("{:?} days", payload.days)),
+        )
+        .await;
+
+        Ok(Json(payload))
+    }
+
+    /// Everything stored about the user as a zip archive of JSON files.
+    pub async fn export(
+        user: UserEntity,
+        State(state): State<Arc<Mutex<AppState>>>,
+        meta: RequestMeta,
+    ) -> Result<Response, AppError> {
+        let (db, keys) = {
+            let state = state.lock().await;
+            (state.db.clone(), state.master_keys.clone())
+        };
+        let export = export_user_data(&db, keys.as_ref(), user.id).await?;
+        audit::record(
+            &db,
+            AuditEvent::new(AuditAction::DataExport, &meta).actor(&user),
+        )
+        .await;
+
+        let files = export.files().map_err(|err| {
+            tracing::error!("Error serializing export: {:?}", err);
+            AppError::InternalServerError
+        })?;
+        let archive = zip_archive(&files)?;
+        let disposition = format!("attachment; filename=\"scytale-export-{}.zip\"", user.id);
+
+        Ok((
+            [
+                (header::CONTENT_TYPE, "application/zip".to_string()),
+                (header::CONTENT_DISPOSITION, disposition),
+            ],
+            archive,
+        )
+            .into_response())
+    }
+
+    /// Deletes the account with everything that belongs to it and closes its
+    /// connections.
+    pub async fn delete_account(
+        client: AuthenticatedClient,
+        State(state): State<Arc<Mutex<AppState>>>,
+        meta: RequestMeta,
+        Json(payload): Json<Reauthentication>,
+    ) -> Result<StatusCode, AppError> {
+        let user = &client.user;
+        let event = AuditEvent::new(AuditAction::AccountDelete, &meta).actor(user);
+        Self::reauthenticate(&state, &client, &payload, meta.ip, event.clone()).await?;
+
+        let mut state = state.lock().await;
+        delete_user(&state.db, user.id).await?;
+        state.disconnect_user(&user.id, "account deleted").await;
+        audit::record(&state.db, event).await;
+
+        Ok(StatusCode::NO_CONTENT)
+    }
+
+    /// Checks the password through the auth providers and, if enabled, the
+    /// second factor. Failures count towards the login throttle and are
+    /// recorded as `event`. Users without a local password need a session
+    /// from a fresh login instead, see [`Self::is_fresh_login`].
+    async fn reauthenticate(
+        state: &Arc<Mutex<AppState>>,
+        client: &AuthenticatedClient,
+        reauth: &Reauthentication,
+        ip: Option<IpAddr>,
+        event: AuditEvent,
+    ) -> Result<(), AppError> {
+        let user = &client.user;
+        if !user.has_password() {
+            let db = state.lock().await.db.clone();
+            if Self::is_fresh_login(&db, user.id, client.session_id.as_deref()).await? {
+                return Ok(());
+            }
+            audit::record(&db, event.failure("no fresh login")).await;
+            return Err(AppError::FreshLoginRequired);
+        }
+
+        let (ctx, providers) = {
+            let mut state = state.lock().await;
+            // counted as failed until the credentials are checked
+            state.login_throttle.reserve(&user.email, ip)?;
+            (state.auth_context(), state.auth_providers.clone())
+        };
+
+        let authenticated = providers
+            .authenticate(&ctx, &user.email, &reauth.password)
+            .await;
+
+        let mut state = state.lock().await;
+        let authenticated = authenticated?.is_some_and(|authenticated| authenticated.id == user.id);
+        let mut verified = authenticated;
+        if verified && is_totp_enabled(&state.db, user.id).await? {
+            verified = match &reauth.code {
+                Some(code) => verify_second_factor(&state.db, user.id, code).await?,
+                None => false,
+            };
+        }
+
+        // the failure reserved above stays counted, wrong codes included
+        if !verified {
+            audit::record(&state.db, event.failure("re-authentication failed")).await;
+            return Err(AppError::WrongCredential);
+        }
+
+        state.login_throttle.release(&user.email, ip);
+        Ok(())
+    }
+
+    /// Whether the session was created by a login within the last
+    /// [`FRESH_LOGIN_MINUTES`], the login through the identity provider then
+    /// stands in for the password.
+    async fn is_fresh_login(
+        db: &Database,
+        user_id: i64,
+        session_id: Option<&str>,
+    ) -> Result<bool, AppError> {
+        let Some(session_id) = session_id else {
+            return Ok(false);
+        };
+        let since = Utc::now() - Duration::minutes(FRESH_LOGIN_MINUTES);
+
+        Ok(list_user_sessions(db, user_id)
+            .await?
+            .iter()
+            .any(|session| session.id == session_id && session.created_at > since))
     }
 }
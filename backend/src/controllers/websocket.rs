@@ -7,16 +7,28 @@ use axum::{
     },
     response::IntoResponse,
 };
+use chrono::Utc;
 use futures::{SinkExt, StreamExt};
 use tokio::sync::{mpsc, Mutex};
 
 use tracing::instrument::WithSubscriber;
 
 use crate::{
+    controllers::pairing::PairingController,
     error::AppError,
+    middleware::{PeerCert, RequestMeta},
     models::{
+        audit::{AuditAction, AuditEvent},
+        jwt::TokenType,
         user::{Client, UserEntity},
-        websocket::WsParam,
+        websocket::{WsCommand, WsEvent, WsParam},
+    },
+    service::{
+        audit,
+        clip::{store_clip, take_queued_clips},
+        device::{authenticate_client_cert, register_device, touch_device},
+        session::{bind_session_client, is_session_active},
+        user::is_user_disabled,
     },
     utils::decode_token,
     AppState,
@@ -28,26 +40,95 @@ impl WebsocketController {
     pub async fn ws_handler(
         Query(ws_para): Query<WsParam>,
         State(state): State<Arc<Mutex<AppState>>>,
+        meta: RequestMeta,
+        peer: PeerCert,
         ws: WebSocketUpgrade,
     ) -> Result<impl IntoResponse, AppError> {
-        let u_state = state.clone();
-        let u_state = u_state.lock().await;
-        let claim = decode_token(&ws_para.token, &u_state.keys).await?;
+        let u_state = state.lock().await;
+        let result = Self::authorize(&u_state, &ws_para, &peer, &meta).await;
 
-        let client = Client::new(ws_para.id.clone(), claim.id, ws_para.name.clone(), None);
-        tracing::debug!("New WebSocket Connection: {:?}", client);
-        if let Some(user) = u_state.users.get(&client.user_id) {
-            if let Some((_, tx)) = user.get(&client.id) {
+        let mut event = AuditEvent::new(AuditAction::WebsocketConnect, &meta)
+            .target(format!("client:{}", ws_para.id))
+            .outcome(&result);
+        if let Ok(client) = &result {
+            event.actor_id = Some(client.user_id);
+        }
+        audit::record(&u_state.db, event).await;
+        drop(u_state);
+
+        let client = result?;
+        Ok(ws.on_upgrade(|socket| Self::handle_socket(socket, client, state, meta)))
+    }
+
+    pub(crate) async fn authorize(
+        u_state: &AppState,
+        ws_para: &WsParam,
+        peer: &PeerCert,
+        meta: &RequestMeta,
+    ) -> Result<Client, AppError> {
+        let user_id = match &peer.0 {
+            Some(cert) if ws_para.token.is_empty() => {
+                // like a token, a certificate only speaks for its own device
+                if cert.client_id != ws_para.id {
+                    return Err(AppError::DeviceMismatch);
+                }
+                authenticate_client_cert(&u_state.db, cert).await?.id
+            }
+            _ => Self::authorize_token(u_state, ws_para).await?,
+        };
+
+        if let Some(user) = u_state.users.get(&user_id) {
+            if let Some((_, tx)) = user.get(&ws_para.id) {
                 let _ = tx.send(Message::Text("You are already connected".to_owned()));
                 return Err(AppError::AlreadyConnected);
             }
         }
-        drop(u_state);
 
-        Ok(ws.on_upgrade(|socket| Self::handle_socket(socket, client, state)))
+        // the stored name wins so that renaming a device sticks across reconnects
+        let device = register_device(&u_state.db, user_id, &ws_para.device(), meta.ip).await?;
+        let client = Client {
+            access: device.access,
+            expires_at: device.expires_at,
+            typed: ws_para.typed,
+            ..Client::new(device.client_id, user_id, device.name, None)
+        };
+        tracing::debug!("New WebSocket Connection: {:?}", client);
+
+        Ok(client)
+    }
+
+    /// Id of the user whose access token was passed along.
+    async fn authorize_token(u_state: &AppState, ws_para: &WsParam) -> Result<i64, AppError> {
+        let claim = decode_token(&ws_para.token, &u_state.keys).await?;
+        if !matches!(claim.token_type, TokenType::AccessToken) {
+            return Err(AppError::NotAccessToken);
+        }
+        let session_id = claim.sid.as_deref().ok_or(AppError::InvalidToken)?;
+        if !is_session_active(&u_state.db, session_id, claim.id).await? {
+            return Err(AppError::InvalidToken);
+        }
+        if is_user_disabled(&u_state.db, claim.id).await? {
+            return Err(AppError::UserDisabled);
+        }
+        // a token only speaks for the device it was issued to. Tokens of a plain
+        // login, from clients that predate device-bound tokens, are bound to
+        // the first device that connects with them
+        let bound = match claim.cid.as_deref() {
+            Some(client_id) => client_id == ws_para.id,
+            None => bind_session_client(&u_state.db, session_id, claim.id, &ws_para.id).await?,
+        };
+        if !bound {
+            return Err(AppError::DeviceMismatch);
+        }
+        Ok(claim.id)
     }
 
-    pub async fn handle_socket(socket: WebSocket, client: Client, state: Arc<Mutex<AppState>>) {
+    pub async fn handle_socket(
+        socket: WebSocket,
+        client: Client,
+        state: Arc<Mutex<AppState>>,
+        meta: RequestMeta,
+    ) {
         tracing::debug!("New WebSocket Upgraded: {:?}", client);
 
         let (tx, mut rx) = mpsc::unbounded_channel::<Message>();
@@ -59,24 +140,45 @@ impl WebsocketController {
         let uid = client.user_id;
         let client_id2 = client.id.clone();
 
+        // guests are shown the door when their access runs out
+        if let Some(expires_at) = client.expires_at {
+            let state = state.clone();
+            let client_id = client.id.clone();
+            let remaining = (expires_at - Utc::now()).to_std().unwrap_or_default();
+            tokio::spawn(async move {
+                tokio::time::sleep(remaining).await;
+                let mut state = state.lock().await;
+                state
+                    .disconnect_client(&uid, &client_id, "guest access expired")
+                    .await;
+            });
+        }
+
         {
-            u_state.lock().await.add_client(uid, client, tx).await;
+            let mut u_state = u_state.lock().await;
+            u_state.add_client(uid, client, tx).await;
+            Self::deliver_queued(&mut u_state, &uid, &client_id).await;
         }
 
         let mut send_task = tokio::task::spawn(async move {
             while let Some(msg) = rx.recv().await {
+                let is_close = matches!(msg, Message::Close(_));
                 if let Err(e) = sender.send(msg).await {
                     tracing::error!("Error sending message: {}", e);
                     break;
                 }
+                if is_close {
+                    break;
+                }
             }
         });
 
         let recv_state = state.clone();
         let mut recv_task = tokio::task::spawn(async move {
             while let Some(Ok(msg)) = receiver.next().await {
-                if let Err(_) =
-                    Self::handle_message(msg, recv_state.clone(), &uid, &client_id).await
+                if Self::handle_message(msg, recv_state.clone(), &uid, &client_id, &meta)
+                    .await
+                    .is_err()
                 {
                     tracing::debug!("Error handling message");
                     break;
@@ -91,6 +193,9 @@ impl WebsocketController {
         let state = state.clone();
         let mut state = state.lock().await;
         state.delete_client(&uid, &client_id2).await;
+        if let Err(err) = touch_device(&state.db, uid, &client_id2).await {
+            tracing::error!("Error updating last seen of device: {:?}", err);
+        }
     }
 
     pub async fn handle_message(
@@ -98,6 +203,7 @@ impl WebsocketController {
         state: Arc<Mutex<AppState>>,
         uid: &i64,
         client_id: &str,
+        meta: &RequestMeta,
     ) -> Result<(), ()> {
         match msg {
             Message::Ping(msg) => {
@@ -120,23 +226,133 @@ impl WebsocketController {
                 Ok(())
             }
             Message::Text(text) => {
-                tracing::debug!("Received text: {}", text);
+                tracing::debug!("Received text of {} bytes", text.len());
                 let mut state = state.lock().await;
+                let (access, typed, guest) = match state.get_client(uid, client_id).await {
+                    Some((client, _)) => (client.access, client.typed, client.expires_at.is_some()),
+                    None => return Err(()),
+                };
 
+                let text = if typed {
+                    Self::handle_command(&mut state, uid, client_id, guest, &text, meta).await?
+                } else {
+                    Some(text)
+                };
+                let Some(text) = text else {
+                    return Ok(());
+                };
+
+                if !access.can_send() {
+                    let event = WsEvent::Error {
+                        error: "this device may only receive".to_string(),
+                    };
+                    return Self::send_event(&mut state, uid, client_id, &event).await;
+                }
+
+                let mut online = Vec::new();
                 let user = state.users.get_mut(uid);
                 if let Some(user) = user {
                     for (_, (client, tx)) in user.iter_mut() {
-                        if client.id == client_id.clone() {
+                        online.push(client.id.clone());
+                        if client.id == client_id || !client.access.can_receive() {
                             continue;
                         }
-                        if let Err(e) = tx.send(Message::Text(text.clone())) {
+                        if let Err(e) = tx.send(Self::clip_message(client, &text)) {
                             tracing::error!("Error sending message: {}", e);
                             break;
                         }
                     }
                 }
+
+                let online: Vec<_> = online.iter().map(String::as_str).collect();
+                let keys = state.master_keys.as_ref();
+                if let Err(err) = store_clip(&state.db, keys, *uid, client_id, &text, &online).await
+                {
+                    tracing::error!("Error storing clip: {:?}", err);
+                }
                 Ok(())
             }
         }
     }
+
+    /// Unwraps the clip of a typed client, or answers its command and
+    /// returns `None`.
+    async fn handle_command(
+        state: &mut AppState,
+        uid: &i64,
+        client_id: &str,
+        guest: bool,
+        text: &str,
+        meta: &RequestMeta,
+    ) -> Result<Option<String>, ()> {
+        let payload = match serde_json::from_str::<WsCommand>(text) {
+            Ok(WsCommand::Clip { text }) => return Ok(Some(text)),
+            Ok(WsCommand::PairApprove(payload)) => payload,
+            Err(_) => {
+                let event = WsEvent::Error {
+                    error: "unknown command".to_string(),
+                };
+                Self::send_event(state, uid, client_id, &event).await?;
+                return Ok(None);
+            }
+        };
+
+        // guests cannot let further devices in on the account
+        let result = if guest {
+            Err(AppError::InsufficientPermission)
+        } else {
+            PairingController::review(state, *uid, &payload, meta).await
+        };
+        let event = match result {
+            Ok(device) => WsEvent::Pairing(device),
+            Err(err) => WsEvent::Error {
+                error: err.status_and_message().1,
+            },
+        };
+        Self::send_event(state, uid, client_id, &event).await?;
+        Ok(None)
+    }
+
+    /// Sends the clips relayed while the device was offline.
+    async fn deliver_queued(state: &mut AppState, uid: &i64, client_id: &str) {
+        let db = state.db.clone();
+        let keys = state.master_keys.clone();
+        let texts = match take_queued_clips(&db, keys.as_ref(), *uid, client_id).await {
+            Ok(texts) => texts,
+            Err(err) => {
+                tracing::error!("Error taking queued clips: {:?}", err);
+                return;
+            }
+        };
+        if let Some((client, tx)) = state.get_client(uid, client_id).await {
+            for text in texts {
+                let _ = tx.send(Self::clip_message(client, &text));
+            }
+        }
+    }
+
+    /// Relayed clips go out as raw text, wrapped in a `clip` event for typed
+    /// clients.
+    fn clip_message(client: &Client, text: &str) -> Message {
+        if !client.typed {
+            return Message::Text(text.to_string());
+        }
+        let event = WsEvent::Clip {
+            text: text.to_string(),
+        };
+        Message::Text(serde_json::to_string(&event).unwrap_or_default())
+    }
+
+    async fn send_event(
+        state: &mut AppState,
+        uid: &i64,
+        client_id: &str,
+        event: &WsEvent,
+    ) -> Result<(), ()> {
+        if let Some((_, tx)) = state.get_client(uid, client_id).await {
+            let event = serde_json::to_string(event).map_err(|_| ())?;
+            let _ = tx.send(Message::Text(event));
+        }
+        Ok(())
+    }
 }
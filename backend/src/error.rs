@@ -1,7 +1,11 @@
 #![allow(dead_code)]
 use std::borrow::{Borrow, Cow};
 
-use axum::{http::StatusCode, response::IntoResponse, Json};
+use axum::{
+    http::{header, HeaderValue, StatusCode},
+    response::IntoResponse,
+    Json,
+};
 use bcrypt::BcryptError;
 use serde_json::json;
 use sqlx::{error::DatabaseError, sqlite::SqliteError};
@@ -19,14 +23,63 @@ pub enum AppError {
     InternalServerError,
     UserDoesNotExist,
     UserAlreadyExits,
+    UserDisabled,
+    /// Admins may not disable or delete their own account.
+    CannotModifySelf,
+    RoleDoesNotExist,
+    RoleInUse,
+    /// The change would leave no role that grants `manage_roles`.
+    LastRoleManager,
+    LastRoleManagerUser,
     AlreadyConnected,
+    DeviceDoesNotExist,
+    /// The client id was revoked by its owner and may not reconnect.
+    DeviceRevoked,
+    /// The client id is already in use, tokens are only bound to new devices.
+    DeviceAlreadyRegistered,
+    InvalidPairingCode,
+    /// The token is bound to a different device than the one it is used for.
+    DeviceMismatch,
+    /// No user has the email of the client certificate.
+    UnknownClientCert,
+    InvalidGuest(String),
+    MfaAlreadyEnabled,
+    MfaNotEnabled,
+    InvalidMfaCode,
+    /// Carries the number of seconds until the next attempt is allowed.
+    TooManyAttempts(i64),
+    WeakPassword(String),
+    /// The account logs in through an external provider that owns the password.
+    PasswordManagedExternally,
+    InvalidInvite,
+    UnknownAuthProvider,
+    AuthProviderUnavailable,
+    OidcNotConfigured,
+    /// The identity provider could not be reached or returned garbage.
+    OidcProvider(String),
+    OidcLoginFailed,
+    OidcUserNotProvisioned,
+    /// Users without a local password re-authenticate by logging in through
+    /// their identity provider again.
+    FreshLoginRequired,
     EmptyPayload,
+    /// Stored content could not be encrypted or decrypted, never shown to clients.
+    Encryption(String),
+    InvalidRetention,
+    /// Taking or restoring a backup failed, e.g. because of a schema mismatch.
+    Backup(String),
+    /// The server could not listen on one of its addresses.
+    Bind(std::net::SocketAddr, std::io::Error),
+    /// The certificate or key for HTTPS could not be loaded or generated.
+    Tls(String),
     DatabaseError(sqlx::Error),
 }
 
-impl IntoResponse for AppError {
-    fn into_response(self) -> axum::response::Response {
-        let (status, err_msg) = match self {
+impl AppError {
+    /// Status and message of the error response, also used by the admin
+    /// commands to report errors.
+    pub fn status_and_message(&self) -> (StatusCode, String) {
+        match self {
             Self::InternalServerError => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "an internal server error occured".to_string(),
@@ -41,19 +94,162 @@ impl IntoResponse for AppError {
             Self::WrongCredential => (StatusCode::UNAUTHORIZED, "wrong credentials".to_string()),
             Self::UserDoesNotExist => (StatusCode::UNAUTHORIZED, "User does not exist".to_string()),
             Self::UserAlreadyExits => (StatusCode::BAD_REQUEST, "User already exists".to_string()),
+            Self::UserDisabled => (StatusCode::FORBIDDEN, "User is disabled".to_string()),
+            Self::CannotModifySelf => (
+                StatusCode::BAD_REQUEST,
+                "cannot disable or delete your own account".to_string(),
+            ),
+            Self::RoleDoesNotExist => (StatusCode::BAD_REQUEST, "Role does not exist".to_string()),
+            Self::RoleInUse => (
+                StatusCode::CONFLICT,
+                "Role is still assigned to users".to_string(),
+            ),
+            Self::LastRoleManager => (
+                StatusCode::CONFLICT,
+                "at least one role must keep manage_roles".to_string(),
+            ),
+            Self::LastRoleManagerUser => (
+                StatusCode::CONFLICT,
+                "at least one enabled user must keep manage_roles".to_string(),
+            ),
             Self::DatabaseError(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+            Self::InvalidRetention => (
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "retention must be between 0 and {} days",
+                    crate::models::retention::UserRetention::MAX_DAYS
+                ),
+            ),
+            Self::Backup(err) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("backup failed: {}", err),
+            ),
+            Self::Bind(addr, err) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("unable to listen on {}: {}", addr, err),
+            ),
+            Self::Tls(err) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("TLS setup failed: {}", err),
+            ),
+            Self::Encryption(_) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "an internal server error occured".to_string(),
+            ),
             Self::InsufficientPermission => {
                 (StatusCode::FORBIDDEN, "insufficient permission".to_string())
             }
             Self::NotAccessToken => (StatusCode::BAD_REQUEST, "not an access token".to_string()),
             Self::NotRefreshToken => (StatusCode::BAD_REQUEST, "not a refresh token".to_string()),
             Self::AlreadyConnected => (StatusCode::BAD_REQUEST, "already connected".to_string()),
+            Self::DeviceDoesNotExist => {
+                (StatusCode::NOT_FOUND, "device does not exist".to_string())
+            }
+            Self::DeviceRevoked => (StatusCode::FORBIDDEN, "device has been revoked".to_string()),
+            Self::DeviceAlreadyRegistered => (
+                StatusCode::CONFLICT,
+                "device is already registered".to_string(),
+            ),
+            Self::InvalidGuest(reason) => (StatusCode::BAD_REQUEST, reason.clone()),
+            Self::DeviceMismatch => (
+                StatusCode::FORBIDDEN,
+                "token is not bound to this device".to_string(),
+            ),
+            Self::UnknownClientCert => (
+                StatusCode::UNAUTHORIZED,
+                "client certificate does not belong to a user".to_string(),
+            ),
+            Self::InvalidPairingCode => (
+                StatusCode::BAD_REQUEST,
+                "invalid or expired pairing code".to_string(),
+            ),
+            Self::MfaAlreadyEnabled => (
+                StatusCode::BAD_REQUEST,
+                "two-factor authentication is already enabled".to_string(),
+            ),
+            Self::MfaNotEnabled => (
+                StatusCode::BAD_REQUEST,
+                "two-factor authentication is not enabled".to_string(),
+            ),
+            Self::InvalidMfaCode => (
+                StatusCode::UNAUTHORIZED,
+                "invalid two-factor code".to_string(),
+            ),
+            Self::WeakPassword(reason) => (StatusCode::BAD_REQUEST, reason.clone()),
+            Self::PasswordManagedExternally => (
+                StatusCode::CONFLICT,
+                "the password is managed by the authentication provider".to_string(),
+            ),
+            Self::InvalidInvite => (
+                StatusCode::BAD_REQUEST,
+                "invalid or expired invite".to_string(),
+            ),
+            Self::UnknownAuthProvider => (
+                StatusCode::BAD_REQUEST,
+                "unknown authentication provider".to_string(),
+            ),
+            Self::AuthProviderUnavailable => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "authentication provider unavailable".to_string(),
+            ),
+            Self::OidcNotConfigured => (
+                StatusCode::NOT_FOUND,
+                "single sign-on is not configured".to_string(),
+            ),
+            Self::OidcProvider(err) => (
+                StatusCode::BAD_GATEWAY,
+                format!("identity provider error: {}", err),
+            ),
+            Self::OidcLoginFailed => (
+                StatusCode::UNAUTHORIZED,
+                "single sign-on failed".to_string(),
+            ),
+            Self::OidcUserNotProvisioned => (
+                StatusCode::FORBIDDEN,
+                "no account is linked to this identity".to_string(),
+            ),
+            Self::FreshLoginRequired => (
+                StatusCode::UNAUTHORIZED,
+                "log in through your identity provider again".to_string(),
+            ),
+            Self::TooManyAttempts(seconds) => (
+                StatusCode::TOO_MANY_REQUESTS,
+                format!("too many failed attempts, retry in {} seconds", seconds),
+            ),
             Self::EmptyPayload => (
                 StatusCode::BAD_REQUEST,
                 "One of the field is empty".to_string(),
             ),
+        }
+    }
+}
+
+impl std::fmt::Display for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Encryption(err) => write!(f, "encryption error: {}", err),
+            _ => f.write_str(&self.status_and_message().1),
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> axum::response::Response {
+        if let Self::Encryption(err) = &self {
+            tracing::error!("encryption error: {}", err);
+        }
+        let retry_after = match self {
+            Self::TooManyAttempts(seconds) => Some(seconds),
+            _ => None,
         };
-        (status, Json(json!({ "error": err_msg }))).into_response()
+        let (status, err_msg) = self.status_and_message();
+        let mut response = (status, Json(json!({ "error": err_msg }))).into_response();
+        if let Some(seconds) = retry_after {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(seconds));
+        }
+        response
     }
 }
 
@@ -1,27 +1,580 @@
-#![allow(unused_variables)]
-#![allow(unused_imports)]
 
+use std::fmt::Display;
 use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand};
+use scytale::{admin, Config};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+/// Self hosted clipboard sharing server.
+#[derive(Parser)]
+#[command(name = "scytale", version)]
+struct Cli {
+    /// TOML config file. Flags and environment variables take precedence over
+    /// its settings.
+    #[arg(short, long, env = "SCYTALE_CONFIG", global = true)]
+    config: Option<PathBuf>,
+    #[command(flatten)]
+    overrides: Overrides,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Runs the server, the default without a command.
+    Serve,
+    /// Writes a consistent copy of the database to PATH while it is in use.
+    Backup { path: PathBuf },
+    /// Replaces the database with the backup at PATH, the server must be stopped.
+    Restore { path: PathBuf },
+    /// Inspects the configuration.
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Manages users directly in the database, e.g. to recover a lost admin
+    /// password.
+    #[command(subcommand)]
+    User(UserCommand),
+    /// Manages the devices of a user directly in the database.
+    #[command(subcommand)]
+    Device(DeviceCommand),
+    /// Applies pending database migrations.
+    Migrate,
+}
+
+/// The user a command acts on, by exactly one of its id and its email.
+#[derive(Args)]
+#[group(required = true, multiple = false)]
+struct UserArg {
+    /// Id of the user.
+    #[arg(long)]
+    id: Option<i64>,
+    /// Email of the user.
+    #[arg(long)]
+    email: Option<String>,
+}
+
+impl UserArg {
+    fn user_ref(self) -> admin::UserRef {
+        match (self.id, self.email) {
+            (Some(id), _) => admin::UserRef::Id(id),
+            (None, Some(email)) => admin::UserRef::Email(email),
+            (None, None) => unreachable!("clap requires --id or --email"),
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum UserCommand {
+    /// Creates a user, with a generated password unless one is given.
+    Create {
+        email: String,
+        #[arg(long)]
+        name: String,
+        #[arg(long, default_value = "USER")]
+        role: String,
+        #[arg(long, env = "SCYTALE_USER_PASSWORD", hide_env_values = true)]
+        password: Option<String>,
+    },
+    /// Lists the users whose email or name contains QUERY, or all of them.
+    List { query: Option<String> },
+    /// Gives the user another role.
+    SetRole {
+        #[command(flatten)]
+        user: UserArg,
+        role: String,
+    },
+    /// Sets a new password, generated unless one is given, and ends every
+    /// session of the user.
+    ResetPassword {
+        #[command(flatten)]
+        user: UserArg,
+        #[arg(long, env = "SCYTALE_USER_PASSWORD", hide_env_values = true)]
+        password: Option<String>,
+    },
+    /// Removes the two-factor authentication of the user, who can then log in
+    /// with their password alone and enroll again.
+    ResetTotp {
+        #[command(flatten)]
+        user: UserArg,
+    },
+    /// Blocks the user from logging in and ends every session.
+    Disable {
+        #[command(flatten)]
+        user: UserArg,
+    },
+}
+
+#[derive(Subcommand)]
+enum DeviceCommand {
+    /// Lists the devices of a user.
+    List {
+        #[command(flatten)]
+        user: UserArg,
+    },
+    /// Blocks a device of the user from reconnecting.
+    Revoke {
+        #[command(flatten)]
+        user: UserArg,
+        client_id: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Validates the configuration and prints the effective one with secrets
+    /// redacted.
+    Check,
+}
+
+/// Settings that override the config file.
+#[derive(Args)]
+struct Overrides {
+    /// Comma separated addresses to listen on.
+    #[arg(long, env = "LISTEN", value_delimiter = ',', global = true)]
+    listen: Vec<SocketAddr>,
+    /// Serves HTTPS and WSS, with the certificate of --tls-cert-file.
+    #[arg(long, env = "TLS_ENABLED", global = true)]
+    tls: bool,
+    #[arg(long, env = "TLS_CERT_FILE", global = true)]
+    tls_cert_file: Option<PathBuf>,
+    #[arg(long, env = "TLS_KEY_FILE", global = true)]
+    tls_key_file: Option<PathBuf>,
+    /// Generates a self-signed certificate when the files do not exist yet.
+    #[arg(long, env = "TLS_SELF_SIGNED", global = true)]
+    tls_self_signed: bool,
+    /// Plain HTTP address that redirects to HTTPS.
+    #[arg(long, env = "TLS_REDIRECT_FROM", global = true)]
+    tls_redirect_from: Option<SocketAddr>,
+    /// CAs whose client certificates log devices in without a token.
+    #[arg(long, env = "TLS_CLIENT_CA_FILE", global = true)]
+    tls_client_ca_file: Option<PathBuf>,
+    #[arg(long, env = "TLS_CLIENT_CERT_REQUIRED", global = true)]
+    tls_client_cert_required: bool,
+    #[arg(long, env = "DATABASE_URL", global = true)]
+    database_url: Option<String>,
+    #[arg(long, env = "JWT_SECRET", hide_env_values = true, global = true)]
+    jwt_secret: Option<String>,
+    #[arg(long, env = "JWT_TOKEN_LIFETIME_MINUTES", global = true)]
+    token_lifetime_minutes: Option<i64>,
+    #[arg(long, env = "ADMIN_EMAIL", global = true)]
+    admin_email: Option<String>,
+    #[arg(long, env = "ADMIN_PASSWORD", hide_env_values = true, global = true)]
+    admin_password: Option<String>,
+    #[arg(long, env = "ADMIN_NAME", global = true)]
+    admin_name: Option<String>,
+    /// Comma separated origins, `*` allows any.
+    #[arg(
+        long,
+        env = "CORS_ALLOWED_ORIGINS",
+        value_delimiter = ',',
+        global = true
+    )]
+    cors_allowed_origins: Vec<String>,
+    #[arg(long, env = "MAX_BODY_BYTES", global = true)]
+    max_body_bytes: Option<usize>,
+    #[arg(long, env = "REQUEST_TIMEOUT_SECONDS", global = true)]
+    request_timeout_seconds: Option<u64>,
+    #[arg(long, env = "SHUTDOWN_TIMEOUT_SECONDS", global = true)]
+    shutdown_timeout_seconds: Option<u64>,
+    #[arg(long, env = "RUST_LOG", global = true)]
+    log_filter: Option<String>,
+    #[arg(long, env = "PASSWORD_MIN_LENGTH", global = true)]
+    password_min_length: Option<usize>,
+    #[arg(long, env = "BREACHED_PASSWORDS_FILE", global = true)]
+    breached_passwords_file: Option<PathBuf>,
+    #[arg(long, env = "RETENTION_DAYS", global = true)]
+    retention_days: Option<i64>,
+    #[arg(long, env = "MASTER_KEY", hide_env_values = true, global = true)]
+    master_key: Option<String>,
+    /// Comma separated keys that --master-key replaced.
+    #[arg(
+        long,
+        env = "MASTER_KEY_PREVIOUS",
+        value_delimiter = ',',
+        hide_env_values = true,
+        global = true
+    )]
+    master_key_previous: Vec<String>,
+    #[arg(long, env = "LDAP_URL", global = true)]
+    ldap_url: Option<String>,
+    #[arg(long, env = "LDAP_BASE_DN", global = true)]
+    ldap_base_dn: Option<String>,
+    #[arg(long, env = "LDAP_BIND_DN", global = true)]
+    ldap_bind_dn: Option<String>,
+    #[arg(
+        long,
+        env = "LDAP_BIND_PASSWORD",
+        hide_env_values = true,
+        global = true
+    )]
+    ldap_bind_password: Option<String>,
+    #[arg(long, env = "LDAP_USER_FILTER", global = true)]
+    ldap_user_filter: Option<String>,
+    #[arg(long, env = "LDAP_PROVISION", global = true)]
+    ldap_provision: bool,
+    #[arg(long, env = "OIDC_ISSUER_URL", global = true)]
+    oidc_issuer_url: Option<String>,
+    #[arg(long, env = "OIDC_CLIENT_ID", global = true)]
+    oidc_client_id: Option<String>,
+    #[arg(
+        long,
+        env = "OIDC_CLIENT_SECRET",
+        hide_env_values = true,
+        global = true
+    )]
+    oidc_client_secret: Option<String>,
+    #[arg(long, env = "OIDC_REDIRECT_URL", global = true)]
+    oidc_redirect_url: Option<String>,
+    #[arg(long, env = "OIDC_PROVISION", global = true)]
+    oidc_provision: bool,
+    #[arg(long, env = "OIDC_LINK_BY_EMAIL", global = true)]
+    oidc_link_by_email: bool,
+    #[arg(long, env = "OIDC_GROUPS_CLAIM", global = true)]
+    oidc_groups_claim: Option<String>,
+    /// Comma separated group=ROLE pairs.
+    #[arg(long, env = "OIDC_GROUP_ROLES", value_delimiter = ',', global = true)]
+    oidc_group_roles: Vec<String>,
+}
+
+impl Overrides {
+    fn apply(self, config: &mut Config) {
+        if !self.listen.is_empty() {
+            config.server.listen = self.listen;
+        }
+        if self.tls {
+            config.tls.enabled = true;
+        }
+        if let Some(path) = self.tls_cert_file {
+            config.tls.cert_file = Some(path);
+        }
+        if let Some(path) = self.tls_key_file {
+            config.tls.key_file = Some(path);
+        }
+        if self.tls_self_signed {
+            config.tls.self_signed = true;
+        }
+        if let Some(addr) = self.tls_redirect_from {
+            config.tls.redirect_from = Some(addr);
+        }
+        if let Some(path) = self.tls_client_ca_file {
+            config.tls.client_ca_file = Some(path);
+        }
+        if self.tls_client_cert_required {
+            config.tls.client_cert_required = true;
+        }
+        if let Some(url) = self.database_url {
+            config.database.url = url;
+        }
+        if let Some(secret) = self.jwt_secret {
+            config.jwt.secret = secret;
+        }
+        if let Some(minutes) = self.token_lifetime_minutes {
+            config.jwt.token_lifetime_minutes = minutes;
+        }
+        if let Some(email) = self.admin_email {
+            config.admin.email = email;
+        }
+        if let Some(password) = self.admin_password {
+            config.admin.password = password;
+        }
+        if let Some(name) = self.admin_name {
+            config.admin.name = name;
+        }
+        if !self.cors_allowed_origins.is_empty() {
+            config.cors.allowed_origins = self.cors_allowed_origins;
+        }
+        if let Some(bytes) = self.max_body_bytes {
+            config.limits.max_body_bytes = bytes;
+        }
+        if let Some(seconds) = self.request_timeout_seconds {
+            config.limits.request_timeout_seconds = seconds;
+        }
+        if let Some(seconds) = self.shutdown_timeout_seconds {
+            config.limits.shutdown_timeout_seconds = seconds;
+        }
+        if let Some(filter) = self.log_filter {
+            config.logging.filter = filter;
+        }
+        if let Some(min_length) = self.password_min_length {
+            config.password.min_length = min_length;
+        }
+        if let Some(path) = self.breached_passwords_file {
+            config.password.breached_passwords_file = Some(path);
+        }
+        if let Some(days) = self.retention_days {
+            config.retention.days = days;
+        }
+        if let Some(key) = self.master_key {
+            config.encryption.master_key = key;
+        }
+        if !self.master_key_previous.is_empty() {
+            config.encryption.previous_master_keys = self
+                .master_key_previous
+                .into_iter()
+                .filter(|key| !key.trim().is_empty())
+                .collect();
+        }
+        if let Some(url) = self.ldap_url {
+            config.ldap.url = url;
+        }
+        if let Some(base_dn) = self.ldap_base_dn {
+            config.ldap.base_dn = base_dn;
+        }
+        if let Some(bind_dn) = self.ldap_bind_dn {
+            config.ldap.bind_dn = Some(bind_dn);
+        }
+        if let Some(password) = self.ldap_bind_password {
+            config.ldap.bind_password = Some(password);
+        }
+        if let Some(filter) = self.ldap_user_filter {
+            config.ldap.user_filter = filter;
+        }
+        if self.ldap_provision {
+            config.ldap.provision = true;
+        }
+        if let Some(url) = self.oidc_issuer_url {
+            config.oidc.issuer_url = url;
+        }
+        if let Some(client_id) = self.oidc_client_id {
+            config.oidc.client_id = client_id;
+        }
+        if let Some(secret) = self.oidc_client_secret {
+            config.oidc.client_secret = Some(secret);
+        }
+        if let Some(url) = self.oidc_redirect_url {
+            config.oidc.redirect_url = url;
+        }
+        if self.oidc_provision {
+            config.oidc.provision = true;
+        }
+        if self.oidc_link_by_email {
+            config.oidc.link_by_email = true;
+        }
+        if let Some(claim) = self.oidc_groups_claim {
+            config.oidc.groups_claim = claim;
+        }
+        if !self.oidc_group_roles.is_empty() {
+            config.oidc.group_roles = self.oidc_group_roles;
+        }
+    }
+}
+
+/// Reports `err` and exits, used instead of panicking on bad input.
+fn fail(err: impl Display) -> ! {
+    eprintln!("error: {}", err);
+    std::process::exit(1);
+}
+
+async fn connect(config: &Config) -> scytale::Database {
+    config.database.validate().unwrap_or_else(|err| fail(err));
+    scytale::connect(&config.database.url)
+        .await
+        .unwrap_or_else(|err| fail(format!("unable to set up database: {}", err)))
+}
+
+/// Like [`connect`] for the offline commands, which leave the schema alone.
+async fn open(config: &Config) -> scytale::Database {
+    config.database.validate().unwrap_or_else(|err| fail(err));
+    scytale::open(&config.database.url)
+        .await
+        .unwrap_or_else(|err| fail(format!("unable to open database: {}", err)))
+}
+
+/// `password` or a generated one, which is printed since nobody knows it yet.
+fn password_or_generated(password: Option<String>) -> String {
+    password.unwrap_or_else(|| {
+        let password = admin::generate_password();
+        println!("Generated password: {}", password);
+        password
+    })
+}
+
+async fn run_user_command(command: UserCommand, config: &Config) {
+    let db = open(config).await;
+    let policy = || config.password_policy().unwrap_or_else(|err| fail(err));
+    let hash_params = || config.hash_params().unwrap_or_else(|err| fail(err));
+    match command {
+        UserCommand::Create {
+            email,
+            name,
+            role,
+            password,
+        } => {
+            let (policy, hash_params) = (policy(), hash_params());
+            let password = password_or_generated(password);
+            let user =
+                admin::create_user(&db, &policy, &hash_params, &email, &name, &password, &role)
+                    .await
+                    .unwrap_or_else(|err| fail(err));
+            println!(
+                "Created user {} <{}> with role {}",
+                user.id, user.email, user.role
+            );
+        }
+        UserCommand::List { query } => {
+            let users = admin::list_users(&db, query)
+                .await
+                .unwrap_or_else(|err| fail(err));
+            println!(
+                "{:>6}  {:<32}  {:<24}  {:<12}  STATUS",
+                "ID", "EMAIL", "NAME", "ROLE"
+            );
+            for user in users {
+                let status = match user.disabled_at {
+                    Some(_) => "disabled",
+                    None => "active",
+                };
+                println!(
+                    "{:>6}  {:<32}  {:<24}  {:<12}  {}",
+                    user.id,
+                    user.email,
+                    user.name,
+                    user.role.to_string(),
+                    status
+                );
+            }
+        }
+        UserCommand::SetRole { user, role } => {
+            let user = admin::set_role(&db, &user.user_ref(), &role)
+                .await
+                .unwrap_or_else(|err| fail(err));
+            println!(
+                "User {} <{}> now has role {}",
+                user.id, user.email, user.role
+            );
+        }
+        UserCommand::ResetPassword { user, password } => {
+            let (policy, hash_params) = (policy(), hash_params());
+            let password = password_or_generated(password);
+            let user =
+                admin::reset_password(&db, &policy, &hash_params, &user.user_ref(), &password)
+                    .await
+                    .unwrap_or_else(|err| fail(err));
+            println!("Reset the password of user {} <{}>", user.id, user.email);
+        }
+        UserCommand::ResetTotp { user } => {
+            let user = admin::reset_totp(&db, &user.user_ref())
+                .await
+                .unwrap_or_else(|err| fail(err));
+            println!(
+                "Reset the two-factor authentication of user {} <{}>",
+                user.id, user.email
+            );
+        }
+        UserCommand::Disable { user } => {
+            let user = admin::disable_user(&db, &user.user_ref())
+                .await
+                .unwrap_or_else(|err| fail(err));
+            println!("Disabled user {} <{}>", user.id, user.email);
+        }
+    }
+}
+
+async fn run_device_command(command: DeviceCommand, config: &Config) {
+    let db = open(config).await;
+    match command {
+        DeviceCommand::List { user } => {
+            let devices = admin::list_devices(&db, &user.user_ref())
+                .await
+                .unwrap_or_else(|err| fail(err));
+            println!(
+                "{:<36}  {:<24}  {:<25}  STATUS",
+                "CLIENT ID", "NAME", "LAST SEEN"
+            );
+            for device in devices {
+                let status = match (device.revoked_at, device.expires_at) {
+                    (Some(_), _) => "revoked".to_string(),
+                    (None, Some(expires_at)) => format!("guest until {}", expires_at),
+                    (None, None) => "active".to_string(),
+                };
+                println!(
+                    "{:<36}  {:<24}  {:<25}  {}",
+                    device.client_id,
+                    device.name,
+                    device.last_seen_at.format("%Y-%m-%d %H:%M:%S UTC"),
+                    status
+                );
+            }
+        }
+        DeviceCommand::Revoke { user, client_id } => {
+            let device = admin::revoke_device(&db, &user.user_ref(), &client_id)
+                .await
+                .unwrap_or_else(|err| fail(err));
+            println!("Revoked device {} ({})", device.client_id, device.name);
+        }
+    }
+}
 
 #[tokio::main]
 async fn main() {
     dotenv::dotenv().ok();
-    let db_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    let secret = std::env::var("JWT_SECRET").expect("SECRET must be set");
-    let admin_email = std::env::var("ADMIN_EMAIL").expect("ADMIN_EMAIL must be set");
-    let admin_password = std::env::var("ADMIN_PASSWORD").expect("ADMIN_PASSWORD must be set");
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
-
-    let admin_name = "Maulik Patel".to_string();
-
-    let app = scytale::Scytale::new(
-        addr,
-        db_url,
-        secret,
-        admin_email,
-        admin_password,
-        admin_name,
-    )
-    .start()
-    .await;
+    let cli = Cli::parse();
+
+    let mut config = match &cli.config {
+        Some(path) => Config::load(path).unwrap_or_else(|err| fail(err)),
+        None => Config::default(),
+    };
+    cli.overrides.apply(&mut config);
+
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => {}
+        Command::Backup { path } => {
+            let db = open(&config).await;
+            db.backup(&path).await.unwrap_or_else(|err| fail(err));
+            println!("Backed up the database to {}", path.display());
+            return;
+        }
+        Command::Restore { path } => {
+            config.database.validate().unwrap_or_else(|err| fail(err));
+            scytale::restore(&config.database.url, &path)
+                .await
+                .unwrap_or_else(|err| fail(err));
+            println!("Restored the database from {}", path.display());
+            return;
+        }
+        Command::Config(ConfigCommand::Check) => {
+            let valid = config.validate();
+            print!("{}", config.redacted().to_toml());
+            match valid {
+                Ok(()) => return,
+                Err(err) => fail(err),
+            }
+        }
+        Command::User(command) => return run_user_command(command, &config).await,
+        Command::Device(command) => return run_device_command(command, &config).await,
+        Command::Migrate => {
+            connect(&config).await;
+            println!("The database is up to date");
+            return;
+        }
+    }
+
+    let app = scytale::Scytale::from_config(&config).unwrap_or_else(|err| fail(err));
+
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::EnvFilter::new(&config.logging.filter))
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+    let server = app.serve().await.unwrap_or_else(|err| fail(err));
+    shutdown_signal().await;
+    tracing::info!("Shutting down");
+    server.shutdown().await;
+}
+
+/// Ctrl-C, or SIGTERM as sent by service managers and container runtimes.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut terminate =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+                .unwrap_or_else(|err| fail(format!("unable to listen for SIGTERM: {}", err)));
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
 }
@@ -1,8 +1,13 @@
-use std::sync::Arc;
+use std::{
+    convert::Infallible,
+    marker::PhantomData,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
 
 use axum::{
     async_trait,
-    extract::{FromRef, FromRequest, FromRequestParts, State},
+    extract::{ConnectInfo, FromRef, FromRequest, FromRequestParts, State},
     headers::{authorization::Bearer, Authorization},
     http::{self, request::Parts, Request, StatusCode},
     middleware::Next,
@@ -10,23 +15,39 @@ use axum::{
     Extension, RequestPartsExt, TypedHeader,
 };
 use jsonwebtoken::{decode, Validation};
-use sqlx::SqlitePool;
 use tokio::sync::Mutex;
 
 use crate::{
     error::AppError,
     models::{
+        device::ClientCert,
         jwt::{Claims, TokenType},
+        role::Permission,
         state::AppStateType,
-        user::{Role, UserEntity},
+        user::UserEntity,
+    },
+    service::{
+        device::authenticate_client_cert,
+        role::has_permission,
+        session::is_session_active,
+        user::{get_user_by_id, is_user_disabled},
     },
-    service::user::get_user_by_id,
     utils::decode_token,
     AppState,
 };
 
+/// The user of an access token or client certificate together with the
+/// device it is bound to, `None` for tokens of a plain login.
+#[derive(Debug, Clone)]
+pub struct AuthenticatedClient {
+    pub user: UserEntity,
+    pub client_id: Option<String>,
+    /// Session of the token, `None` for client certificates.
+    pub session_id: Option<String>,
+}
+
 #[async_trait]
-impl<S> FromRequestParts<S> for UserEntity
+impl<S> FromRequestParts<S> for AuthenticatedClient
 where
     AppStateType: FromRef<S>,
     S: Send + Sync,
@@ -34,37 +55,179 @@ where
     type Rejection = AppError;
 
     async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
-        let TypedHeader(Authorization(bearer)) = parts
-            .extract::<TypedHeader<Authorization<Bearer>>>()
-            .await
-            .map_err(|_| AppError::MissingToken)?;
-
         let state = AppStateType::from_ref(state);
+        let Ok(TypedHeader(Authorization(bearer))) =
+            parts.extract::<TypedHeader<Authorization<Bearer>>>().await
+        else {
+            // devices with a client certificate get by without a token
+            let cert = parts
+                .extensions
+                .get::<PeerCert>()
+                .and_then(|PeerCert(cert)| cert.clone())
+                .ok_or(AppError::MissingToken)?;
+            let state = state.lock().await;
+            let user = authenticate_client_cert(&state.db, &cert).await?;
+            return Ok(Self {
+                user,
+                client_id: Some(cert.client_id),
+                session_id: None,
+            });
+        };
         let state = state.lock().await;
 
         let claims = decode_token(bearer.token(), &state.keys).await?;
 
         match claims.token_type {
             TokenType::AccessToken => {
+                if claims.guest {
+                    return Err(AppError::InsufficientPermission);
+                }
                 let id = claims.id;
-                let email = claims.email;
-                let user = get_user_by_id(&state.pool, id).await?;
-                Ok(user)
+                let session_id = claims.sid.ok_or(AppError::InvalidToken)?;
+                if !is_session_active(&state.db, &session_id, id).await? {
+                    return Err(AppError::InvalidToken);
+                }
+                if is_user_disabled(&state.db, id).await? {
+                    return Err(AppError::UserDisabled);
+                }
+                let user = get_user_by_id(&state.db, id).await?;
+                Ok(Self {
+                    user,
+                    client_id: claims.cid,
+                    session_id: Some(session_id),
+                })
             }
-            TokenType::RefreshToken => {
+            TokenType::RefreshToken | TokenType::MfaPending => {
                 return Err(AppError::NotAccessToken);
             }
         }
     }
 }
-pub async fn is_admin<B>(
-    user: UserEntity,
-    State(state): State<Arc<Mutex<AppState>>>,
+
+#[async_trait]
+impl<S> FromRequestParts<S> for UserEntity
+where
+    AppStateType: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let client = AuthenticatedClient::from_request_parts(parts, state).await?;
+        Ok(client.user)
+    }
+}
+
+/// A [`Permission`] known at compile time, used to parameterize [`Authorized`].
+pub trait RequiredPermission: Send + Sync + 'static {
+    const PERMISSION: Permission;
+}
+
+macro_rules! required_permissions {
+    ($($name:ident),* $(,)?) => {
+        $(
+            pub struct $name;
+
+            impl RequiredPermission for $name {
+                const PERMISSION: Permission = Permission::$name;
+            }
+        )*
+    };
+}
+
+pub mod permission {
+    use super::RequiredPermission;
+    use crate::models::role::Permission;
+
+    required_permissions!(
+        ManageUsers,
+        ManageRoles,
+        ViewAuditLog,
+        ReadMetrics,
+        ManageBackups,
+    );
+}
+
+/// Proof that the authenticated user has a role granting `P`, rejected with
+/// [`AppError::InsufficientPermission`] otherwise.
+pub struct Authorized<P>(PhantomData<fn() -> P>);
+
+#[async_trait]
+impl<S, P> FromRequestParts<S> for Authorized<P>
+where
+    AppStateType: FromRef<S>,
+    S: Send + Sync,
+    P: RequiredPermission,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let user = UserEntity::from_request_parts(parts, state).await?;
+
+        let state = AppStateType::from_ref(state);
+        let state = state.lock().await;
+
+        if has_permission(&state.db, &user.role, P::PERMISSION).await? {
+            Ok(Self(PhantomData))
+        } else {
+            Err(AppError::InsufficientPermission)
+        }
+    }
+}
+
+/// Route layer guarding every route behind permission `P`:
+/// `from_fn_with_state(state, require_permission::<permission::ManageUsers, _>)`.
+pub async fn require_permission<P: RequiredPermission, B>(
+    _: Authorized<P>,
     req: Request<B>,
     next: Next<B>,
 ) -> Result<Response, AppError> {
-    match user.role {
-        Role::ADMIN => Ok(next.run(req).await),
-        Role::USER => Err(AppError::InsufficientPermission),
+    Ok(next.run(req).await)
+}
+
+/// The [`ClientCert`] the TLS connection of the request was opened with,
+/// `None` without one or when serving plain HTTP.
+#[derive(Debug, Clone, Default)]
+pub struct PeerCert(pub Option<ClientCert>);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for PeerCert
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        Ok(parts.extensions.get::<Self>().cloned().unwrap_or_default())
+    }
+}
+
+/// Who is on the other end of the request, recorded in the audit log. The ip
+/// is only known when the server was started with connect info.
+#[derive(Debug, Clone, Default)]
+pub struct RequestMeta {
+    pub ip: Option<IpAddr>,
+    pub user_agent: Option<String>,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for RequestMeta
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        let ip = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        let user_agent = parts
+            .headers
+            .get(http::header::USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+
+        Ok(Self { ip, user_agent })
     }
 }
@@ -1,6 +1,22 @@
+pub mod audit;
 pub mod auth;
+pub mod clip;
+pub mod config;
+pub mod device;
+pub mod encryption;
+pub mod export;
+pub mod invite;
 pub mod jwt;
+pub mod ldap;
+pub mod oidc;
+pub mod pairing;
+pub mod password;
+pub mod retention;
+pub mod role;
+pub mod scytale;
+pub mod session;
 pub mod state;
+pub mod throttle;
+pub mod totp;
 pub mod user;
 pub mod websocket;
-pub mod scytale;
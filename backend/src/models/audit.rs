@@ -0,0 +1,167 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::user::UserEntity;
+use crate::middleware::RequestMeta;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+pub enum AuditAction {
+    Login,
+    LoginMfa,
+    OidcLogin,
+    TokenRefresh,
+    WebsocketConnect,
+    UserCreate,
+    UserRegister,
+    UserUpdate,
+    UserDisable,
+    UserEnable,
+    UserDelete,
+    UserLogout,
+    PasswordChange,
+    ProfileUpdate,
+    DataExport,
+    AccountDelete,
+    PasswordReset,
+    PasswordResetIssue,
+    TotpEnable,
+    TotpDisable,
+    TotpReset,
+    AccountUnlock,
+    AuthProviderChange,
+    RoleUpdate,
+    RoleDelete,
+    InviteCreate,
+    InviteRevoke,
+    DeviceRegister,
+    GuestCreate,
+    DeviceRename,
+    DeviceRevoke,
+    DevicePair,
+    DatabaseBackup,
+    RetentionChange,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+pub enum AuditResult {
+    Success,
+    Failure,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub created_at: DateTime<Utc>,
+    pub actor_id: Option<i64>,
+    pub actor_email: Option<String>,
+    pub action: AuditAction,
+    pub target: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub result: AuditResult,
+    pub detail: Option<String>,
+}
+
+/// An entry about to be written, see [`crate::service::audit::record`].
+#[derive(Debug, Clone)]
+pub struct AuditEvent {
+    pub actor_id: Option<i64>,
+    pub actor_email: Option<String>,
+    pub action: AuditAction,
+    pub target: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub result: AuditResult,
+    pub detail: Option<String>,
+}
+
+impl AuditEvent {
+    pub fn new(action: AuditAction, meta: &RequestMeta) -> Self {
+        Self {
+            actor_id: None,
+            actor_email: None,
+            action,
+            target: None,
+            ip: meta.ip.map(|ip| ip.to_string()),
+            user_agent: meta.user_agent.clone(),
+            result: AuditResult::Success,
+            detail: None,
+        }
+    }
+
+    pub fn actor(mut self, user: &UserEntity) -> Self {
+        self.actor_id = Some(user.id);
+        self.actor_email = Some(user.email.clone());
+        self
+    }
+
+    /// For failed logins, where only the attempted email is known.
+    pub fn actor_email(mut self, email: &str) -> Self {
+        self.actor_email = Some(email.to_string());
+        self
+    }
+
+    pub fn target(mut self, target: impl ToString) -> Self {
+        self.target = Some(target.to_string());
+        self
+    }
+
+    pub fn detail(mut self, detail: impl ToString) -> Self {
+        self.detail = Some(detail.to_string());
+        self
+    }
+
+    pub fn failure(mut self, detail: impl ToString) -> Self {
+        self.result = AuditResult::Failure;
+        self.detail(detail)
+    }
+
+    /// Success, or failure with the error as detail.
+    pub fn outcome<T, E: std::fmt::Debug>(self, result: &Result<T, E>) -> Self {
+        match result {
+            Ok(_) => self,
+            Err(err) => self.failure(format!("{:?}", err)),
+        }
+    }
+}
+
+/// Filters of the audit query, all optional and combined with AND.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct AuditQuery {
+    pub actor_id: Option<i64>,
+    pub actor_email: Option<String>,
+    pub action: Option<AuditAction>,
+    pub target: Option<String>,
+    pub result: Option<AuditResult>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    #[serde(default = "AuditQuery::default_page")]
+    pub page: i64,
+    #[serde(default = "AuditQuery::default_per_page")]
+    pub per_page: i64,
+}
+
+/// Newest entries first.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AuditPage {
+    pub entries: Vec<AuditEntry>,
+    pub total: i64,
+    pub page: i64,
+    pub per_page: i64,
+}
+
+impl AuditQuery {
+    pub const MAX_PER_PAGE: i64 = 500;
+
+    fn default_page() -> i64 {
+        1
+    }
+
+    fn default_per_page() -> i64 {
+        50
+    }
+}
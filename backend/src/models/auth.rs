@@ -9,3 +9,23 @@ pub struct LoginResponse {
     pub access_token: String,
     pub refresh_token: String,
 }
+
+/// Returned by login instead of [`LoginResponse`] when the user has TOTP enabled.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MfaPendingResponse {
+    pub message: String,
+    pub mfa_token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MfaLogin {
+    pub mfa_token: String,
+    /// Either a TOTP code or one of the recovery codes.
+    pub code: String,
+}
+
+/// Pins a user to one authentication provider, `null` lets every provider try.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuthProviderUpdate {
+    pub provider: Option<String>,
+}
@@ -0,0 +1,34 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Row of `clips`, a clip relayed between the devices of a user. `content` is
+/// sealed with the user's data key, with the id as the additional data.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct ClipEntity {
+    pub id: String,
+    pub user_id: i64,
+    /// The device that sent the clip.
+    pub client_id: String,
+    pub content: Vec<u8>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Row of `clip_queue`, a clip waiting for the device `client_id`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct QueuedClipEntity {
+    pub clip_id: String,
+    pub user_id: i64,
+    pub client_id: String,
+    pub queued_at: DateTime<Utc>,
+}
+
+/// A clip of the history as handed out by the data export, `text` is `None`
+/// when the clip could not be opened.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ClipExport {
+    pub id: String,
+    /// The device that sent the clip.
+    pub client_id: String,
+    pub text: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
@@ -0,0 +1,128 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Row of `devices`, one per client id a user has connected with.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct DeviceEntity {
+    pub client_id: String,
+    pub user_id: i64,
+    pub name: String,
+    pub platform: Option<String>,
+    pub app_version: Option<String>,
+    pub first_seen_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub last_ip: Option<String>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub access: DeviceAccess,
+    /// Only set for guest devices.
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// Which way clips may flow for a device. Regular devices have full access,
+/// guests are limited to one direction.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+pub enum DeviceAccess {
+    #[default]
+    Full,
+    /// Gets the clips of the other devices but cannot send any.
+    Receive,
+    /// Sends clips to the other devices but gets none.
+    Send,
+}
+
+impl DeviceAccess {
+    pub fn can_send(self) -> bool {
+        self != Self::Receive
+    }
+
+    pub fn can_receive(self) -> bool {
+        self != Self::Send
+    }
+}
+
+/// Device as listed to its owner.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Device {
+    #[serde(flatten)]
+    pub device: DeviceEntity,
+    pub online: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DeviceRename {
+    pub name: String,
+}
+
+/// What a client tells about itself when it connects or asks to be paired.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct DeviceRegistration {
+    pub id: String,
+    pub name: String,
+    /// E.g. `android` or `linux`.
+    pub platform: Option<String>,
+    pub app_version: Option<String>,
+}
+
+/// Longest a guest device may be valid for.
+pub const MAX_GUEST_MINUTES: i64 = 24 * 60;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GuestCreate {
+    pub name: String,
+    /// `receive` or `send`.
+    pub access: DeviceAccess,
+    /// Defaults to an hour.
+    pub minutes: Option<i64>,
+}
+
+/// The token is only handed out once and works on `/api/ws` alone.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GuestCreated {
+    pub client_id: String,
+    pub name: String,
+    pub access: DeviceAccess,
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Identity of a client certificate verified against `tls.client_ca_file`:
+/// the common name of the subject is the client id of the device and the
+/// email, a subject alternative name or else the `emailAddress` of the
+/// subject, that of its owner.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientCert {
+    pub email: String,
+    pub client_id: String,
+}
+
+impl ClientCert {
+    /// `None` for certificates without a common name or email.
+    pub fn from_der(der: &[u8]) -> Option<Self> {
+        use x509_parser::extensions::GeneralName;
+
+        let (_, cert) = x509_parser::parse_x509_certificate(der).ok()?;
+        let subject = cert.subject();
+        let client_id = subject.iter_common_name().next()?.as_str().ok()?;
+        let san_email = cert
+            .subject_alternative_name()
+            .ok()
+            .flatten()
+            .and_then(|san| {
+                san.value.general_names.iter().find_map(|name| match name {
+                    GeneralName::RFC822Name(email) => Some(*email),
+                    _ => None,
+                })
+            });
+        let email = match san_email {
+            Some(email) => email,
+            None => subject.iter_email().next()?.as_str().ok()?,
+        };
+
+        Some(Self {
+            email: email.to_string(),
+            client_id: client_id.to_string(),
+        })
+    }
+}
@@ -0,0 +1,90 @@
+use std::fmt;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::error::AppError;
+
+/// Row of `data_keys`, the key a user's stored content is encrypted with,
+/// itself encrypted with the master key `master_key_id`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct DataKeyEntity {
+    pub user_id: i64,
+    pub master_key_id: String,
+    pub wrapped_key: Vec<u8>,
+    pub created_at: DateTime<Utc>,
+    /// Last time the key was wrapped by a new master key.
+    pub rotated_at: Option<DateTime<Utc>>,
+}
+
+/// 256 bit server key that wraps the per-user data keys.
+#[derive(Clone)]
+pub struct MasterKey {
+    /// Start of the SHA-256 of the key, stored next to every key it wraps.
+    pub id: String,
+    pub key: [u8; 32],
+}
+
+impl MasterKey {
+    pub fn new(key: [u8; 32]) -> Self {
+        let id = Sha256::digest(key)[..8]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        Self { id, key }
+    }
+
+    /// Standard base64 of exactly 32 bytes, e.g. from `openssl rand -base64 32`.
+    pub fn from_base64(encoded: &str) -> Result<Self, AppError> {
+        let bytes = STANDARD
+            .decode(encoded.trim())
+            .map_err(|err| AppError::Encryption(format!("master key is not base64: {}", err)))?;
+        let key = bytes
+            .try_into()
+            .map_err(|_| AppError::Encryption("master key must be 32 bytes".to_string()))?;
+
+        Ok(Self::new(key))
+    }
+}
+
+/// Never prints the key itself.
+impl fmt::Debug for MasterKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MasterKey").field("id", &self.id).finish()
+    }
+}
+
+/// The master key new data keys are wrapped with, and the ones it replaced.
+/// Rotating adds the new key as `current` and moves the old one to `previous`
+/// until [`crate::rotate_master_key`] has rewrapped every data key.
+#[derive(Debug, Clone)]
+pub struct MasterKeys {
+    pub current: MasterKey,
+    pub previous: Vec<MasterKey>,
+}
+
+impl MasterKeys {
+    pub fn new(current: MasterKey) -> Self {
+        Self {
+            current,
+            previous: Vec::new(),
+        }
+    }
+
+    /// Keys that are already known, e.g. the current one listed again as
+    /// previous during a rotation, are skipped.
+    pub fn with_previous(mut self, key: MasterKey) -> Self {
+        if self.get(&key.id).is_none() {
+            self.previous.push(key);
+        }
+        self
+    }
+
+    pub fn get(&self, id: &str) -> Option<&MasterKey> {
+        std::iter::once(&self.current)
+            .chain(&self.previous)
+            .find(|key| key.id == id)
+    }
+}
@@ -0,0 +1,90 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    audit::AuditEntry,
+    clip::{ClipExport, QueuedClipEntity},
+    device::DeviceEntity,
+    invite::InviteEntity,
+    oidc::IdentityEntity,
+    retention::UserRetention,
+    session::SessionEntity,
+    user::UserDetails,
+};
+
+/// Everything stored about one user, handed out by the data export. Secrets
+/// such as password and TOTP hashes are left out.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UserExport {
+    pub exported_at: DateTime<Utc>,
+    pub profile: UserDetails,
+    pub sessions: Vec<SessionEntity>,
+    pub devices: Vec<DeviceEntity>,
+    pub identities: Vec<IdentityEntity>,
+    pub invites_created: Vec<InviteEntity>,
+    pub audit_log: Vec<AuditEntry>,
+    pub clips: Vec<ClipExport>,
+    pub clip_queue: Vec<QueuedClipEntity>,
+    pub retention: UserRetention,
+}
+
+impl UserExport {
+    /// Files of the export archive, one per section.
+    pub fn files(&self) -> Result<Vec<(&'static str, Vec<u8>)>, serde_json::Error> {
+        let audit_log = self
+            .audit_log
+            .iter()
+            .map(|entry| serde_json::to_string(entry).map(|line| line + "\n"))
+            .collect::<Result<String, _>>()?;
+
+        Ok(vec![
+            (
+                "export.json",
+                serde_json::to_vec_pretty(&serde_json::json!({
+                    "exported_at": self.exported_at,
+                    "user_id": self.profile.id,
+                }))?,
+            ),
+            ("profile.json", serde_json::to_vec_pretty(&self.profile)?),
+            ("sessions.json", serde_json::to_vec_pretty(&self.sessions)?),
+            ("devices.json", serde_json::to_vec_pretty(&self.devices)?),
+            (
+                "identities.json",
+                serde_json::to_vec_pretty(&self.identities)?,
+            ),
+            (
+                "invites_created.json",
+                serde_json::to_vec_pretty(&self.invites_created)?,
+            ),
+            ("audit_log.jsonl", audit_log.into_bytes()),
+            ("clips.json", serde_json::to_vec_pretty(&self.clips)?),
+            (
+                "clip_queue.json",
+                serde_json::to_vec_pretty(&self.clip_queue)?,
+            ),
+            (
+                "retention.json",
+                serde_json::to_vec_pretty(&self.retention)?,
+            ),
+        ])
+    }
+}
+
+/// Proof that the user is present, required for sensitive profile changes.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Reauthentication {
+    /// Left empty by users without a local password, see
+    /// `UserController::reauthenticate`.
+    #[serde(default)]
+    pub password: String,
+    /// TOTP or recovery code, required when two-factor authentication is on.
+    pub code: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ProfileUpdate {
+    pub name: Option<String>,
+    pub email: Option<String>,
+    #[serde(flatten)]
+    pub reauth: Reauthentication,
+}
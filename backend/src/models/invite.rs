@@ -0,0 +1,46 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::user::Role;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct InviteEntity {
+    pub id: i64,
+    pub role: Role,
+    pub max_uses: i64,
+    pub uses: i64,
+    pub created_by: Option<i64>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct InviteCreate {
+    #[serde(default)]
+    pub role: Role,
+    #[serde(default = "InviteCreate::default_max_uses")]
+    pub max_uses: i64,
+    pub expires_in_hours: Option<i64>,
+}
+
+/// Returned once on creation, the plain code is not stored.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct InviteCreated {
+    pub code: String,
+    #[serde(flatten)]
+    pub invite: InviteEntity,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct InviteRegister {
+    pub code: String,
+    pub email: String,
+    pub name: String,
+    pub password: String,
+}
+
+impl InviteCreate {
+    fn default_max_uses() -> i64 {
+        1
+    }
+}
@@ -1,4 +1,4 @@
-use chrono::{Duration, Utc};
+use chrono::{DateTime, Duration, Utc};
 use jsonwebtoken::{DecodingKey, EncodingKey};
 use serde::{Deserialize, Serialize};
 
@@ -11,12 +11,24 @@ pub struct Claims {
     pub id: i64,
     pub exp: i64,
     pub token_type: TokenType,
+    /// Session the token belongs to, absent only for mfa pending tokens.
+    #[serde(default)]
+    pub sid: Option<String>,
+    /// Client id of the device the token was issued to. Only tokens bound to a
+    /// device may open its WebSocket connection.
+    #[serde(default)]
+    pub cid: Option<String>,
+    /// Guest tokens are limited to the WebSocket connection of their device.
+    #[serde(default)]
+    pub guest: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum TokenType {
     AccessToken,
     RefreshToken,
+    /// Password was verified but the second factor is still missing.
+    MfaPending,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -25,23 +37,50 @@ pub struct TokeRefresh {
 }
 
 impl Claims {
-    pub fn from(user: &UserEntity, token_type: TokenType) -> Self {
+    /// `lifetime` of access and refresh tokens, mfa pending ones always last
+    /// five minutes.
+    pub fn from(
+        user: &UserEntity,
+        token_type: TokenType,
+        sid: Option<&str>,
+        lifetime: Duration,
+    ) -> Self {
         let iat = Utc::now();
-        let exp = iat + Duration::hours(24);
+        let exp = match token_type {
+            TokenType::MfaPending => iat + Duration::minutes(5),
+            _ => iat + lifetime,
+        };
         Self {
             email: user.email.clone(),
             role: user.role.clone(),
             id: user.id,
             exp: exp.timestamp(),
             token_type,
+            sid: sid.map(str::to_string),
+            cid: None,
+            guest: false,
         }
     }
+
+    pub fn bound_to(mut self, client_id: Option<&str>) -> Self {
+        self.cid = client_id.map(str::to_string);
+        self
+    }
+
+    /// Turns the claims into those of a guest device valid until `expires_at`.
+    pub fn guest(mut self, expires_at: DateTime<Utc>) -> Self {
+        self.exp = expires_at.timestamp();
+        self.guest = true;
+        self
+    }
 }
 
 #[derive(Clone)]
 pub struct Keys {
     pub encoding: EncodingKey,
     pub decoding: DecodingKey,
+    /// Of the access and refresh tokens signed with the keys.
+    pub token_lifetime: Duration,
 }
 
 impl Keys {
@@ -49,6 +88,12 @@ impl Keys {
         Self {
             encoding: EncodingKey::from_secret(secret.as_bytes()),
             decoding: DecodingKey::from_secret(secret.as_bytes()),
+            token_lifetime: Duration::hours(24),
         }
     }
+
+    pub fn with_token_lifetime(mut self, token_lifetime: Duration) -> Self {
+        self.token_lifetime = token_lifetime;
+        self
+    }
 }
@@ -0,0 +1,36 @@
+use super::user::Role;
+
+/// Settings of the LDAP bind provider. The user entry is looked up with
+/// `user_filter`, then bound to with the password given at login.
+#[derive(Debug, Clone)]
+pub struct LdapConfig {
+    /// `ldap://` or `ldaps://` url of the directory server.
+    pub url: String,
+    /// Account used for the lookup, the search is anonymous without one.
+    pub bind_dn: Option<String>,
+    pub bind_password: Option<String>,
+    pub base_dn: String,
+    /// `{login}` is replaced by the escaped login name.
+    pub user_filter: String,
+    pub email_attribute: String,
+    pub name_attribute: String,
+    /// Create a local user on the first login of a directory user.
+    pub provision: bool,
+    pub default_role: Role,
+}
+
+impl LdapConfig {
+    pub fn new(url: String, base_dn: String) -> Self {
+        Self {
+            url,
+            bind_dn: None,
+            bind_password: None,
+            base_dn,
+            user_filter: "(mail={login})".into(),
+            email_attribute: "mail".into(),
+            name_attribute: "cn".into(),
+            provision: false,
+            default_role: Role::user(),
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::user::Role;
+
+/// Relying party settings for a single OpenID Connect provider.
+#[derive(Debug, Clone)]
+pub struct OidcConfig {
+    /// Issuer identifier, discovery is fetched from
+    /// `{issuer_url}/.well-known/openid-configuration`.
+    pub issuer_url: String,
+    pub client_id: String,
+    /// Public clients rely on PKCE alone and leave this empty.
+    pub client_secret: Option<String>,
+    /// Must point at `/api/oidc/callback` of this server.
+    pub redirect_url: String,
+    pub scopes: Vec<String>,
+    /// Create a local user on the first login of an unknown identity.
+    pub provision: bool,
+    /// Role of provisioned users when none of `group_roles` matches.
+    pub default_role: Role,
+    /// Name of the ID token claim listing the groups of the user.
+    pub groups_claim: String,
+    /// Checked in order, the first group the user is member of decides the
+    /// role of provisioned users.
+    pub group_roles: Vec<(String, Role)>,
+    /// Also update the role from `group_roles` on every login, overwriting
+    /// roles set by admins. Users matching no group keep the role they have.
+    pub sync_roles: bool,
+    /// Link an unknown identity to the local user with the same email, only
+    /// done when the provider marks the email as verified.
+    pub link_by_email: bool,
+}
+
+impl OidcConfig {
+    pub fn new(issuer_url: String, client_id: String, redirect_url: String) -> Self {
+        Self {
+            issuer_url: issuer_url.trim_end_matches('/').to_string(),
+            client_id,
+            client_secret: None,
+            redirect_url,
+            scopes: vec!["openid".into(), "email".into(), "profile".into()],
+            provision: false,
+            default_role: Role::user(),
+            groups_claim: "groups".into(),
+            group_roles: Vec::new(),
+            sync_roles: false,
+            link_by_email: false,
+        }
+    }
+
+    pub fn role_for_groups(&self, claims: &IdTokenClaims) -> Option<Role> {
+        let groups = claims.groups(&self.groups_claim);
+        self.group_roles
+            .iter()
+            .find(|(group, _)| groups.contains(&group.as_str()))
+            .map(|(_, role)| role.clone())
+    }
+}
+
+/// The subset of the discovery document the login flow needs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OidcTokenResponse {
+    pub id_token: String,
+}
+
+/// Query string the provider redirects back with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OidcCallback {
+    pub code: Option<String>,
+    pub state: String,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    pub nonce: Option<String>,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    pub name: Option<String>,
+    /// Everything else, the groups claim name is configurable.
+    #[serde(flatten)]
+    pub extra: HashMap<String, Value>,
+}
+
+impl IdTokenClaims {
+    /// Accepts the claim both as a list of strings and as a single string.
+    pub fn groups(&self, claim: &str) -> Vec<&str> {
+        match self.extra.get(claim) {
+            Some(Value::Array(groups)) => groups.iter().filter_map(Value::as_str).collect(),
+            Some(Value::String(group)) => vec![group.as_str()],
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Row of `user_identities`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, sqlx::FromRow)]
+pub struct IdentityEntity {
+    pub provider: String,
+    pub subject: String,
+    pub user_id: i64,
+    pub email: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_login_at: DateTime<Utc>,
+}
@@ -0,0 +1,223 @@
+use std::{collections::HashMap, net::IpAddr};
+
+use chrono::{DateTime, Duration, Utc};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{error::AppError, utils::generate_token};
+
+use super::{device::DeviceRegistration, throttle::LoginThrottle};
+
+/// Pending pairings above which new ones are refused.
+const MAX_PENDING: usize = 1024;
+
+/// Handed to the device asking to be paired. It shows `code`, as digits or as
+/// a QR code, and polls for its tokens with `pairing_id`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PairingStarted {
+    pub pairing_id: String,
+    pub code: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PairingClaim {
+    pub pairing_id: String,
+}
+
+/// Sent once with the code alone, which shows the device behind it, then
+/// again with the `client_id` of that device to let it in.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PairingApprove {
+    pub code: String,
+    pub client_id: Option<String>,
+}
+
+/// The device behind a code, `confirmed` once it is let in on the account of
+/// the approving user.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PairingDevice {
+    pub client_id: String,
+    pub name: String,
+    pub platform: Option<String>,
+    pub confirmed: bool,
+}
+
+impl PairingDevice {
+    pub fn new(device: DeviceRegistration, confirmed: bool) -> Self {
+        Self {
+            client_id: device.id,
+            name: device.name,
+            platform: device.platform,
+            confirmed,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Pairing {
+    code: String,
+    device: DeviceRegistration,
+    expires_at: DateTime<Utc>,
+    /// The user last shown the device behind the code.
+    offered_to: Option<i64>,
+    /// Set once that user confirmed the device.
+    approved_by: Option<i64>,
+}
+
+/// In-memory pairings keyed by their `pairing_id`. Codes are short, so they
+/// expire quickly and each pairing is removed as soon as its tokens are
+/// claimed.
+#[derive(Debug)]
+pub struct PairingRegistry {
+    lifetime: Duration,
+    pairings: HashMap<String, Pairing>,
+    /// Counts every start against its IP, separately from the logins. Starts
+    /// without one come from no user either, so only [`MAX_PENDING`] bounds
+    /// them rather than one budget shared by every such client.
+    throttle: LoginThrottle,
+    /// Counts wrong codes against the approving user and their IP.
+    approvals: LoginThrottle,
+}
+
+impl Default for PairingRegistry {
+    fn default() -> Self {
+        Self::new(Duration::minutes(2))
+    }
+}
+
+impl PairingRegistry {
+    pub fn new(lifetime: Duration) -> Self {
+        Self {
+            lifetime,
+            pairings: HashMap::new(),
+            throttle: LoginThrottle::default(),
+            approvals: LoginThrottle::default(),
+        }
+    }
+
+    pub fn start(
+        &mut self,
+        device: DeviceRegistration,
+        ip: Option<IpAddr>,
+    ) -> Result<PairingStarted, AppError> {
+        if let Some(ip) = ip {
+            self.throttle.check_ip(ip)?;
+            self.throttle.record_ip_failure(ip);
+        }
+
+        let now = Utc::now();
+        self.pairings.retain(|_, pairing| pairing.expires_at > now);
+        if self.pairings.len() >= MAX_PENDING {
+            return Err(AppError::TooManyAttempts(self.lifetime.num_seconds()));
+        }
+
+        // codes are unique among the pending pairings so that a code always
+        // approves the device showing it
+        let code = loop {
+            let code = format!("{:06}", rand::thread_rng().gen_range(0..1_000_000));
+            if !self.pairings.values().any(|pairing| pairing.code == code) {
+                break code;
+            }
+        };
+        let pairing_id = generate_token(32);
+        let expires_at = now + self.lifetime;
+
+        self.pairings.insert(
+            pairing_id.clone(),
+            Pairing {
+                code: code.clone(),
+                device,
+                expires_at,
+                offered_to: None,
+                approved_by: None,
+            },
+        );
+
+        Ok(PairingStarted {
+            pairing_id,
+            code,
+            expires_at,
+        })
+    }
+
+    /// Returns the device of the pending pairing showing `code` so that the
+    /// user can check it is theirs. Only the user shown the device last can
+    /// confirm it.
+    pub fn offer(
+        &mut self,
+        code: &str,
+        user_id: i64,
+        ip: Option<IpAddr>,
+    ) -> Result<DeviceRegistration, AppError> {
+        let pairing = self.pending(code, user_id, ip, |_| true)?;
+        pairing.offered_to = Some(user_id);
+        Ok(pairing.device.clone())
+    }
+
+    /// Binds the pending pairing showing `code` to the user once they confirm
+    /// the device they were shown, a code can only be approved once.
+    pub fn approve(
+        &mut self,
+        code: &str,
+        client_id: &str,
+        user_id: i64,
+        ip: Option<IpAddr>,
+    ) -> Result<DeviceRegistration, AppError> {
+        let pairing = self.pending(code, user_id, ip, |pairing| {
+            pairing.offered_to == Some(user_id) && pairing.device.id == client_id
+        })?;
+        pairing.approved_by = Some(user_id);
+        Ok(pairing.device.clone())
+    }
+
+    /// The unapproved pairing showing `code`. Misses are throttled per
+    /// approving user and their IP, they do not touch other users' pairings.
+    fn pending(
+        &mut self,
+        code: &str,
+        user_id: i64,
+        ip: Option<IpAddr>,
+        filter: impl Fn(&Pairing) -> bool,
+    ) -> Result<&mut Pairing, AppError> {
+        let approver = user_id.to_string();
+        self.approvals.check(&approver, ip)?;
+
+        let now = Utc::now();
+        let pairing = self.pairings.values_mut().find(|pairing| {
+            pairing.code == code
+                && pairing.approved_by.is_none()
+                && pairing.expires_at > now
+                && filter(pairing)
+        });
+        match pairing {
+            Some(pairing) => Ok(pairing),
+            None => {
+                self.approvals.record_failure(&approver, ip);
+                Err(AppError::InvalidPairingCode)
+            }
+        }
+    }
+
+    /// Returns the approving user and the device once the pairing has been
+    /// approved, `None` while it is still pending.
+    pub fn claim(
+        &mut self,
+        pairing_id: &str,
+    ) -> Result<Option<(i64, DeviceRegistration)>, AppError> {
+        let now = Utc::now();
+        let pairing = self
+            .pairings
+            .get(pairing_id)
+            .filter(|pairing| pairing.expires_at > now)
+            .ok_or(AppError::InvalidPairingCode)?;
+
+        match pairing.approved_by {
+            Some(user_id) => {
+                let pairing = self.pairings.remove(pairing_id);
+                Ok(pairing.map(|pairing| (user_id, pairing.device)))
+            }
+            None => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use std::{collections::HashSet, fs, io, path::Path};
+
+use argon2::{Config, Variant, Version};
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+
+use crate::error::AppError;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PasswordChange {
+    /// Left empty by users without a local password, who set their first one.
+    #[serde(default)]
+    pub current_password: String,
+    pub new_password: String,
+    /// TOTP or recovery code, required when two-factor authentication is on.
+    pub code: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct PasswordResetRequest {
+    pub expires_in_minutes: Option<i64>,
+}
+
+/// Handed to the admin once, only the token hash is stored.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PasswordResetLink {
+    pub token: String,
+    pub path: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PasswordReset {
+    pub token: String,
+    pub new_password: String,
+}
+
+/// Argon2 parameters used for new hashes. Stored hashes created with other
+/// parameters are re-hashed on the next successful login.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HashParams {
+    pub variant: Variant,
+    /// Memory in KiB.
+    pub mem_cost: u32,
+    pub time_cost: u32,
+    pub lanes: u32,
+}
+
+impl Default for HashParams {
+    /// Matches `argon2::Config::default()`, which every existing hash used.
+    fn default() -> Self {
+        let config = Config::default();
+        Self {
+            variant: config.variant,
+            mem_cost: config.mem_cost,
+            time_cost: config.time_cost,
+            lanes: config.lanes,
+        }
+    }
+}
+
+impl HashParams {
+    pub fn config(&self) -> Config<'static> {
+        Config {
+            variant: self.variant,
+            version: Version::Version13,
+            mem_cost: self.mem_cost,
+            time_cost: self.time_cost,
+            lanes: self.lanes,
+            ..Config::default()
+        }
+    }
+
+    pub fn hash(&self, password: &[u8]) -> Result<String, AppError> {
+        let salt: [u8; 32] = rand::thread_rng().gen();
+        argon2::hash_encoded(password, &salt, &self.config()).map_err(|err| {
+            tracing::error!("Error hashing password: {:?}", err);
+            AppError::InternalServerError
+        })
+    }
+
+    /// Whether `encoded` (`$argon2id$v=19$m=4096,t=3,p=1$salt$hash`) was made
+    /// with different parameters than these.
+    pub fn needs_rehash(&self, encoded: &str) -> bool {
+        let parts = encoded.split('$').collect::<Vec<_>>();
+        let [_, variant, version, params, _, _] = parts.as_slice() else {
+            return true;
+        };
+
+        let expected = format!("m={},t={},p={}", self.mem_cost, self.time_cost, self.lanes);
+
+        *variant != self.variant.as_lowercase_str()
+            || *version != format!("v={}", Version::Version13.as_u32())
+            || *params != expected
+    }
+}
+
+/// Rules every new password has to satisfy.
+#[derive(Debug, Clone)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub max_length: usize,
+    /// Upper-case SHA-1 hex digests of known breached passwords.
+    pub breached: HashSet<String>,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 8,
+            max_length: 256,
+            breached: HashSet::new(),
+        }
+    }
+}
+
+impl PasswordPolicy {
+    /// Loads a breached password list, one entry per line. Lines may either be
+    /// plain passwords or SHA-1 digests in the `HASH:count` format of the
+    /// Pwned Passwords downloads.
+    pub fn load_breached_list(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let content = fs::read_to_string(path)?;
+
+        for line in content.lines() {
+            let line = line.trim_end_matches('\r');
+            if line.is_empty() {
+                continue;
+            }
+            let hash = line.split(':').next().unwrap_or_default();
+            if hash.len() == 40 && hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                self.breached.insert(hash.to_ascii_uppercase());
+            } else {
+                self.breached.insert(sha1_hex(line));
+            }
+        }
+
+        tracing::debug!("Loaded {} breached passwords", self.breached.len());
+
+        Ok(())
+    }
+
+    pub fn validate(&self, password: &str, email: &str) -> Result<(), AppError> {
+        let length = password.chars().count();
+
+        if length < self.min_length {
+            return Err(AppError::WeakPassword(format!(
+                "password must be at least {} characters",
+                self.min_length
+            )));
+        }
+        if length > self.max_length {
+            return Err(AppError::WeakPassword(format!(
+                "password must be at most {} characters",
+                self.max_length
+            )));
+        }
+        if password.eq_ignore_ascii_case(email) {
+            return Err(AppError::WeakPassword(
+                "password must not be the email".to_string(),
+            ));
+        }
+        if self.breached.contains(&sha1_hex(password)) {
+            return Err(AppError::WeakPassword(
+                "password appears in a list of breached passwords".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+fn sha1_hex(value: &str) -> String {
+    Sha1::digest(value.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect()
+}
@@ -0,0 +1,49 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+/// How long rows that stopped doing anything, like expired sessions or used
+/// reset links, and the clip history and queue are kept before the cleanup
+/// jobs delete them, and how often the jobs run.
+#[derive(Debug, Clone)]
+pub struct RetentionPolicy {
+    /// Used for every user without a retention of their own.
+    pub days: i64,
+    pub purge_interval: Duration,
+    pub vacuum_interval: Duration,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            days: 30,
+            purge_interval: Duration::hours(1),
+            vacuum_interval: Duration::days(1),
+        }
+    }
+}
+
+/// Body of `PUT /api/user/retention`, `None` goes back to the server wide
+/// retention.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct UserRetention {
+    pub days: Option<i64>,
+}
+
+impl UserRetention {
+    pub const MAX_DAYS: i64 = 3650;
+}
+
+/// State of one background job as shown to admins.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct JobStatus {
+    pub name: String,
+    pub interval_seconds: i64,
+    pub running: bool,
+    pub runs: u64,
+    pub last_started_at: Option<DateTime<Utc>>,
+    pub last_finished_at: Option<DateTime<Utc>>,
+    /// Rows deleted by the last successful run.
+    pub last_removed: Option<u64>,
+    pub last_error: Option<String>,
+    pub next_run_at: Option<DateTime<Utc>>,
+}
@@ -0,0 +1,28 @@
+use serde::{Deserialize, Serialize};
+
+use super::user::Role;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+pub enum Permission {
+    ManageUsers,
+    ManageRoles,
+    ViewAuditLog,
+    ReadMetrics,
+    ManageBackups,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct RoleEntity {
+    pub name: Role,
+    pub description: String,
+    pub permissions: Vec<Permission>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq, Eq)]
+pub struct RoleUpsert {
+    #[serde(default)]
+    pub description: String,
+    pub permissions: Vec<Permission>,
+}
@@ -1,24 +1,78 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{borrow::Cow, collections::HashMap, sync::Arc};
 
-use axum::extract::ws::Message;
+use axum::extract::ws::{close_code, CloseFrame, Message};
 use tokio::sync::{mpsc::UnboundedSender, Mutex};
 
-use super::{jwt::Keys, user::Client};
+use crate::{
+    repository::Database,
+    service::{
+        auth_provider::{AuthContext, AuthProviders},
+        oidc::OidcClient,
+        scheduler::Scheduler,
+    },
+};
+
+use super::{
+    encryption::MasterKeys,
+    jwt::Keys,
+    pairing::PairingRegistry,
+    password::{HashParams, PasswordPolicy},
+    retention::RetentionPolicy,
+    throttle::LoginThrottle,
+    user::{Client, UserCreate},
+};
 
 pub struct AppState {
-    pub pool: sqlx::SqlitePool,
+    pub db: Database,
     pub keys: Keys,
     pub users: HashMap<i64, HashMap<String, (Client, UnboundedSender<Message>)>>,
+    pub login_throttle: LoginThrottle,
+    pub pairings: PairingRegistry,
+    /// Verified against when the login email is unknown, so that unknown users
+    /// cost as much time as wrong passwords.
+    pub dummy_password_hash: String,
+    pub password_policy: PasswordPolicy,
+    pub hash_params: HashParams,
+    pub auth_providers: AuthProviders,
+    /// Shared so that requests to the provider run without holding the state lock.
+    pub oidc: Option<Arc<OidcClient>>,
+    /// Stored content is only encrypted when a master key is configured.
+    pub master_keys: Option<MasterKeys>,
+    pub retention: RetentionPolicy,
+    pub scheduler: Scheduler,
 }
 
 pub type AppStateType = Arc<Mutex<AppState>>;
 
 impl AppState {
-    pub fn new(pool: sqlx::SqlitePool, secret: &str) -> Self {
+    pub fn new(db: Database, secret: &str) -> Self {
         Self {
-            pool,
+            db,
             keys: Keys::new(secret),
             users: HashMap::new(),
+            login_throttle: LoginThrottle::default(),
+            pairings: PairingRegistry::default(),
+            dummy_password_hash: UserCreate::dummy_password_hash(&HashParams::default()),
+            password_policy: PasswordPolicy::default(),
+            hash_params: HashParams::default(),
+            auth_providers: AuthProviders::default(),
+            oidc: None,
+            master_keys: None,
+            retention: RetentionPolicy::default(),
+            scheduler: Scheduler::default(),
+        }
+    }
+
+    pub fn set_hash_params(&mut self, params: HashParams) {
+        self.dummy_password_hash = UserCreate::dummy_password_hash(&params);
+        self.hash_params = params;
+    }
+
+    pub fn auth_context(&self) -> AuthContext {
+        AuthContext {
+            db: self.db.clone(),
+            hash_params: self.hash_params.clone(),
+            dummy_password_hash: self.dummy_password_hash.clone(),
         }
     }
 
@@ -54,4 +108,53 @@ impl AppState {
             },
         );
     }
+
+    /// Asks every live connection of the user to close, the socket tasks remove
+    /// the clients from `users` once the close frame has been sent.
+    pub async fn disconnect_user(&mut self, uid: &i64, reason: &'static str) {
+        if let Some(user) = self.users.get(uid) {
+            for (client, tx) in user.values() {
+                Self::close(client, tx, close_code::POLICY, reason);
+            }
+        }
+    }
+
+    /// Same as `disconnect_user` for a single client.
+    pub async fn disconnect_client(&mut self, uid: &i64, client_id: &str, reason: &'static str) {
+        if let Some((client, tx)) = self.users.get(uid).and_then(|user| user.get(client_id)) {
+            Self::close(client, tx, close_code::POLICY, reason);
+        }
+    }
+
+    /// Same as `disconnect_user` except for the client `keep`.
+    pub async fn disconnect_other_clients(
+        &mut self,
+        uid: &i64,
+        keep: Option<&str>,
+        reason: &'static str,
+    ) {
+        if let Some(user) = self.users.get(uid) {
+            for (client_id, (client, tx)) in user {
+                if Some(client_id.as_str()) != keep {
+                    Self::close(client, tx, close_code::POLICY, reason);
+                }
+            }
+        }
+    }
+
+    /// Closes every connection with "going away", clients may reconnect once
+    /// the server is back.
+    pub async fn disconnect_all(&mut self, reason: &'static str) {
+        for (client, tx) in self.users.values().flat_map(HashMap::values) {
+            Self::close(client, tx, close_code::AWAY, reason);
+        }
+    }
+
+    fn close(client: &Client, tx: &UnboundedSender<Message>, code: u16, reason: &'static str) {
+        tracing::debug!("Disconnecting client {:?}: {}", client, reason);
+        let _ = tx.send(Message::Close(Some(CloseFrame {
+            code,
+            reason: Cow::Borrowed(reason),
+        })));
+    }
 }
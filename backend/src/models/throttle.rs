@@ -0,0 +1,244 @@
+use std::{collections::HashMap, hash::Hash, net::IpAddr};
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::error::AppError;
+
+/// Number of tracked keys above which stale records are pruned on insert.
+const PRUNE_THRESHOLD: usize = 1024;
+/// Keys tracked at most, e.g. while random emails are sprayed from many IPs.
+/// The record with the oldest failure makes room for a new one.
+pub(crate) const MAX_RECORDS: usize = 64 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct ThrottlePolicy {
+    /// Failures allowed before any delay is imposed.
+    pub free_attempts: u32,
+    /// Failures after which the key is locked for `lockout_duration`.
+    pub lockout_threshold: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub lockout_duration: Duration,
+    /// Records without a new failure for this long are forgotten.
+    pub reset_after: Duration,
+}
+
+impl ThrottlePolicy {
+    pub fn account() -> Self {
+        Self {
+            free_attempts: 3,
+            lockout_threshold: 10,
+            base_delay: Duration::seconds(1),
+            max_delay: Duration::minutes(5),
+            lockout_duration: Duration::minutes(15),
+            reset_after: Duration::hours(1),
+        }
+    }
+
+    pub fn ip() -> Self {
+        Self {
+            free_attempts: 10,
+            lockout_threshold: 50,
+            ..Self::account()
+        }
+    }
+
+    fn delay(&self, failures: u32) -> Option<Duration> {
+        if failures >= self.lockout_threshold {
+            return Some(self.lockout_duration);
+        }
+        if failures < self.free_attempts {
+            return None;
+        }
+        let exponent = (failures - self.free_attempts).min(20);
+        let delay = self.base_delay * 2i32.pow(exponent);
+        Some(delay.min(self.max_delay))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct FailureRecord {
+    failures: u32,
+    last_failure: DateTime<Utc>,
+    blocked_until: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug)]
+struct Tracker<K> {
+    policy: ThrottlePolicy,
+    records: HashMap<K, FailureRecord>,
+    /// Number of keys at which the next new key prunes stale records.
+    prune_at: usize,
+}
+
+impl<K: Eq + Hash + Clone> Tracker<K> {
+    fn new(policy: ThrottlePolicy) -> Self {
+        Self {
+            policy,
+            records: HashMap::new(),
+            prune_at: PRUNE_THRESHOLD,
+        }
+    }
+
+    fn retry_after(&self, key: &K, now: DateTime<Utc>) -> Option<Duration> {
+        self.records
+            .get(key)
+            .and_then(|record| record.blocked_until)
+            .filter(|until| *until > now)
+            .map(|until| until - now)
+    }
+
+    fn record_failure(&mut self, key: K, now: DateTime<Utc>) {
+        if !self.records.contains_key(&key) {
+            if self.records.len() >= self.prune_at {
+                let reset_after = self.policy.reset_after;
+                self.records
+                    .retain(|_, record| now - record.last_failure < reset_after);
+                // the fresh records left would only be scanned again on the next key
+                self.prune_at = (self.records.len() * 2).clamp(PRUNE_THRESHOLD, MAX_RECORDS);
+            }
+            if self.records.len() >= MAX_RECORDS {
+                let oldest = self
+                    .records
+                    .iter()
+                    .min_by_key(|(_, record)| record.last_failure)
+                    .map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    self.records.remove(&oldest);
+                }
+            }
+        }
+
+        let record = self.records.entry(key).or_insert(FailureRecord {
+            failures: 0,
+            last_failure: now,
+            blocked_until: None,
+        });
+        if now - record.last_failure >= self.policy.reset_after {
+            record.failures = 0;
+        }
+        record.failures += 1;
+        record.last_failure = now;
+        record.blocked_until = self.policy.delay(record.failures).map(|delay| now + delay);
+    }
+
+    /// Takes back one failure, the block is recomputed as if it never happened.
+    fn release(&mut self, key: &K) {
+        match self.records.get_mut(key) {
+            Some(record) if record.failures > 1 => {
+                record.failures -= 1;
+                record.blocked_until = self
+                    .policy
+                    .delay(record.failures)
+                    .map(|delay| record.last_failure + delay);
+            }
+            Some(_) => {
+                self.records.remove(key);
+            }
+            None => {}
+        }
+    }
+
+    fn clear(&mut self, key: &K) {
+        self.records.remove(key);
+    }
+}
+
+/// In-memory failed login tracking per account and per client IP, imposing an
+/// exponential backoff after a few failures and a lockout after many.
+///
+/// Accounts are keyed by the submitted email, whether or not such a user
+/// exists, so lockouts do not reveal which accounts are real. Each side keeps
+/// at most [`MAX_RECORDS`] keys.
+#[derive(Debug)]
+pub struct LoginThrottle {
+    accounts: Tracker<String>,
+    ips: Tracker<IpAddr>,
+}
+
+impl Default for LoginThrottle {
+    fn default() -> Self {
+        Self::new(ThrottlePolicy::account(), ThrottlePolicy::ip())
+    }
+}
+
+impl LoginThrottle {
+    pub fn new(account: ThrottlePolicy, ip: ThrottlePolicy) -> Self {
+        Self {
+            accounts: Tracker::new(account),
+            ips: Tracker::new(ip),
+        }
+    }
+
+    fn account_key(email: &str) -> String {
+        email.trim().to_lowercase()
+    }
+
+    /// Rejects the attempt with [`AppError::TooManyAttempts`] while either the
+    /// account or the IP is backing off or locked.
+    pub fn check(&self, email: &str, ip: Option<IpAddr>) -> Result<(), AppError> {
+        let now = Utc::now();
+        let account = self.accounts.retry_after(&Self::account_key(email), now);
+        let ip = ip.and_then(|ip| self.ips.retry_after(&ip, now));
+        Self::reject_for(account.max(ip))
+    }
+
+    /// Same as [`Self::check`] for requests without an account, only the IP is
+    /// tracked.
+    pub fn check_ip(&self, ip: IpAddr) -> Result<(), AppError> {
+        Self::reject_for(self.ips.retry_after(&ip, Utc::now()))
+    }
+
+    fn reject_for(retry_after: Option<Duration>) -> Result<(), AppError> {
+        match retry_after {
+            Some(retry_after) => Err(AppError::TooManyAttempts(
+                (retry_after.num_milliseconds() + 999) / 1000,
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks like [`Self::check`] and counts the attempt as failed right away,
+    /// so that parallel attempts cannot all pass the check before the first
+    /// failure is recorded. [`Self::release`] takes it back once the
+    /// credentials turn out to be right.
+    pub fn reserve(&mut self, email: &str, ip: Option<IpAddr>) -> Result<(), AppError> {
+        self.check(email, ip)?;
+        self.record_failure(email, ip);
+        Ok(())
+    }
+
+    pub fn release(&mut self, email: &str, ip: Option<IpAddr>) {
+        self.accounts.release(&Self::account_key(email));
+        if let Some(ip) = ip {
+            self.ips.release(&ip);
+        }
+    }
+
+    pub fn record_failure(&mut self, email: &str, ip: Option<IpAddr>) {
+        let now = Utc::now();
+        self.accounts.record_failure(Self::account_key(email), now);
+        if let Some(ip) = ip {
+            self.ips.record_failure(ip, now);
+        }
+    }
+
+    pub fn record_ip_failure(&mut self, ip: IpAddr) {
+        self.ips.record_failure(ip, Utc::now());
+    }
+
+    /// Forgets the account's failures after a successful login. The IP record
+    /// is kept so a valid account cannot be used to reset it.
+    pub fn record_success(&mut self, email: &str) {
+        self.accounts.clear(&Self::account_key(email));
+    }
+
+    pub fn unlock_account(&mut self, email: &str) {
+        self.accounts.clear(&Self::account_key(email));
+    }
+
+    #[cfg(test)]
+    pub(crate) fn tracked_accounts(&self) -> usize {
+        self.accounts.records.len()
+    }
+}
@@ -0,0 +1,95 @@
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+use crate::{error::AppError, utils::hash_token};
+
+pub const TOTP_ISSUER: &str = "scytale";
+const TOTP_DIGITS: usize = 6;
+const TOTP_STEP: u64 = 30;
+const RECOVERY_CODE_COUNT: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct TotpEntity {
+    pub user_id: i64,
+    pub secret: String,
+    pub enabled: bool,
+    pub last_used_step: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TotpCode {
+    pub code: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TotpEnrollResponse {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RecoveryCodesResponse {
+    pub recovery_codes: Vec<String>,
+}
+
+impl TotpEntity {
+    /// Fresh base32 encoded secret, 160 bits as recommended by RFC 4226.
+    pub fn generate_secret() -> String {
+        match Secret::generate_secret().to_encoded() {
+            Secret::Encoded(secret) => secret,
+            Secret::Raw(_) => unreachable!("to_encoded always returns an encoded secret"),
+        }
+    }
+
+    pub fn totp(&self, account_name: &str) -> Result<TOTP, AppError> {
+        let secret = Secret::Encoded(self.secret.clone())
+            .to_bytes()
+            .map_err(|_| AppError::InternalServerError)?;
+
+        TOTP::new(
+            Algorithm::SHA1,
+            TOTP_DIGITS,
+            0,
+            TOTP_STEP,
+            secret,
+            Some(TOTP_ISSUER.to_string()),
+            account_name.replace(':', ""),
+        )
+        .map_err(|err| {
+            tracing::error!("Error building totp: {:?}", err);
+            AppError::InternalServerError
+        })
+    }
+
+    /// Returns the time step `code` belongs to if it is valid at `now`, allowing
+    /// one step of clock drift and refusing steps that were already used.
+    pub fn verify(&self, code: &str, now: u64) -> Result<Option<i64>, AppError> {
+        let totp = self.totp("")?;
+        let current = now / TOTP_STEP;
+
+        let step = [current.saturating_sub(1), current, current + 1]
+            .into_iter()
+            .filter(|step| *step as i64 > self.last_used_step)
+            .find(|step| totp.check(code.trim(), step * TOTP_STEP));
+
+        Ok(step.map(|step| step as i64))
+    }
+}
+
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code: String = rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(10)
+                .map(|c| char::from(c).to_ascii_lowercase())
+                .collect();
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect()
+}
+
+pub fn hash_recovery_code(code: &str) -> String {
+    hash_token(&code.trim().replace('-', "").to_ascii_lowercase())
+}
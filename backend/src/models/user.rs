@@ -1,7 +1,7 @@
 use axum::extract::ws::Message;
 use std::{collections::HashMap, fmt};
 
-use argon2::Config;
+use chrono::{DateTime, Utc};
 use jsonwebtoken::{DecodingKey, EncodingKey};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
@@ -9,9 +9,9 @@ use tokio::sync::mpsc::{self, UnboundedSender};
 
 use crate::error::AppError;
 
-#[derive(
-    Debug, Serialize, Deserialize, Default, Clone, PartialEq, Eq, sqlx::FromRow, sqlx::Type,
-)]
+use super::{device::DeviceAccess, password::HashParams};
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq, Eq, sqlx::FromRow)]
 pub struct UserEntity {
     pub id: i64,
     pub email: String,
@@ -29,17 +29,98 @@ pub struct UserCreate {
     pub role: Role,
 }
 
+/// A user as shown to admins.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct UserDetails {
+    pub id: i64,
+    pub email: String,
+    pub name: String,
+    pub role: Role,
+    pub auth_provider: Option<String>,
+    pub totp_enabled: bool,
+    pub disabled_at: Option<DateTime<Utc>>,
+}
+
+/// Admin update, absent fields are left unchanged.
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq, Eq)]
+pub struct UserUpdate {
+    pub email: Option<String>,
+    pub name: Option<String>,
+    pub role: Option<Role>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct UserListQuery {
+    /// Case-insensitive substring of the email or name.
+    pub q: Option<String>,
+    pub role: Option<Role>,
+    pub disabled: Option<bool>,
+    #[serde(default = "UserListQuery::default_page")]
+    pub page: i64,
+    #[serde(default = "UserListQuery::default_per_page")]
+    pub per_page: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UserPage {
+    pub users: Vec<UserDetails>,
+    pub total: i64,
+    pub page: i64,
+    pub per_page: i64,
+}
+
+impl UserListQuery {
+    pub const MAX_PER_PAGE: i64 = 500;
+
+    fn default_page() -> i64 {
+        1
+    }
+
+    fn default_per_page() -> i64 {
+        50
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq, Eq)]
 pub struct UserLogin {
     pub email: String,
     pub password: String,
 }
 
-#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq, Eq, sqlx::Type)]
-pub enum Role {
-    ADMIN,
-    #[default]
-    USER,
+/// Name of a row in the `roles` table, the permissions it grants live in
+/// `role_permissions`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash, sqlx::Type)]
+#[serde(transparent)]
+#[sqlx(transparent)]
+pub struct Role(pub String);
+
+impl Role {
+    pub const ADMIN: &'static str = "ADMIN";
+    pub const USER: &'static str = "USER";
+
+    pub fn new(name: impl Into<String>) -> Self {
+        Self(name.into())
+    }
+
+    pub fn admin() -> Self {
+        Self::new(Self::ADMIN)
+    }
+
+    pub fn user() -> Self {
+        Self::new(Self::USER)
+    }
+}
+
+impl Default for Role {
+    fn default() -> Self {
+        Self::user()
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
@@ -48,6 +129,11 @@ pub struct Client {
     pub user_id: i64,
     pub name: String,
     pub pub_key: Option<String>,
+    pub access: DeviceAccess,
+    /// Guests are disconnected at this time.
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Exchanges typed frames rather than raw clipboard text.
+    pub typed: bool,
 }
 
 impl Client {
@@ -57,16 +143,16 @@ impl Client {
             user_id,
             name,
             pub_key,
+            access: DeviceAccess::Full,
+            expires_at: None,
+            typed: false,
         }
     }
 }
 
 impl From<String> for Role {
     fn from(role: String) -> Self {
-        match role.as_str() {
-            "ADMIN" => Self::ADMIN,
-            _ => Self::USER,
-        }
+        Self(role)
     }
 }
 
@@ -83,18 +169,28 @@ impl From<UserCreate> for UserEntity {
 }
 
 impl UserCreate {
-    pub fn hash_password(&mut self) -> Result<(), AppError> {
-        let salt: [u8; 32] = rand::thread_rng().gen();
-        let config = Config::default();
+    /// Hash of a random password nobody knows, see `AppState::dummy_password_hash`.
+    pub fn dummy_password_hash(params: &HashParams) -> String {
+        let password: [u8; 32] = rand::thread_rng().gen();
+        params
+            .hash(&password)
+            .expect("argon2 parameters were validated")
+    }
 
-        self.password = argon2::hash_encoded(self.password.as_bytes(), &salt, &config)
-            .map_err(|e| AppError::InternalServerError)?;
+    pub fn hash_password(&mut self, params: &HashParams) -> Result<(), AppError> {
+        self.password = params.hash(self.password.as_bytes())?;
 
         Ok(())
     }
 }
 
 impl UserEntity {
+    /// Users provisioned through an identity provider have no local password
+    /// until they set one.
+    pub fn has_password(&self) -> bool {
+        !self.password.is_empty()
+    }
+
     pub fn verify_password(&self, password: &[u8]) -> Result<bool, AppError> {
         argon2::verify_encoded(&self.password, password).map_err(|e| AppError::WrongCredential)
     }
@@ -1,8 +1,55 @@
 use serde::{Deserialize, Serialize};
 
+use super::{
+    device::DeviceRegistration,
+    pairing::{PairingApprove, PairingDevice},
+};
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct WsParam {
     pub id: String,
     pub name: String,
+    /// Left out by devices authenticating with a client certificate.
+    #[serde(default)]
     pub token: String,
+    /// Recorded in the device registry, e.g. `android` or `linux`.
+    pub platform: Option<String>,
+    pub app_version: Option<String>,
+    /// Set by clients that send and receive [`WsCommand`] and [`WsEvent`]
+    /// frames. Other clients exchange raw clipboard text.
+    #[serde(default)]
+    pub typed: bool,
+}
+
+impl WsParam {
+    pub fn device(&self) -> DeviceRegistration {
+        DeviceRegistration {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            platform: self.platform.clone(),
+            app_version: self.app_version.clone(),
+        }
+    }
+}
+
+/// Sent by typed clients, which wrap their clips so that no clip is ever
+/// mistaken for a command.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WsCommand {
+    Clip {
+        text: String,
+    },
+    /// Same as `POST /api/pair/approve`, answered with a `pairing` event.
+    PairApprove(PairingApprove),
+}
+
+/// Sent by the server itself rather than relayed from another client. Typed
+/// clients get relayed clips as `clip` events too.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WsEvent {
+    Error { error: String },
+    Clip { text: String },
+    Pairing(PairingDevice),
 }
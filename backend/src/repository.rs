@@ -0,0 +1,560 @@
+//! Storage backends. Every table is reached through one of the repository
+//! traits, the service functions only see a [`Database`] and never SQL.
+
+use std::{any::Any, net::IpAddr, path::Path, sync::Arc};
+
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::migrate::Migrator;
+
+use crate::{
+    error::AppError,
+    models::{
+        audit::{AuditEntry, AuditEvent, AuditQuery},
+        clip::{ClipEntity, QueuedClipEntity},
+        device::{DeviceAccess, DeviceEntity, DeviceRegistration},
+        encryption::DataKeyEntity,
+        invite::InviteEntity,
+        oidc::IdentityEntity,
+        role::{Permission, RoleEntity, RoleUpsert},
+        session::SessionEntity,
+        totp::TotpEntity,
+        user::{Role, UserCreate, UserDetails, UserEntity, UserListQuery},
+    },
+};
+
+pub mod memory;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+pub mod sqlite;
+
+pub use memory::MemoryRepository;
+#[cfg(feature = "postgres")]
+pub use postgres::PostgresRepository;
+pub use sqlite::SqliteRepository;
+
+/// A complete storage backend, shared by the state and every request.
+pub type Database = Arc<dyn Repository>;
+
+/// Picks the backend from the scheme of `db_url`, creates the database if it
+/// does not exist and runs the migrations of that backend. `memory:` keeps
+/// everything in process memory without any SQL.
+pub async fn connect(db_url: &str) -> Result<Database, sqlx::Error> {
+    match scheme(db_url) {
+        "memory" => Ok(Arc::new(MemoryRepository::new())),
+        "sqlite" => Ok(Arc::new(SqliteRepository::connect(db_url).await?)),
+        #[cfg(feature = "postgres")]
+        "postgres" | "postgresql" => Ok(Arc::new(PostgresRepository::connect(db_url).await?)),
+        scheme => Err(sqlx::Error::Configuration(
+            format!("unsupported database scheme {:?}", scheme).into(),
+        )),
+    }
+}
+
+/// Like [`connect`] for the offline commands, but neither creates nor migrates
+/// the database: it has to exist and be at the schema of this version.
+pub async fn open(db_url: &str) -> Result<Database, sqlx::Error> {
+    match scheme(db_url) {
+        "memory" => Ok(Arc::new(MemoryRepository::new())),
+        "sqlite" => Ok(Arc::new(SqliteRepository::open(db_url).await?)),
+        #[cfg(feature = "postgres")]
+        "postgres" | "postgresql" => Ok(Arc::new(PostgresRepository::open(db_url).await?)),
+        scheme => Err(sqlx::Error::Configuration(
+            format!("unsupported database scheme {:?}", scheme).into(),
+        )),
+    }
+}
+
+/// Replaces the database of `db_url` with a backup taken by
+/// [`BackupRepository::backup`] on the same kind of backend and migrates it to
+/// the current schema. Nothing may be connected to the database meanwhile.
+pub async fn restore(db_url: &str, path: &Path) -> Result<(), AppError> {
+    match scheme(db_url) {
+        "sqlite" => SqliteRepository::restore(db_url, path).await,
+        #[cfg(feature = "postgres")]
+        "postgres" | "postgresql" => PostgresRepository::restore(db_url, path).await,
+        scheme => Err(AppError::Backup(format!(
+            "cannot restore into a {:?} database",
+            scheme
+        ))),
+    }
+}
+
+fn scheme(db_url: &str) -> &str {
+    db_url.split_once(':').map_or(db_url, |(scheme, _)| scheme)
+}
+
+/// Number of `migrator`'s migrations that the backup with the `applied`
+/// `(version, checksum)` pairs was taken at. Fails for backups of a newer
+/// scytale and for schemas whose migrations were edited.
+fn check_backup_migrations(
+    migrator: &Migrator,
+    applied: &[(i64, Vec<u8>)],
+) -> Result<usize, AppError> {
+    for (index, (version, checksum)) in applied.iter().enumerate() {
+        let migration = migrator
+            .migrations
+            .get(index)
+            .filter(|migration| migration.version == *version)
+            .ok_or_else(|| {
+                AppError::Backup(format!(
+                    "migration {} of the backup is unknown to this version of scytale",
+                    version
+                ))
+            })?;
+        if migration.checksum.as_ref() != checksum.as_slice() {
+            return Err(AppError::Backup(format!(
+                "migration {} of the backup does not match the one of this version of scytale",
+                version
+            )));
+        }
+    }
+
+    Ok(applied.len())
+}
+
+/// Fails unless exactly the migrations of `migrator` were `applied`, given as
+/// `(version, checksum)` pairs, see [`open`].
+fn check_schema(migrator: &Migrator, applied: &[(i64, Vec<u8>)]) -> Result<(), sqlx::Error> {
+    let current = applied.len() == migrator.migrations.len()
+        && applied.iter().zip(migrator.migrations.iter()).all(
+            |((version, checksum), migration)| {
+                migration.version == *version && migration.checksum.as_ref() == checksum.as_slice()
+            },
+        );
+    if !current {
+        return Err(sqlx::Error::Configuration(
+            "the database schema does not match this version of scytale, run `scytale migrate`"
+                .into(),
+        ));
+    }
+
+    Ok(())
+}
+
+pub trait Repository:
+    UserRepository
+    + RoleRepository
+    + SessionRepository
+    + TotpRepository
+    + PasswordResetRepository
+    + InviteRepository
+    + IdentityRepository
+    + AuditRepository
+    + DeviceRepository
+    + DataKeyRepository
+    + ClipRepository
+    + BackupRepository
+    + RetentionRepository
+    + Any
+{
+}
+
+impl<T> Repository for T where
+    T: UserRepository
+        + RoleRepository
+        + SessionRepository
+        + TotpRepository
+        + PasswordResetRepository
+        + InviteRepository
+        + IdentityRepository
+        + AuditRepository
+        + DeviceRepository
+        + DataKeyRepository
+        + ClipRepository
+        + BackupRepository
+        + RetentionRepository
+        + Any
+{
+}
+
+#[async_trait]
+pub trait UserRepository: Send + Sync {
+    /// `user.password` is expected to be hashed already.
+    async fn create_user(&self, user: &UserCreate) -> Result<UserEntity, AppError>;
+
+    async fn get_user_by_id(&self, id: i64) -> Result<UserEntity, AppError>;
+
+    async fn get_user_by_id_email(&self, id: i64, email: &str) -> Result<UserEntity, AppError>;
+
+    async fn get_user_by_email(&self, email: &str) -> Result<Option<UserEntity>, AppError>;
+
+    async fn update_user_password(&self, id: i64, password_hash: &str) -> Result<(), AppError>;
+
+    async fn update_user_role(&self, id: i64, role: &Role) -> Result<(), AppError>;
+
+    async fn get_user_auth_provider(&self, email: &str) -> Result<Option<String>, AppError>;
+
+    async fn set_user_auth_provider(&self, id: i64, provider: Option<&str>)
+        -> Result<(), AppError>;
+
+    async fn is_user_disabled(&self, id: i64) -> Result<bool, AppError>;
+
+    async fn count_users(&self, filter: &UserListQuery) -> Result<i64, AppError>;
+
+    async fn list_users(
+        &self,
+        filter: &UserListQuery,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<UserDetails>, AppError>;
+
+    async fn get_user_details(&self, id: i64) -> Result<UserDetails, AppError>;
+
+    /// Overwrites the profile without any checks, see `service::user::update_user`.
+    async fn update_user_profile(
+        &self,
+        id: i64,
+        email: &str,
+        name: &str,
+        role: &Role,
+    ) -> Result<(), AppError>;
+
+    async fn set_user_disabled(&self, id: i64, disabled: bool) -> Result<(), AppError>;
+
+    /// Everything owned by the user goes with it, the audit log keeps its entries.
+    async fn delete_user(&self, id: i64) -> Result<(), AppError>;
+}
+
+#[async_trait]
+pub trait RoleRepository: Send + Sync {
+    async fn role_exists(&self, role: &Role) -> Result<bool, AppError>;
+
+    async fn get_role_permissions(&self, role: &Role) -> Result<Vec<Permission>, AppError>;
+
+    async fn has_permission(&self, role: &Role, permission: Permission) -> Result<bool, AppError>;
+
+    async fn get_role(&self, role: &Role) -> Result<RoleEntity, AppError>;
+
+    async fn list_roles(&self) -> Result<Vec<RoleEntity>, AppError>;
+
+    /// Creates the role or replaces its description and whole permission set.
+    async fn upsert_role(&self, role: &Role, payload: &RoleUpsert) -> Result<RoleEntity, AppError>;
+
+    /// Fails with `RoleInUse` while users still have the role.
+    async fn delete_role(&self, role: &Role) -> Result<(), AppError>;
+}
+
+#[async_trait]
+pub trait SessionRepository: Send + Sync {
+    /// Returns the id of the new session.
+    async fn create_session_until(
+        &self,
+        user_id: i64,
+        client_id: Option<&str>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<String, AppError>;
+
+    async fn is_session_active(&self, id: &str, user_id: i64) -> Result<bool, AppError>;
+
+    /// Moves the expiry of a session that is not revoked.
+    async fn extend_session(&self, id: &str, expires_at: DateTime<Utc>) -> Result<(), AppError>;
+
+    async fn revoke_user_sessions(&self, user_id: i64) -> Result<u64, AppError>;
+
+    /// Revokes every session of the user except `keep`.
+    async fn revoke_other_sessions(&self, user_id: i64, keep: &str) -> Result<u64, AppError>;
+
+    async fn revoke_device_sessions(&self, user_id: i64, client_id: &str) -> Result<u64, AppError>;
+
+    /// Binds a session without a device to `client_id`, unless other active
+    /// sessions of the user are bound to it. Returns whether the session is
+    /// bound to `client_id` afterwards.
+    async fn bind_session_client(
+        &self,
+        id: &str,
+        user_id: i64,
+        client_id: &str,
+    ) -> Result<bool, AppError>;
+
+    async fn list_user_sessions(&self, user_id: i64) -> Result<Vec<SessionEntity>, AppError>;
+}
+
+#[async_trait]
+pub trait TotpRepository: Send + Sync {
+    async fn get_totp(&self, user_id: i64) -> Result<Option<TotpEntity>, AppError>;
+
+    /// Stores a not yet confirmed secret, replacing any earlier pending enrollment.
+    async fn set_pending_totp(&self, user_id: i64, secret: &str) -> Result<(), AppError>;
+
+    /// Enables the pending secret and replaces all recovery codes with `code_hashes`.
+    async fn enable_totp(
+        &self,
+        user_id: i64,
+        step: i64,
+        code_hashes: &[String],
+    ) -> Result<(), AppError>;
+
+    async fn set_totp_last_step(&self, user_id: i64, step: i64) -> Result<(), AppError>;
+
+    /// Marks the recovery code as used, returns false if it is unknown or already used.
+    async fn use_recovery_code(&self, user_id: i64, code_hash: &str) -> Result<bool, AppError>;
+
+    async fn delete_totp(&self, user_id: i64) -> Result<(), AppError>;
+}
+
+#[async_trait]
+pub trait PasswordResetRepository: Send + Sync {
+    /// Stores a new reset token for the user, dropping any unused earlier ones.
+    async fn create_password_reset(
+        &self,
+        user_id: i64,
+        created_by: i64,
+        token_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), AppError>;
+
+    /// User of an unused and unexpired token, without consuming it.
+    async fn get_password_reset_user(&self, token_hash: &str) -> Result<i64, AppError>;
+
+    /// Marks an unused, unexpired token as used, sets the password hash of its
+    /// user and revokes all their sessions, returning the user. Must be atomic
+    /// so that the token can only be consumed once and never half way.
+    async fn consume_password_reset(
+        &self,
+        token_hash: &str,
+        password_hash: &str,
+    ) -> Result<i64, AppError>;
+}
+
+#[async_trait]
+pub trait InviteRepository: Send + Sync {
+    async fn create_invite(
+        &self,
+        code_hash: &str,
+        role: &Role,
+        max_uses: i64,
+        created_by: i64,
+        expires_at: DateTime<Utc>,
+    ) -> Result<InviteEntity, AppError>;
+
+    /// Invites that are neither revoked, expired nor used up.
+    async fn list_outstanding_invites(&self) -> Result<Vec<InviteEntity>, AppError>;
+
+    async fn revoke_invite(&self, id: i64) -> Result<(), AppError>;
+
+    /// Role of a still usable invite, without using it.
+    async fn get_invite_role(&self, code_hash: &str) -> Result<Role, AppError>;
+
+    /// Counts one use of the invite, failing if it is no longer usable. Must be
+    /// atomic so that concurrent uses cannot exceed `max_uses`.
+    async fn use_invite(&self, code_hash: &str) -> Result<Role, AppError>;
+
+    /// Takes back one use of the invite, after the registration it was used
+    /// for failed.
+    async fn release_invite(&self, code_hash: &str) -> Result<(), AppError>;
+
+    async fn list_invites_created_by(&self, user_id: i64) -> Result<Vec<InviteEntity>, AppError>;
+}
+
+#[async_trait]
+pub trait IdentityRepository: Send + Sync {
+    /// Local user the external identity is linked to.
+    async fn get_identity_user(
+        &self,
+        provider: &str,
+        subject: &str,
+    ) -> Result<Option<i64>, AppError>;
+
+    async fn link_identity(
+        &self,
+        provider: &str,
+        subject: &str,
+        user_id: i64,
+        email: Option<&str>,
+    ) -> Result<(), AppError>;
+
+    /// Records a login through the identity, keeping the last known email.
+    async fn touch_identity(
+        &self,
+        provider: &str,
+        subject: &str,
+        email: Option<&str>,
+    ) -> Result<(), AppError>;
+
+    async fn list_user_identities(&self, user_id: i64) -> Result<Vec<IdentityEntity>, AppError>;
+}
+
+#[async_trait]
+pub trait AuditRepository: Send + Sync {
+    async fn record_audit(&self, event: &AuditEvent) -> Result<(), AppError>;
+
+    async fn count_audit(&self, filter: &AuditQuery) -> Result<i64, AppError>;
+
+    /// Matching entries newest first, a negative `limit` returns all of them.
+    async fn list_audit(
+        &self,
+        filter: &AuditQuery,
+        before_id: Option<i64>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<AuditEntry>, AppError>;
+}
+
+#[async_trait]
+pub trait DeviceRepository: Send + Sync {
+    async fn get_device(
+        &self,
+        user_id: i64,
+        client_id: &str,
+    ) -> Result<Option<DeviceEntity>, AppError>;
+
+    /// Creates the device if it is unknown and records the connection. The
+    /// name is only taken on creation, platform and version are kept when the
+    /// client stops sending them.
+    async fn upsert_device(
+        &self,
+        user_id: i64,
+        param: &DeviceRegistration,
+        ip: Option<IpAddr>,
+    ) -> Result<DeviceEntity, AppError>;
+
+    async fn create_guest_device(
+        &self,
+        user_id: i64,
+        client_id: &str,
+        name: &str,
+        access: DeviceAccess,
+        expires_at: DateTime<Utc>,
+    ) -> Result<DeviceEntity, AppError>;
+
+    async fn is_device_revoked(&self, user_id: i64, client_id: &str) -> Result<bool, AppError>;
+
+    async fn touch_device(&self, user_id: i64, client_id: &str) -> Result<(), AppError>;
+
+    async fn list_devices(&self, user_id: i64) -> Result<Vec<DeviceEntity>, AppError>;
+
+    async fn rename_device(
+        &self,
+        user_id: i64,
+        client_id: &str,
+        name: &str,
+    ) -> Result<DeviceEntity, AppError>;
+
+    /// Revoking an already revoked device keeps the original revocation time.
+    async fn revoke_device(&self, user_id: i64, client_id: &str) -> Result<DeviceEntity, AppError>;
+}
+
+#[async_trait]
+pub trait DataKeyRepository: Send + Sync {
+    async fn get_data_key(&self, user_id: i64) -> Result<Option<DataKeyEntity>, AppError>;
+
+    /// Stores the key unless the user already has one, returns the stored key.
+    async fn insert_data_key(
+        &self,
+        user_id: i64,
+        master_key_id: &str,
+        wrapped_key: &[u8],
+    ) -> Result<DataKeyEntity, AppError>;
+
+    async fn list_data_keys_wrapped_by(
+        &self,
+        master_key_id: &str,
+        limit: i64,
+    ) -> Result<Vec<DataKeyEntity>, AppError>;
+
+    async fn count_data_keys_not_wrapped_by(&self, master_key_id: &str) -> Result<i64, AppError>;
+
+    /// Replaces the wrapped key if it is still wrapped by `old_master_key_id`,
+    /// returns false otherwise.
+    async fn rewrap_data_key(
+        &self,
+        user_id: i64,
+        old_master_key_id: &str,
+        new_master_key_id: &str,
+        wrapped_key: &[u8],
+    ) -> Result<bool, AppError>;
+}
+
+#[async_trait]
+pub trait ClipRepository: Send + Sync {
+    async fn insert_clip(&self, clip: &ClipEntity) -> Result<(), AppError>;
+
+    /// Keeps the clip for the device `client_id` until it connects again.
+    async fn queue_clip(
+        &self,
+        clip_id: &str,
+        user_id: i64,
+        client_id: &str,
+    ) -> Result<(), AppError>;
+
+    /// Clips queued for the device, oldest first.
+    async fn list_queued_clips(
+        &self,
+        user_id: i64,
+        client_id: &str,
+    ) -> Result<Vec<ClipEntity>, AppError>;
+
+    /// Removes the clip from the device's queue once it was delivered.
+    async fn dequeue_clip(&self, clip_id: &str, client_id: &str) -> Result<(), AppError>;
+
+    /// The user's clip history, oldest first.
+    async fn list_clips(&self, user_id: i64) -> Result<Vec<ClipEntity>, AppError>;
+
+    /// Clips queued for any of the user's devices, oldest first.
+    async fn list_clip_queue(&self, user_id: i64) -> Result<Vec<QueuedClipEntity>, AppError>;
+}
+
+#[async_trait]
+pub trait BackupRepository: Send + Sync {
+    /// Writes a consistent snapshot of the whole database to the new file
+    /// `path` while requests keep being served, see [`restore`].
+    async fn backup(&self, path: &Path) -> Result<(), AppError>;
+}
+
+/// Cleanup of rows that expired, were revoked or were used up, and of the clip
+/// history and queue. `user_id`
+/// limits a purge to one user, `None` purges every user without a retention
+/// of their own.
+#[async_trait]
+pub trait RetentionRepository: Send + Sync {
+    /// Users with a retention of their own as `(user_id, days)`.
+    async fn list_user_retention(&self) -> Result<Vec<(i64, i64)>, AppError>;
+
+    async fn get_user_retention(&self, user_id: i64) -> Result<Option<i64>, AppError>;
+
+    /// `None` goes back to the server wide retention.
+    async fn set_user_retention(&self, user_id: i64, days: Option<i64>) -> Result<(), AppError>;
+
+    /// Sessions that expired or were revoked before `before`.
+    async fn purge_sessions(
+        &self,
+        user_id: Option<i64>,
+        before: DateTime<Utc>,
+    ) -> Result<u64, AppError>;
+
+    /// Reset links that expired or were used before `before`.
+    async fn purge_password_resets(
+        &self,
+        user_id: Option<i64>,
+        before: DateTime<Utc>,
+    ) -> Result<u64, AppError>;
+
+    /// Guest devices that expired before `before`.
+    async fn purge_guest_devices(
+        &self,
+        user_id: Option<i64>,
+        before: DateTime<Utc>,
+    ) -> Result<u64, AppError>;
+
+    /// Clips relayed before `before`, along with their queue entries.
+    async fn purge_clips(
+        &self,
+        user_id: Option<i64>,
+        before: DateTime<Utc>,
+    ) -> Result<u64, AppError>;
+
+    /// Queue entries of devices that have not connected since before `before`,
+    /// the clips stay in the history.
+    async fn purge_clip_queue(
+        &self,
+        user_id: Option<i64>,
+        before: DateTime<Utc>,
+    ) -> Result<u64, AppError>;
+
+    /// Invites that expired or were revoked before `before`.
+    async fn purge_invites(&self, before: DateTime<Utc>) -> Result<u64, AppError>;
+
+    /// Gives the space of deleted rows back, a no-op where there is none.
+    async fn vacuum(&self) -> Result<(), AppError>;
+}
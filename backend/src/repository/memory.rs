@@ -0,0 +1,1488 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::IpAddr,
+    path::Path,
+    sync::{Mutex, MutexGuard},
+};
+
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+
+use super::{
+    AuditRepository, BackupRepository, ClipRepository, DataKeyRepository, DeviceRepository,
+    IdentityRepository, InviteRepository, PasswordResetRepository, RetentionRepository,
+    RoleRepository, SessionRepository, TotpRepository, UserRepository,
+};
+use crate::{
+    error::AppError,
+    models::{
+        audit::{AuditEntry, AuditEvent, AuditQuery},
+        clip::{ClipEntity, QueuedClipEntity},
+        device::{DeviceAccess, DeviceEntity, DeviceRegistration},
+        encryption::DataKeyEntity,
+        invite::InviteEntity,
+        oidc::IdentityEntity,
+        role::{Permission, RoleEntity, RoleUpsert},
+        session::SessionEntity,
+        totp::TotpEntity,
+        user::{Role, UserCreate, UserDetails, UserEntity, UserListQuery},
+    },
+    utils::generate_token,
+};
+
+/// Keeps everything in process memory and loses it on drop, selected with a
+/// `memory:` database url or [`crate::Scytale::with_database`].
+///
+/// Mirrors the SQL schema: the roles of the migrations are seeded, deleting a
+/// user or role cascades the same way and the audit log is append-only.
+pub struct MemoryRepository {
+    tables: Mutex<Tables>,
+}
+
+struct Tables {
+    users: BTreeMap<i64, UserRow>,
+    next_user_id: i64,
+    roles: BTreeMap<String, RoleRow>,
+    sessions: Vec<SessionEntity>,
+    totp: HashMap<i64, TotpEntity>,
+    recovery_codes: Vec<RecoveryCode>,
+    password_resets: Vec<PasswordReset>,
+    invites: Vec<InviteRow>,
+    next_invite_id: i64,
+    identities: Vec<IdentityEntity>,
+    audit_log: Vec<AuditEntry>,
+    devices: Vec<DeviceEntity>,
+    data_keys: BTreeMap<i64, DataKeyEntity>,
+    clips: Vec<ClipEntity>,
+    clip_queue: Vec<QueuedClipEntity>,
+    user_retention: BTreeMap<i64, i64>,
+}
+
+struct UserRow {
+    user: UserEntity,
+    auth_provider: Option<String>,
+    disabled_at: Option<DateTime<Utc>>,
+}
+
+struct RoleRow {
+    description: String,
+    permissions: Vec<Permission>,
+}
+
+struct RecoveryCode {
+    user_id: i64,
+    code_hash: String,
+    used_at: Option<DateTime<Utc>>,
+}
+
+struct PasswordReset {
+    token_hash: String,
+    user_id: i64,
+    /// Cleared when the issuing admin is deleted, like `ON DELETE SET NULL`.
+    created_by: Option<i64>,
+    expires_at: DateTime<Utc>,
+    used_at: Option<DateTime<Utc>>,
+}
+
+struct InviteRow {
+    invite: InviteEntity,
+    code_hash: String,
+    revoked_at: Option<DateTime<Utc>>,
+}
+
+impl InviteRow {
+    fn is_usable(&self, now: DateTime<Utc>) -> bool {
+        self.revoked_at.is_none()
+            && self.invite.expires_at > now
+            && self.invite.uses < self.invite.max_uses
+    }
+}
+
+impl MemoryRepository {
+    pub fn new() -> Self {
+        let roles = BTreeMap::from([
+            (
+                Role::ADMIN.to_string(),
+                RoleRow {
+                    description: "Full access to every administrative feature".to_string(),
+                    permissions: vec![
+                        Permission::ManageUsers,
+                        Permission::ManageRoles,
+                        Permission::ViewAuditLog,
+                        Permission::ReadMetrics,
+                        Permission::ManageBackups,
+                    ],
+                },
+            ),
+            (
+                Role::USER.to_string(),
+                RoleRow {
+                    description: "Regular user without administrative permissions".to_string(),
+                    permissions: Vec::new(),
+                },
+            ),
+        ]);
+
+        Self {
+            tables: Mutex::new(Tables {
+                users: BTreeMap::new(),
+                next_user_id: 1,
+                roles,
+                sessions: Vec::new(),
+                totp: HashMap::new(),
+                recovery_codes: Vec::new(),
+                password_resets: Vec::new(),
+                invites: Vec::new(),
+                next_invite_id: 1,
+                identities: Vec::new(),
+                audit_log: Vec::new(),
+                devices: Vec::new(),
+                data_keys: BTreeMap::new(),
+                clips: Vec::new(),
+                clip_queue: Vec::new(),
+                user_retention: BTreeMap::new(),
+            }),
+        }
+    }
+
+    /// No method awaits while holding the lock, so it is never poisoned by a
+    /// cancelled request, only by a panic inside this module.
+    fn tables(&self) -> MutexGuard<'_, Tables> {
+        self.tables.lock().expect("memory repository poisoned")
+    }
+}
+
+impl Default for MemoryRepository {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Tables {
+    fn user(&self, id: i64) -> Result<&UserRow, AppError> {
+        self.users.get(&id).ok_or(AppError::UserDoesNotExist)
+    }
+
+    fn user_mut(&mut self, id: i64) -> Result<&mut UserRow, AppError> {
+        self.users.get_mut(&id).ok_or(AppError::UserDoesNotExist)
+    }
+
+    fn check_role(&self, role: &Role) -> Result<(), AppError> {
+        if self.roles.contains_key(&role.0) {
+            Ok(())
+        } else {
+            Err(AppError::RoleDoesNotExist)
+        }
+    }
+
+    fn check_email(&self, email: &str, except: Option<i64>) -> Result<(), AppError> {
+        let taken = self
+            .users
+            .values()
+            .any(|row| row.user.email == email && Some(row.user.id) != except);
+        if taken {
+            Err(AppError::UserAlreadyExits)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn user_details(&self, row: &UserRow) -> UserDetails {
+        UserDetails {
+            id: row.user.id,
+            email: row.user.email.clone(),
+            name: row.user.name.clone(),
+            role: row.user.role.clone(),
+            auth_provider: row.auth_provider.clone(),
+            totp_enabled: self.totp.get(&row.user.id).is_some_and(|totp| totp.enabled),
+            disabled_at: row.disabled_at,
+        }
+    }
+
+    fn filter_users<'a>(
+        &'a self,
+        filter: &'a UserListQuery,
+    ) -> impl Iterator<Item = &'a UserRow> + 'a {
+        let q = filter.q.as_ref().map(|q| q.to_lowercase());
+        self.users.values().filter(move |row| {
+            q.as_ref().is_none_or(|q| {
+                row.user.email.to_lowercase().contains(q)
+                    || row.user.name.to_lowercase().contains(q)
+            }) && filter
+                .role
+                .as_ref()
+                .is_none_or(|role| &row.user.role == role)
+                && filter
+                    .disabled
+                    .is_none_or(|disabled| row.disabled_at.is_some() == disabled)
+        })
+    }
+
+    fn role_entity(&self, name: &str) -> Result<RoleEntity, AppError> {
+        let row = self.roles.get(name).ok_or(AppError::RoleDoesNotExist)?;
+
+        Ok(RoleEntity {
+            name: Role::new(name),
+            description: row.description.clone(),
+            permissions: row.permissions.clone(),
+        })
+    }
+
+    fn device_mut(&mut self, user_id: i64, client_id: &str) -> Option<&mut DeviceEntity> {
+        self.devices
+            .iter_mut()
+            .find(|device| device.user_id == user_id && device.client_id == client_id)
+    }
+}
+
+fn audit_matches(entry: &AuditEntry, filter: &AuditQuery) -> bool {
+    filter.actor_id.is_none_or(|id| entry.actor_id == Some(id))
+        && filter
+            .actor_email
+            .as_ref()
+            .is_none_or(|email| entry.actor_email.as_ref() == Some(email))
+        && filter.action.is_none_or(|action| entry.action == action)
+        && filter
+            .target
+            .as_ref()
+            .is_none_or(|target| entry.target.as_ref() == Some(target))
+        && filter.result.is_none_or(|result| entry.result == result)
+        && filter.since.is_none_or(|since| entry.created_at >= since)
+        && filter.until.is_none_or(|until| entry.created_at < until)
+}
+
+/// Whether a purge for `user_id` covers the rows of `owner`.
+fn is_purged(user_retention: &BTreeMap<i64, i64>, user_id: Option<i64>, owner: i64) -> bool {
+    match user_id {
+        Some(user_id) => user_id == owner,
+        None => !user_retention.contains_key(&owner),
+    }
+}
+
+/// `LIMIT` and `OFFSET` as SQLite reads them, a negative limit is no limit.
+fn page<T>(items: impl Iterator<Item = T>, limit: i64, offset: i64) -> Vec<T> {
+    let items = items.skip(offset.max(0) as usize);
+    match usize::try_from(limit) {
+        Ok(limit) => items.take(limit).collect(),
+        Err(_) => items.collect(),
+    }
+}
+
+#[async_trait]
+impl UserRepository for MemoryRepository {
+    async fn create_user(&self, user: &UserCreate) -> Result<UserEntity, AppError> {
+        let mut tables = self.tables();
+        tables.check_role(&user.role)?;
+        tables.check_email(&user.email, None)?;
+
+        let id = tables.next_user_id;
+        tables.next_user_id += 1;
+        let entity = UserEntity {
+            id,
+            email: user.email.clone(),
+            name: user.name.clone(),
+            password: user.password.clone(),
+            role: user.role.clone(),
+        };
+        tables.users.insert(
+            id,
+            UserRow {
+                user: entity.clone(),
+                auth_provider: None,
+                disabled_at: None,
+            },
+        );
+
+        Ok(entity)
+    }
+
+    async fn get_user_by_id(&self, id: i64) -> Result<UserEntity, AppError> {
+        Ok(self.tables().user(id)?.user.clone())
+    }
+
+    async fn get_user_by_id_email(&self, id: i64, email: &str) -> Result<UserEntity, AppError> {
+        let tables = self.tables();
+        let row = tables.user(id)?;
+        if row.user.email != email {
+            return Err(AppError::UserDoesNotExist);
+        }
+
+        Ok(row.user.clone())
+    }
+
+    async fn get_user_by_email(&self, email: &str) -> Result<Option<UserEntity>, AppError> {
+        let tables = self.tables();
+        let user = tables
+            .users
+            .values()
+            .find(|row| row.user.email == email)
+            .map(|row| row.user.clone());
+
+        Ok(user)
+    }
+
+    async fn update_user_password(&self, id: i64, password_hash: &str) -> Result<(), AppError> {
+        if let Some(row) = self.tables().users.get_mut(&id) {
+            row.user.password = password_hash.to_string();
+        }
+
+        Ok(())
+    }
+
+    async fn update_user_role(&self, id: i64, role: &Role) -> Result<(), AppError> {
+        let mut tables = self.tables();
+        tables.check_role(role)?;
+        if let Some(row) = tables.users.get_mut(&id) {
+            row.user.role = role.clone();
+        }
+
+        Ok(())
+    }
+
+    async fn get_user_auth_provider(&self, email: &str) -> Result<Option<String>, AppError> {
+        let tables = self.tables();
+        let provider = tables
+            .users
+            .values()
+            .find(|row| row.user.email == email)
+            .and_then(|row| row.auth_provider.clone());
+
+        Ok(provider)
+    }
+
+    async fn set_user_auth_provider(
+        &self,
+        id: i64,
+        provider: Option<&str>,
+    ) -> Result<(), AppError> {
+        self.tables().user_mut(id)?.auth_provider = provider.map(str::to_string);
+
+        Ok(())
+    }
+
+    async fn is_user_disabled(&self, id: i64) -> Result<bool, AppError> {
+        Ok(self.tables().user(id)?.disabled_at.is_some())
+    }
+
+    async fn count_users(&self, filter: &UserListQuery) -> Result<i64, AppError> {
+        Ok(self.tables().filter_users(filter).count() as i64)
+    }
+
+    async fn list_users(
+        &self,
+        filter: &UserListQuery,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<UserDetails>, AppError> {
+        let tables = self.tables();
+        let users = tables
+            .filter_users(filter)
+            .map(|row| tables.user_details(row));
+
+        Ok(page(users, limit, offset))
+    }
+
+    async fn get_user_details(&self, id: i64) -> Result<UserDetails, AppError> {
+        let tables = self.tables();
+        let row = tables.user(id)?;
+
+        Ok(tables.user_details(row))
+    }
+
+    async fn update_user_profile(
+        &self,
+        id: i64,
+        email: &str,
+        name: &str,
+        role: &Role,
+    ) -> Result<(), AppError> {
+        let mut tables = self.tables();
+        tables.check_role(role)?;
+        tables.check_email(email, Some(id))?;
+        if let Some(row) = tables.users.get_mut(&id) {
+            row.user.email = email.to_string();
+            row.user.name = name.to_string();
+            row.user.role = role.clone();
+        }
+
+        Ok(())
+    }
+
+    async fn set_user_disabled(&self, id: i64, disabled: bool) -> Result<(), AppError> {
+        self.tables().user_mut(id)?.disabled_at = disabled.then(Utc::now);
+
+        Ok(())
+    }
+
+    /// Same cascades as the foreign keys of the SQL schema.
+    async fn delete_user(&self, id: i64) -> Result<(), AppError> {
+        let mut tables = self.tables();
+        if tables.users.remove(&id).is_none() {
+            return Err(AppError::UserDoesNotExist);
+        }
+
+        tables.sessions.retain(|session| session.user_id != id);
+        tables.totp.remove(&id);
+        tables.recovery_codes.retain(|code| code.user_id != id);
+        tables.password_resets.retain(|reset| reset.user_id != id);
+        for reset in &mut tables.password_resets {
+            if reset.created_by == Some(id) {
+                reset.created_by = None;
+            }
+        }
+        tables.identities.retain(|identity| identity.user_id != id);
+        tables.devices.retain(|device| device.user_id != id);
+        tables.data_keys.remove(&id);
+        tables.clips.retain(|clip| clip.user_id != id);
+        tables.clip_queue.retain(|queued| queued.user_id != id);
+        tables.user_retention.remove(&id);
+        for row in &mut tables.invites {
+            if row.invite.created_by == Some(id) {
+                row.invite.created_by = None;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl RoleRepository for MemoryRepository {
+    async fn role_exists(&self, role: &Role) -> Result<bool, AppError> {
+        Ok(self.tables().roles.contains_key(&role.0))
+    }
+
+    async fn get_role_permissions(&self, role: &Role) -> Result<Vec<Permission>, AppError> {
+        let tables = self.tables();
+        let permissions = tables
+            .roles
+            .get(&role.0)
+            .map(|row| row.permissions.clone())
+            .unwrap_or_default();
+
+        Ok(permissions)
+    }
+
+    async fn has_permission(&self, role: &Role, permission: Permission) -> Result<bool, AppError> {
+        let tables = self.tables();
+        let granted = tables
+            .roles
+            .get(&role.0)
+            .is_some_and(|row| row.permissions.contains(&permission));
+
+        Ok(granted)
+    }
+
+    async fn get_role(&self, role: &Role) -> Result<RoleEntity, AppError> {
+        self.tables().role_entity(&role.0)
+    }
+
+    async fn list_roles(&self) -> Result<Vec<RoleEntity>, AppError> {
+        let tables = self.tables();
+        tables
+            .roles
+            .keys()
+            .map(|name| tables.role_entity(name))
+            .collect()
+    }
+
+    async fn upsert_role(&self, role: &Role, payload: &RoleUpsert) -> Result<RoleEntity, AppError> {
+        let mut permissions = Vec::with_capacity(payload.permissions.len());
+        for permission in &payload.permissions {
+            if !permissions.contains(permission) {
+                permissions.push(*permission);
+            }
+        }
+
+        let mut tables = self.tables();
+        tables.roles.insert(
+            role.0.clone(),
+            RoleRow {
+                description: payload.description.clone(),
+                permissions,
+            },
+        );
+
+        tables.role_entity(&role.0)
+    }
+
+    async fn delete_role(&self, role: &Role) -> Result<(), AppError> {
+        let mut tables = self.tables();
+        if tables.users.values().any(|row| &row.user.role == role) {
+            return Err(AppError::RoleInUse);
+        }
+
+        if tables.roles.remove(&role.0).is_none() {
+            return Err(AppError::RoleDoesNotExist);
+        }
+        tables.invites.retain(|row| &row.invite.role != role);
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SessionRepository for MemoryRepository {
+    async fn create_session_until(
+        &self,
+        user_id: i64,
+        client_id: Option<&str>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<String, AppError> {
+        let mut tables = self.tables();
+        tables.user(user_id)?;
+
+        let id = generate_token(32);
+        tables.sessions.push(SessionEntity {
+            id: id.clone(),
+            user_id,
+            created_at: Utc::now(),
+            expires_at,
+            revoked_at: None,
+            client_id: client_id.map(str::to_string),
+        });
+
+        Ok(id)
+    }
+
+    async fn is_session_active(&self, id: &str, user_id: i64) -> Result<bool, AppError> {
+        let now = Utc::now();
+        let active = self.tables().sessions.iter().any(|session| {
+            session.id == id
+                && session.user_id == user_id
+                && session.revoked_at.is_none()
+                && session.expires_at > now
+        });
+
+        Ok(active)
+    }
+
+    async fn extend_session(&self, id: &str, expires_at: DateTime<Utc>) -> Result<(), AppError> {
+        let mut tables = self.tables();
+        let session = tables
+            .sessions
+            .iter_mut()
+            .find(|session| session.id == id && session.revoked_at.is_none());
+        if let Some(session) = session {
+            session.expires_at = expires_at;
+        }
+
+        Ok(())
+    }
+
+    async fn revoke_user_sessions(&self, user_id: i64) -> Result<u64, AppError> {
+        let now = Utc::now();
+        let mut revoked = 0;
+        for session in &mut self.tables().sessions {
+            if session.user_id == user_id && session.revoked_at.is_none() {
+                session.revoked_at = Some(now);
+                revoked += 1;
+            }
+        }
+
+        Ok(revoked)
+    }
+
+    async fn revoke_other_sessions(&self, user_id: i64, keep: &str) -> Result<u64, AppError> {
+        let now = Utc::now();
+        let mut revoked = 0;
+        for session in &mut self.tables().sessions {
+            if session.user_id == user_id && session.id != keep && session.revoked_at.is_none() {
+                session.revoked_at = Some(now);
+                revoked += 1;
+            }
+        }
+
+        Ok(revoked)
+    }
+
+    async fn revoke_device_sessions(&self, user_id: i64, client_id: &str) -> Result<u64, AppError> {
+        let now = Utc::now();
+        let mut revoked = 0;
+        for session in &mut self.tables().sessions {
+            if session.user_id == user_id
+                && session.client_id.as_deref() == Some(client_id)
+                && session.revoked_at.is_none()
+            {
+                session.revoked_at = Some(now);
+                revoked += 1;
+            }
+        }
+
+        Ok(revoked)
+    }
+
+    async fn bind_session_client(
+        &self,
+        id: &str,
+        user_id: i64,
+        client_id: &str,
+    ) -> Result<bool, AppError> {
+        let now = Utc::now();
+        let mut tables = self.tables();
+        let taken = tables.sessions.iter().any(|other| {
+            other.user_id == user_id
+                && other.client_id.as_deref() == Some(client_id)
+                && other.revoked_at.is_none()
+                && other.expires_at > now
+        });
+        let session = tables
+            .sessions
+            .iter_mut()
+            .find(|session| session.id == id && session.user_id == user_id);
+        match session {
+            Some(session) if session.client_id.is_none() && !taken => {
+                session.client_id = Some(client_id.to_string());
+                Ok(true)
+            }
+            Some(session) => Ok(session.client_id.as_deref() == Some(client_id)),
+            None => Ok(false),
+        }
+    }
+
+    async fn list_user_sessions(&self, user_id: i64) -> Result<Vec<SessionEntity>, AppError> {
+        let sessions = self
+            .tables()
+            .sessions
+            .iter()
+            .filter(|session| session.user_id == user_id)
+            .cloned()
+            .collect();
+
+        Ok(sessions)
+    }
+}
+
+#[async_trait]
+impl TotpRepository for MemoryRepository {
+    async fn get_totp(&self, user_id: i64) -> Result<Option<TotpEntity>, AppError> {
+        Ok(self.tables().totp.get(&user_id).cloned())
+    }
+
+    async fn set_pending_totp(&self, user_id: i64, secret: &str) -> Result<(), AppError> {
+        let mut tables = self.tables();
+        tables.user(user_id)?;
+        tables.totp.insert(
+            user_id,
+            TotpEntity {
+                user_id,
+                secret: secret.to_string(),
+                enabled: false,
+                last_used_step: 0,
+            },
+        );
+
+        Ok(())
+    }
+
+    async fn enable_totp(
+        &self,
+        user_id: i64,
+        step: i64,
+        code_hashes: &[String],
+    ) -> Result<(), AppError> {
+        let mut tables = self.tables();
+        if let Some(totp) = tables.totp.get_mut(&user_id) {
+            totp.enabled = true;
+            totp.last_used_step = step;
+        }
+
+        tables.recovery_codes.retain(|code| code.user_id != user_id);
+        tables
+            .recovery_codes
+            .extend(code_hashes.iter().map(|code_hash| RecoveryCode {
+                user_id,
+                code_hash: code_hash.clone(),
+                used_at: None,
+            }));
+
+        Ok(())
+    }
+
+    async fn set_totp_last_step(&self, user_id: i64, step: i64) -> Result<(), AppError> {
+        if let Some(totp) = self.tables().totp.get_mut(&user_id) {
+            totp.last_used_step = step;
+        }
+
+        Ok(())
+    }
+
+    async fn use_recovery_code(&self, user_id: i64, code_hash: &str) -> Result<bool, AppError> {
+        let mut tables = self.tables();
+        let code = tables.recovery_codes.iter_mut().find(|code| {
+            code.user_id == user_id && code.code_hash == code_hash && code.used_at.is_none()
+        });
+
+        match code {
+            Some(code) => {
+                code.used_at = Some(Utc::now());
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn delete_totp(&self, user_id: i64) -> Result<(), AppError> {
+        let mut tables = self.tables();
+        tables.recovery_codes.retain(|code| code.user_id != user_id);
+        tables.totp.remove(&user_id);
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl PasswordResetRepository for MemoryRepository {
+    async fn create_password_reset(
+        &self,
+        user_id: i64,
+        created_by: i64,
+        token_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), AppError> {
+        let mut tables = self.tables();
+        tables.user(user_id)?;
+
+        tables
+            .password_resets
+            .retain(|reset| reset.user_id != user_id || reset.used_at.is_some());
+        tables.password_resets.push(PasswordReset {
+            token_hash: token_hash.to_string(),
+            user_id,
+            created_by: Some(created_by),
+            expires_at,
+            used_at: None,
+        });
+
+        Ok(())
+    }
+
+    async fn get_password_reset_user(&self, token_hash: &str) -> Result<i64, AppError> {
+        let now = Utc::now();
+        self.tables()
+            .password_resets
+            .iter()
+            .find(|reset| {
+                reset.token_hash == token_hash && reset.used_at.is_none() && reset.expires_at > now
+            })
+            .map(|reset| reset.user_id)
+            .ok_or(AppError::InvalidToken)
+    }
+
+    /// Atomic through the table lock.
+    async fn consume_password_reset(
+        &self,
+        token_hash: &str,
+        password_hash: &str,
+    ) -> Result<i64, AppError> {
+        let now = Utc::now();
+        let mut tables = self.tables();
+        let reset = tables
+            .password_resets
+            .iter_mut()
+            .find(|reset| {
+                reset.token_hash == token_hash && reset.used_at.is_none() && reset.expires_at > now
+            })
+            .ok_or(AppError::InvalidToken)?;
+        reset.used_at = Some(now);
+        let user_id = reset.user_id;
+
+        if let Some(row) = tables.users.get_mut(&user_id) {
+            row.user.password = password_hash.to_string();
+        }
+        for session in &mut tables.sessions {
+            if session.user_id == user_id && session.revoked_at.is_none() {
+                session.revoked_at = Some(now);
+            }
+        }
+
+        Ok(user_id)
+    }
+}
+
+#[async_trait]
+impl InviteRepository for MemoryRepository {
+    async fn create_invite(
+        &self,
+        code_hash: &str,
+        role: &Role,
+        max_uses: i64,
+        created_by: i64,
+        expires_at: DateTime<Utc>,
+    ) -> Result<InviteEntity, AppError> {
+        let mut tables = self.tables();
+        tables.check_role(role)?;
+        if tables.invites.iter().any(|row| row.code_hash == code_hash) {
+            return Err(AppError::InternalServerError);
+        }
+
+        let id = tables.next_invite_id;
+        tables.next_invite_id += 1;
+        let invite = InviteEntity {
+            id,
+            role: role.clone(),
+            max_uses,
+            uses: 0,
+            created_by: Some(created_by),
+            created_at: Utc::now(),
+            expires_at,
+        };
+        tables.invites.push(InviteRow {
+            invite: invite.clone(),
+            code_hash: code_hash.to_string(),
+            revoked_at: None,
+        });
+
+        Ok(invite)
+    }
+
+    async fn list_outstanding_invites(&self) -> Result<Vec<InviteEntity>, AppError> {
+        let now = Utc::now();
+        let invites = self
+            .tables()
+            .invites
+            .iter()
+            .filter(|row| row.is_usable(now))
+            .map(|row| row.invite.clone())
+            .collect();
+
+        Ok(invites)
+    }
+
+    async fn revoke_invite(&self, id: i64) -> Result<(), AppError> {
+        let mut tables = self.tables();
+        let row = tables
+            .invites
+            .iter_mut()
+            .find(|row| row.invite.id == id && row.revoked_at.is_none())
+            .ok_or(AppError::InvalidInvite)?;
+        row.revoked_at = Some(Utc::now());
+
+        Ok(())
+    }
+
+    async fn get_invite_role(&self, code_hash: &str) -> Result<Role, AppError> {
+        let now = Utc::now();
+        self.tables()
+            .invites
+            .iter()
+            .find(|row| row.code_hash == code_hash && row.is_usable(now))
+            .map(|row| row.invite.role.clone())
+            .ok_or(AppError::InvalidInvite)
+    }
+
+    /// Atomic through the table lock.
+    async fn use_invite(&self, code_hash: &str) -> Result<Role, AppError> {
+        let now = Utc::now();
+        let mut tables = self.tables();
+        let row = tables
+            .invites
+            .iter_mut()
+            .find(|row| row.code_hash == code_hash && row.is_usable(now))
+            .ok_or(AppError::InvalidInvite)?;
+        row.invite.uses += 1;
+
+        Ok(row.invite.role.clone())
+    }
+
+    async fn release_invite(&self, code_hash: &str) -> Result<(), AppError> {
+        let mut tables = self.tables();
+        if let Some(row) = tables
+            .invites
+            .iter_mut()
+            .find(|row| row.code_hash == code_hash && row.invite.uses > 0)
+        {
+            row.invite.uses -= 1;
+        }
+
+        Ok(())
+    }
+
+    async fn list_invites_created_by(&self, user_id: i64) -> Result<Vec<InviteEntity>, AppError> {
+        let invites = self
+            .tables()
+            .invites
+            .iter()
+            .filter(|row| row.invite.created_by == Some(user_id))
+            .map(|row| row.invite.clone())
+            .collect();
+
+        Ok(invites)
+    }
+}
+
+#[async_trait]
+impl IdentityRepository for MemoryRepository {
+    async fn get_identity_user(
+        &self,
+        provider: &str,
+        subject: &str,
+    ) -> Result<Option<i64>, AppError> {
+        let user_id = self
+            .tables()
+            .identities
+            .iter()
+            .find(|identity| identity.provider == provider && identity.subject == subject)
+            .map(|identity| identity.user_id);
+
+        Ok(user_id)
+    }
+
+    async fn link_identity(
+        &self,
+        provider: &str,
+        subject: &str,
+        user_id: i64,
+        email: Option<&str>,
+    ) -> Result<(), AppError> {
+        let mut tables = self.tables();
+        tables.user(user_id)?;
+        let linked = tables
+            .identities
+            .iter()
+            .any(|identity| identity.provider == provider && identity.subject == subject);
+        if linked {
+            return Err(AppError::InternalServerError);
+        }
+
+        let now = Utc::now();
+        tables.identities.push(IdentityEntity {
+            provider: provider.to_string(),
+            subject: subject.to_string(),
+            user_id,
+            email: email.map(str::to_string),
+            created_at: now,
+            last_login_at: now,
+        });
+
+        Ok(())
+    }
+
+    async fn touch_identity(
+        &self,
+        provider: &str,
+        subject: &str,
+        email: Option<&str>,
+    ) -> Result<(), AppError> {
+        let mut tables = self.tables();
+        let identity = tables
+            .identities
+            .iter_mut()
+            .find(|identity| identity.provider == provider && identity.subject == subject);
+        if let Some(identity) = identity {
+            identity.last_login_at = Utc::now();
+            if let Some(email) = email {
+                identity.email = Some(email.to_string());
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn list_user_identities(&self, user_id: i64) -> Result<Vec<IdentityEntity>, AppError> {
+        let identities = self
+            .tables()
+            .identities
+            .iter()
+            .filter(|identity| identity.user_id == user_id)
+            .cloned()
+            .collect();
+
+        Ok(identities)
+    }
+}
+
+#[async_trait]
+impl AuditRepository for MemoryRepository {
+    /// Entries are only ever pushed, nothing in this module removes or changes them.
+    async fn record_audit(&self, event: &AuditEvent) -> Result<(), AppError> {
+        let mut tables = self.tables();
+        let id = tables.audit_log.len() as i64 + 1;
+        tables.audit_log.push(AuditEntry {
+            id,
+            created_at: Utc::now(),
+            actor_id: event.actor_id,
+            actor_email: event.actor_email.clone(),
+            action: event.action,
+            target: event.target.clone(),
+            ip: event.ip.clone(),
+            user_agent: event.user_agent.clone(),
+            result: event.result,
+            detail: event.detail.clone(),
+        });
+
+        Ok(())
+    }
+
+    async fn count_audit(&self, filter: &AuditQuery) -> Result<i64, AppError> {
+        let count = self
+            .tables()
+            .audit_log
+            .iter()
+            .filter(|entry| audit_matches(entry, filter))
+            .count();
+
+        Ok(count as i64)
+    }
+
+    async fn list_audit(
+        &self,
+        filter: &AuditQuery,
+        before_id: Option<i64>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<AuditEntry>, AppError> {
+        let tables = self.tables();
+        let entries = tables
+            .audit_log
+            .iter()
+            .rev()
+            .filter(|entry| audit_matches(entry, filter))
+            .filter(|entry| before_id.is_none_or(|before_id| entry.id < before_id))
+            .cloned();
+
+        Ok(page(entries, limit, offset))
+    }
+}
+
+#[async_trait]
+impl DeviceRepository for MemoryRepository {
+    async fn get_device(
+        &self,
+        user_id: i64,
+        client_id: &str,
+    ) -> Result<Option<DeviceEntity>, AppError> {
+        Ok(self.tables().device_mut(user_id, client_id).cloned())
+    }
+
+    async fn upsert_device(
+        &self,
+        user_id: i64,
+        param: &DeviceRegistration,
+        ip: Option<IpAddr>,
+    ) -> Result<DeviceEntity, AppError> {
+        let now = Utc::now();
+        let mut tables = self.tables();
+        tables.user(user_id)?;
+
+        if tables.device_mut(user_id, &param.id).is_none() {
+            tables.devices.push(DeviceEntity {
+                client_id: param.id.clone(),
+                user_id,
+                name: param.name.clone(),
+                platform: None,
+                app_version: None,
+                first_seen_at: now,
+                last_seen_at: now,
+                last_ip: None,
+                revoked_at: None,
+                access: DeviceAccess::default(),
+                expires_at: None,
+            });
+        }
+
+        let device = tables
+            .device_mut(user_id, &param.id)
+            .ok_or(AppError::DeviceDoesNotExist)?;
+        if param.platform.is_some() {
+            device.platform = param.platform.clone();
+        }
+        if param.app_version.is_some() {
+            device.app_version = param.app_version.clone();
+        }
+        device.last_seen_at = now;
+        device.last_ip = ip.map(|ip| ip.to_string());
+
+        Ok(device.clone())
+    }
+
+    async fn create_guest_device(
+        &self,
+        user_id: i64,
+        client_id: &str,
+        name: &str,
+        access: DeviceAccess,
+        expires_at: DateTime<Utc>,
+    ) -> Result<DeviceEntity, AppError> {
+        let now = Utc::now();
+        let mut tables = self.tables();
+        tables.user(user_id)?;
+        if tables.device_mut(user_id, client_id).is_some() {
+            return Err(AppError::InternalServerError);
+        }
+
+        let device = DeviceEntity {
+            client_id: client_id.to_string(),
+            user_id,
+            name: name.to_string(),
+            platform: None,
+            app_version: None,
+            first_seen_at: now,
+            last_seen_at: now,
+            last_ip: None,
+            revoked_at: None,
+            access,
+            expires_at: Some(expires_at),
+        };
+        tables.devices.push(device.clone());
+
+        Ok(device)
+    }
+
+    async fn is_device_revoked(&self, user_id: i64, client_id: &str) -> Result<bool, AppError> {
+        let revoked = self
+            .tables()
+            .device_mut(user_id, client_id)
+            .is_some_and(|device| device.revoked_at.is_some());
+
+        Ok(revoked)
+    }
+
+    async fn touch_device(&self, user_id: i64, client_id: &str) -> Result<(), AppError> {
+        if let Some(device) = self.tables().device_mut(user_id, client_id) {
+            device.last_seen_at = Utc::now();
+        }
+
+        Ok(())
+    }
+
+    async fn list_devices(&self, user_id: i64) -> Result<Vec<DeviceEntity>, AppError> {
+        let mut devices: Vec<_> = self
+            .tables()
+            .devices
+            .iter()
+            .filter(|device| device.user_id == user_id)
+            .cloned()
+            .collect();
+        devices.sort_by_key(|device| std::cmp::Reverse(device.last_seen_at));
+
+        Ok(devices)
+    }
+
+    async fn rename_device(
+        &self,
+        user_id: i64,
+        client_id: &str,
+        name: &str,
+    ) -> Result<DeviceEntity, AppError> {
+        let mut tables = self.tables();
+        let device = tables
+            .device_mut(user_id, client_id)
+            .ok_or(AppError::DeviceDoesNotExist)?;
+        device.name = name.to_string();
+
+        Ok(device.clone())
+    }
+
+    async fn revoke_device(&self, user_id: i64, client_id: &str) -> Result<DeviceEntity, AppError> {
+        let mut tables = self.tables();
+        let device = tables
+            .device_mut(user_id, client_id)
+            .ok_or(AppError::DeviceDoesNotExist)?;
+        device.revoked_at.get_or_insert_with(Utc::now);
+
+        Ok(device.clone())
+    }
+}
+
+#[async_trait]
+impl DataKeyRepository for MemoryRepository {
+    async fn get_data_key(&self, user_id: i64) -> Result<Option<DataKeyEntity>, AppError> {
+        Ok(self.tables().data_keys.get(&user_id).cloned())
+    }
+
+    async fn insert_data_key(
+        &self,
+        user_id: i64,
+        master_key_id: &str,
+        wrapped_key: &[u8],
+    ) -> Result<DataKeyEntity, AppError> {
+        let mut tables = self.tables();
+        tables.user(user_id)?;
+        let data_key = tables
+            .data_keys
+            .entry(user_id)
+            .or_insert_with(|| DataKeyEntity {
+                user_id,
+                master_key_id: master_key_id.to_string(),
+                wrapped_key: wrapped_key.to_vec(),
+                created_at: Utc::now(),
+                rotated_at: None,
+            });
+
+        Ok(data_key.clone())
+    }
+
+    async fn list_data_keys_wrapped_by(
+        &self,
+        master_key_id: &str,
+        limit: i64,
+    ) -> Result<Vec<DataKeyEntity>, AppError> {
+        let tables = self.tables();
+        let data_keys = tables
+            .data_keys
+            .values()
+            .filter(|data_key| data_key.master_key_id == master_key_id)
+            .cloned();
+
+        Ok(page(data_keys, limit, 0))
+    }
+
+    async fn count_data_keys_not_wrapped_by(&self, master_key_id: &str) -> Result<i64, AppError> {
+        let count = self
+            .tables()
+            .data_keys
+            .values()
+            .filter(|data_key| data_key.master_key_id != master_key_id)
+            .count();
+
+        Ok(count as i64)
+    }
+
+    async fn rewrap_data_key(
+        &self,
+        user_id: i64,
+        old_master_key_id: &str,
+        new_master_key_id: &str,
+        wrapped_key: &[u8],
+    ) -> Result<bool, AppError> {
+        let mut tables = self.tables();
+        let data_key = tables
+            .data_keys
+            .get_mut(&user_id)
+            .filter(|data_key| data_key.master_key_id == old_master_key_id);
+
+        match data_key {
+            Some(data_key) => {
+                data_key.master_key_id = new_master_key_id.to_string();
+                data_key.wrapped_key = wrapped_key.to_vec();
+                data_key.rotated_at = Some(Utc::now());
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+#[async_trait]
+impl ClipRepository for MemoryRepository {
+    async fn insert_clip(&self, clip: &ClipEntity) -> Result<(), AppError> {
+        let mut tables = self.tables();
+        tables.user(clip.user_id)?;
+        tables.clips.push(clip.clone());
+
+        Ok(())
+    }
+
+    async fn queue_clip(
+        &self,
+        clip_id: &str,
+        user_id: i64,
+        client_id: &str,
+    ) -> Result<(), AppError> {
+        let mut tables = self.tables();
+        let queued = tables
+            .clip_queue
+            .iter()
+            .any(|queued| queued.clip_id == clip_id && queued.client_id == client_id);
+        if !queued {
+            tables.clip_queue.push(QueuedClipEntity {
+                clip_id: clip_id.to_string(),
+                user_id,
+                client_id: client_id.to_string(),
+                queued_at: Utc::now(),
+            });
+        }
+
+        Ok(())
+    }
+
+    async fn list_queued_clips(
+        &self,
+        user_id: i64,
+        client_id: &str,
+    ) -> Result<Vec<ClipEntity>, AppError> {
+        let tables = self.tables();
+        let queued: Vec<&str> = tables
+            .clip_queue
+            .iter()
+            .filter(|queued| queued.user_id == user_id && queued.client_id == client_id)
+            .map(|queued| queued.clip_id.as_str())
+            .collect();
+
+        Ok(tables
+            .clips
+            .iter()
+            .filter(|clip| queued.contains(&clip.id.as_str()))
+            .cloned()
+            .collect())
+    }
+
+    async fn dequeue_clip(&self, clip_id: &str, client_id: &str) -> Result<(), AppError> {
+        self.tables()
+            .clip_queue
+            .retain(|queued| !(queued.clip_id == clip_id && queued.client_id == client_id));
+
+        Ok(())
+    }
+
+    async fn list_clips(&self, user_id: i64) -> Result<Vec<ClipEntity>, AppError> {
+        Ok(self
+            .tables()
+            .clips
+            .iter()
+            .filter(|clip| clip.user_id == user_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn list_clip_queue(&self, user_id: i64) -> Result<Vec<QueuedClipEntity>, AppError> {
+        Ok(self
+            .tables()
+            .clip_queue
+            .iter()
+            .filter(|queued| queued.user_id == user_id)
+            .cloned()
+            .collect())
+    }
+}
+
+#[async_trait]
+impl BackupRepository for MemoryRepository {
+    async fn backup(&self, path: &Path) -> Result<(), AppError> {
+        Err(AppError::Backup(
+            "the in-memory backend cannot be backed up".to_string(),
+        ))
+    }
+}
+
+#[async_trait]
+impl RetentionRepository for MemoryRepository {
+    async fn list_user_retention(&self) -> Result<Vec<(i64, i64)>, AppError> {
+        let tables = self.tables();
+        Ok(tables
+            .user_retention
+            .iter()
+            .map(|(user_id, days)| (*user_id, *days))
+            .collect())
+    }
+
+    async fn get_user_retention(&self, user_id: i64) -> Result<Option<i64>, AppError> {
+        Ok(self.tables().user_retention.get(&user_id).copied())
+    }
+
+    async fn set_user_retention(&self, user_id: i64, days: Option<i64>) -> Result<(), AppError> {
+        let mut tables = self.tables();
+        tables.user(user_id)?;
+        match days {
+            Some(days) => tables.user_retention.insert(user_id, days),
+            None => tables.user_retention.remove(&user_id),
+        };
+
+        Ok(())
+    }
+
+    async fn purge_sessions(
+        &self,
+        user_id: Option<i64>,
+        before: DateTime<Utc>,
+    ) -> Result<u64, AppError> {
+        let mut tables = self.tables();
+        let tables = &mut *tables;
+        let count = tables.sessions.len();
+        tables.sessions.retain(|session| {
+            let ended = session.expires_at < before
+                || session
+                    .revoked_at
+                    .is_some_and(|revoked_at| revoked_at < before);
+            !(ended && is_purged(&tables.user_retention, user_id, session.user_id))
+        });
+
+        Ok((count - tables.sessions.len()) as u64)
+    }
+
+    async fn purge_password_resets(
+        &self,
+        user_id: Option<i64>,
+        before: DateTime<Utc>,
+    ) -> Result<u64, AppError> {
+        let mut tables = self.tables();
+        let tables = &mut *tables;
+        let count = tables.password_resets.len();
+        tables.password_resets.retain(|reset| {
+            let ended =
+                reset.expires_at < before || reset.used_at.is_some_and(|used_at| used_at < before);
+            !(ended && is_purged(&tables.user_retention, user_id, reset.user_id))
+        });
+
+        Ok((count - tables.password_resets.len()) as u64)
+    }
+
+    async fn purge_guest_devices(
+        &self,
+        user_id: Option<i64>,
+        before: DateTime<Utc>,
+    ) -> Result<u64, AppError> {
+        let mut tables = self.tables();
+        let tables = &mut *tables;
+        let count = tables.devices.len();
+        tables.devices.retain(|device| {
+            let ended = device
+                .expires_at
+                .is_some_and(|expires_at| expires_at < before);
+            !(ended && is_purged(&tables.user_retention, user_id, device.user_id))
+        });
+
+        Ok((count - tables.devices.len()) as u64)
+    }
+
+    async fn purge_clips(
+        &self,
+        user_id: Option<i64>,
+        before: DateTime<Utc>,
+    ) -> Result<u64, AppError> {
+        let mut tables = self.tables();
+        let tables = &mut *tables;
+        let count = tables.clips.len();
+        tables.clips.retain(|clip| {
+            !(clip.created_at < before && is_purged(&tables.user_retention, user_id, clip.user_id))
+        });
+        let clips = &tables.clips;
+        tables
+            .clip_queue
+            .retain(|queued| clips.iter().any(|clip| clip.id == queued.clip_id));
+
+        Ok((count - tables.clips.len()) as u64)
+    }
+
+    async fn purge_clip_queue(
+        &self,
+        user_id: Option<i64>,
+        before: DateTime<Utc>,
+    ) -> Result<u64, AppError> {
+        let mut tables = self.tables();
+        let tables = &mut *tables;
+        let count = tables.clip_queue.len();
+        tables.clip_queue.retain(|queued| {
+            !(queued.queued_at < before
+                && is_purged(&tables.user_retention, user_id, queued.user_id))
+        });
+
+        Ok((count - tables.clip_queue.len()) as u64)
+    }
+
+    async fn purge_invites(&self, before: DateTime<Utc>) -> Result<u64, AppError> {
+        let mut tables = self.tables();
+        let count = tables.invites.len();
+        tables.invites.retain(|row| {
+            row.invite.expires_at >= before
+                && row.revoked_at.is_none_or(|revoked_at| revoked_at >= before)
+        });
+
+        Ok((count - tables.invites.len()) as u64)
+    }
+
+    async fn vacuum(&self) -> Result<(), AppError> {
+        Ok(())
+    }
+}
@@ -0,0 +1,1769 @@
+use std::{
+    borrow::Cow,
+    io::{Cursor, Read},
+    net::IpAddr,
+    path::Path,
+    str::FromStr,
+};
+
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use sqlx::{
+    migrate::{MigrateDatabase, Migrator},
+    postgres::PgConnectOptions,
+    query, query_as, query_scalar, Connection, Executor, PgConnection, PgPool, Postgres,
+};
+
+use super::{
+    check_backup_migrations, check_schema, AuditRepository, BackupRepository, ClipRepository,
+    DataKeyRepository, DeviceRepository, IdentityRepository, InviteRepository,
+    PasswordResetRepository, RetentionRepository, RoleRepository, SessionRepository,
+    TotpRepository, UserRepository,
+};
+use crate::{
+    error::AppError,
+    models::{
+        audit::{AuditEntry, AuditEvent, AuditQuery},
+        clip::{ClipEntity, QueuedClipEntity},
+        device::{DeviceAccess, DeviceEntity, DeviceRegistration},
+        encryption::DataKeyEntity,
+        invite::InviteEntity,
+        oidc::IdentityEntity,
+        role::{Permission, RoleEntity, RoleUpsert},
+        session::SessionEntity,
+        totp::TotpEntity,
+        user::{Role, UserCreate, UserDetails, UserEntity, UserListQuery},
+    },
+    utils::generate_token,
+};
+
+// The compile time checked macros are bound to the SQLite database the crate is
+// built against: sqlx 0.6 keeps the offline data of a crate in one
+// `sqlx-data.json` prepared against a single database. The queries here are
+// checked by running the tests against a Postgres server instead, which CI does
+// in `.github/workflows/backend.yml`.
+
+const USER_COLUMNS: &str = "id, name, email, password, role";
+
+const USER_DETAILS_COLUMNS: &str = "id, email, name, role, auth_provider,
+    EXISTS(SELECT 1 FROM user_totp WHERE user_id = users.id AND enabled) AS totp_enabled,
+    disabled_at";
+
+const USER_FILTER: &str = "($1::TEXT IS NULL OR strpos(lower(email), lower($1)) > 0
+        OR strpos(lower(name), lower($1)) > 0)
+    AND ($2::TEXT IS NULL OR role = $2)
+    AND ($3::BOOLEAN IS NULL OR (disabled_at IS NOT NULL) = $3)";
+
+const INVITE_COLUMNS: &str = "id, role, max_uses, uses, created_by, created_at, expires_at";
+
+const AUDIT_FILTER: &str = "($1::BIGINT IS NULL OR actor_id = $1)
+    AND ($2::TEXT IS NULL OR actor_email = $2)
+    AND ($3::TEXT IS NULL OR action = $3)
+    AND ($4::TEXT IS NULL OR target = $4)
+    AND ($5::TEXT IS NULL OR result = $5)
+    AND ($6::TIMESTAMPTZ IS NULL OR created_at >= $6)
+    AND ($7::TIMESTAMPTZ IS NULL OR created_at < $7)";
+
+const DEVICE_COLUMNS: &str = "client_id, user_id, name, platform, app_version, first_seen_at,
+    last_seen_at, last_ip, revoked_at, access, expires_at";
+
+const DATA_KEY_COLUMNS: &str = "user_id, master_key_id, wrapped_key, created_at, rotated_at";
+
+const CLIP_COLUMNS: &str = "id, user_id, client_id, content, created_at";
+
+/// Rows of the user `$2`, or of every user without a retention of their own.
+const RETENTION_FILTER: &str = "(user_id = $2
+    OR ($2::BIGINT IS NULL AND user_id NOT IN (SELECT user_id FROM user_retention)))";
+
+/// Every table in an order that satisfies the foreign keys when restoring.
+const BACKUP_TABLES: [&str; 15] = [
+    "roles",
+    "role_permissions",
+    "users",
+    "user_totp",
+    "recovery_codes",
+    "sessions",
+    "password_reset_tokens",
+    "invites",
+    "user_identities",
+    "audit_log",
+    "devices",
+    "data_keys",
+    "user_retention",
+    "clips",
+    "clip_queue",
+];
+
+/// Tables with a `BIGSERIAL` id whose sequence continues after a restore.
+const SERIAL_TABLES: [&str; 4] = ["users", "recovery_codes", "invites", "audit_log"];
+
+/// Stored next to one file per table, named after the table.
+const BACKUP_MIGRATIONS_FILE: &str = "migrations.json";
+
+/// Selected by `postgres://` database urls.
+#[derive(Clone)]
+pub struct PostgresRepository {
+    pool: PgPool,
+}
+
+impl PostgresRepository {
+    pub async fn connect(db_url: &str) -> Result<Self, sqlx::Error> {
+        if !Postgres::database_exists(db_url).await? {
+            tracing::debug!("Database does not exist");
+            Postgres::create_database(db_url).await?;
+        }
+
+        let pool = PgPool::connect(db_url).await?;
+        sqlx::migrate!("migrations/postgres").run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+
+    /// Connects to the existing database without migrating it, see
+    /// [`super::open`].
+    pub async fn open(db_url: &str) -> Result<Self, sqlx::Error> {
+        let pool = PgPool::connect(db_url).await?;
+        let applied = query_as::<_, (i64, Vec<u8>)>(
+            "SELECT version, checksum FROM _sqlx_migrations WHERE success ORDER BY version",
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap_or_default();
+        check_schema(&sqlx::migrate!("migrations/postgres"), &applied)?;
+
+        Ok(Self { pool })
+    }
+
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+
+    /// Loads the backup into a staging database next to the one of `db_url`,
+    /// at the schema the backup was taken at, runs the newer migrations and
+    /// only then swaps it in. The database is left as it was if anything fails.
+    pub async fn restore(db_url: &str, path: &Path) -> Result<(), AppError> {
+        let not_a_backup =
+            || AppError::Backup(format!("{} is not a scytale backup", path.display()));
+        let archive = tokio::fs::read(path).await.map_err(|err| {
+            AppError::Backup(format!("unable to read {}: {}", path.display(), err))
+        })?;
+        let mut archive = zip::ZipArchive::new(Cursor::new(archive)).map_err(|_| not_a_backup())?;
+        let read_file = |archive: &mut zip::ZipArchive<_>, name: &str| {
+            let mut contents = Vec::new();
+            archive
+                .by_name(name)
+                .ok()?
+                .read_to_end(&mut contents)
+                .ok()?;
+            Some(contents)
+        };
+
+        let applied: Vec<(i64, Vec<u8>)> = read_file(&mut archive, BACKUP_MIGRATIONS_FILE)
+            .and_then(|contents| serde_json::from_slice(&contents).ok())
+            .ok_or_else(not_a_backup)?;
+        let migrator = sqlx::migrate!("migrations/postgres");
+        let backup_migrations = check_backup_migrations(&migrator, &applied)?;
+        let tables: Vec<_> = BACKUP_TABLES
+            .iter()
+            .filter_map(|table| read_file(&mut archive, table).map(|rows| (*table, rows)))
+            .collect();
+
+        let options = PgConnectOptions::from_str(db_url)?;
+        let database = options
+            .get_database()
+            .ok_or_else(|| AppError::Backup("the database url names no database".to_string()))?
+            .to_string();
+        let token = generate_token(8).to_lowercase();
+        let staging = format!("{}_restore_{}", database, token);
+        let mut server = PgConnection::connect_with(&options.clone().database("postgres")).await?;
+        server
+            .execute(format!("CREATE DATABASE {}", quote_identifier(&staging)).as_str())
+            .await?;
+
+        let pool = PgPool::connect_with(options.database(&staging)).await;
+        let result = match pool {
+            Ok(pool) => {
+                let result = Self::load_backup(&pool, &migrator, backup_migrations, &tables).await;
+                pool.close().await;
+                result
+            }
+            Err(err) => Err(err.into()),
+        };
+        let result = match result {
+            Ok(()) => {
+                let replaced = format!("{}_replaced_{}", database, token);
+                Self::swap_database(&mut server, &database, &staging, &replaced).await
+            }
+            Err(err) => Err(err),
+        };
+        if result.is_err() {
+            let drop = format!("DROP DATABASE IF EXISTS {}", quote_identifier(&staging));
+            if let Err(err) = server.execute(drop.as_str()).await {
+                tracing::error!("Unable to drop the staging database {}: {:?}", staging, err);
+            }
+        }
+
+        result
+    }
+
+    /// Migrates the empty database to the schema of the backup, loads the
+    /// tables and then runs the newer migrations.
+    async fn load_backup(
+        pool: &PgPool,
+        migrator: &Migrator,
+        backup_migrations: usize,
+        tables: &[(&str, Vec<u8>)],
+    ) -> Result<(), AppError> {
+        let mut backup_schema = sqlx::migrate!("migrations/postgres");
+        backup_schema.migrations = Cow::Owned(migrator.migrations[..backup_migrations].to_vec());
+        backup_schema
+            .run(pool)
+            .await
+            .map_err(|err| AppError::DatabaseError(err.into()))?;
+
+        let mut tx = pool.begin().await?;
+        // rows the migrations seeded, like the default roles, are in the backup,
+        // no TRUNCATE as the audit log refuses it even when empty
+        for (table, _) in tables.iter().rev() {
+            query(&format!("DELETE FROM {}", table))
+                .execute(&mut tx)
+                .await?;
+        }
+        for (table, rows) in tables {
+            let mut copy = tx
+                .copy_in_raw(&format!("COPY {} FROM STDIN", table))
+                .await?;
+            copy.send(rows.as_slice()).await?;
+            copy.finish().await?;
+        }
+        for table in SERIAL_TABLES
+            .iter()
+            .filter(|serial| tables.iter().any(|(table, _)| table == *serial))
+        {
+            query(&format!(
+                "SELECT setval(pg_get_serial_sequence('{0}', 'id'), COALESCE(MAX(id), 0) + 1, false)
+                FROM {0}",
+                table
+            ))
+            .execute(&mut tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        migrator
+            .run(pool)
+            .await
+            .map_err(|err| AppError::DatabaseError(err.into()))
+    }
+
+    /// Renames `staging` to `database` in one transaction, the database it
+    /// replaces is dropped afterwards.
+    async fn swap_database(
+        server: &mut PgConnection,
+        database: &str,
+        staging: &str,
+        replaced: &str,
+    ) -> Result<(), AppError> {
+        let mut tx = server.begin().await?;
+        let exists: bool =
+            query_scalar("SELECT EXISTS (SELECT 1 FROM pg_database WHERE datname = $1)")
+                .bind(database)
+                .fetch_one(&mut tx)
+                .await?;
+        if exists {
+            tx.execute(
+                format!(
+                    "ALTER DATABASE {} RENAME TO {}",
+                    quote_identifier(database),
+                    quote_identifier(replaced)
+                )
+                .as_str(),
+            )
+            .await?;
+        }
+        tx.execute(
+            format!(
+                "ALTER DATABASE {} RENAME TO {}",
+                quote_identifier(staging),
+                quote_identifier(database)
+            )
+            .as_str(),
+        )
+        .await?;
+        tx.commit().await?;
+
+        if exists {
+            // the restore went through, a leftover copy is only a warning
+            let drop = format!("DROP DATABASE {}", quote_identifier(replaced));
+            if let Err(err) = server.execute(drop.as_str()).await {
+                tracing::warn!(
+                    "Unable to drop the replaced database {}: {:?}",
+                    replaced,
+                    err
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Database names are identifiers and cannot be bound as parameters.
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+#[async_trait]
+impl UserRepository for PostgresRepository {
+    async fn create_user(&self, user: &UserCreate) -> Result<UserEntity, AppError> {
+        let user = query_as(&format!(
+            "INSERT INTO users (email, name, password, role) VALUES ($1, $2, $3, $4)
+            RETURNING {}",
+            USER_COLUMNS
+        ))
+        .bind(&user.email)
+        .bind(&user.name)
+        .bind(&user.password)
+        .bind(&user.role)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(user)
+    }
+
+    async fn get_user_by_id(&self, id: i64) -> Result<UserEntity, AppError> {
+        query_as(&format!("SELECT {} FROM users WHERE id = $1", USER_COLUMNS))
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or(AppError::UserDoesNotExist)
+    }
+
+    async fn get_user_by_id_email(&self, id: i64, email: &str) -> Result<UserEntity, AppError> {
+        query_as(&format!(
+            "SELECT {} FROM users WHERE id = $1 AND email = $2",
+            USER_COLUMNS
+        ))
+        .bind(id)
+        .bind(email)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(AppError::UserDoesNotExist)
+    }
+
+    async fn get_user_by_email(&self, email: &str) -> Result<Option<UserEntity>, AppError> {
+        let user = query_as(&format!(
+            "SELECT {} FROM users WHERE email = $1",
+            USER_COLUMNS
+        ))
+        .bind(email)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(user)
+    }
+
+    async fn update_user_password(&self, id: i64, password_hash: &str) -> Result<(), AppError> {
+        query("UPDATE users SET password = $1 WHERE id = $2")
+            .bind(password_hash)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn update_user_role(&self, id: i64, role: &Role) -> Result<(), AppError> {
+        query("UPDATE users SET role = $1 WHERE id = $2")
+            .bind(role)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_user_auth_provider(&self, email: &str) -> Result<Option<String>, AppError> {
+        let provider: Option<Option<String>> =
+            query_scalar("SELECT auth_provider FROM users WHERE email = $1")
+                .bind(email)
+                .fetch_optional(&self.pool)
+                .await?;
+
+        Ok(provider.flatten())
+    }
+
+    async fn set_user_auth_provider(
+        &self,
+        id: i64,
+        provider: Option<&str>,
+    ) -> Result<(), AppError> {
+        let result = query("UPDATE users SET auth_provider = $1 WHERE id = $2")
+            .bind(provider)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(AppError::UserDoesNotExist);
+        }
+
+        Ok(())
+    }
+
+    async fn is_user_disabled(&self, id: i64) -> Result<bool, AppError> {
+        let disabled = query_scalar("SELECT disabled_at IS NOT NULL FROM users WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        disabled.ok_or(AppError::UserDoesNotExist)
+    }
+
+    async fn count_users(&self, filter: &UserListQuery) -> Result<i64, AppError> {
+        let count = query_scalar(&format!("SELECT COUNT(*) FROM users WHERE {}", USER_FILTER))
+            .bind(&filter.q)
+            .bind(&filter.role)
+            .bind(filter.disabled)
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(count)
+    }
+
+    async fn list_users(
+        &self,
+        filter: &UserListQuery,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<UserDetails>, AppError> {
+        let users = query_as(&format!(
+            "SELECT {} FROM users WHERE {} ORDER BY id LIMIT $4 OFFSET $5",
+            USER_DETAILS_COLUMNS, USER_FILTER
+        ))
+        .bind(&filter.q)
+        .bind(&filter.role)
+        .bind(filter.disabled)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(users)
+    }
+
+    async fn get_user_details(&self, id: i64) -> Result<UserDetails, AppError> {
+        query_as(&format!(
+            "SELECT {} FROM users WHERE id = $1",
+            USER_DETAILS_COLUMNS
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(AppError::UserDoesNotExist)
+    }
+
+    async fn update_user_profile(
+        &self,
+        id: i64,
+        email: &str,
+        name: &str,
+        role: &Role,
+    ) -> Result<(), AppError> {
+        query("UPDATE users SET email = $1, name = $2, role = $3 WHERE id = $4")
+            .bind(email)
+            .bind(name)
+            .bind(role)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn set_user_disabled(&self, id: i64, disabled: bool) -> Result<(), AppError> {
+        let result = query("UPDATE users SET disabled_at = $1 WHERE id = $2")
+            .bind(disabled.then(Utc::now))
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(AppError::UserDoesNotExist);
+        }
+
+        Ok(())
+    }
+
+    async fn delete_user(&self, id: i64) -> Result<(), AppError> {
+        let result = query("DELETE FROM users WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(AppError::UserDoesNotExist);
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl RoleRepository for PostgresRepository {
+    async fn role_exists(&self, role: &Role) -> Result<bool, AppError> {
+        let exists = query_scalar("SELECT EXISTS(SELECT 1 FROM roles WHERE name = $1)")
+            .bind(role)
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(exists)
+    }
+
+    async fn get_role_permissions(&self, role: &Role) -> Result<Vec<Permission>, AppError> {
+        let permissions = query_scalar("SELECT permission FROM role_permissions WHERE role = $1")
+            .bind(role)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(permissions)
+    }
+
+    async fn has_permission(&self, role: &Role, permission: Permission) -> Result<bool, AppError> {
+        let exists = query_scalar(
+            "SELECT EXISTS(SELECT 1 FROM role_permissions WHERE role = $1 AND permission = $2)",
+        )
+        .bind(role)
+        .bind(permission)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(exists)
+    }
+
+    async fn get_role(&self, role: &Role) -> Result<RoleEntity, AppError> {
+        let (name, description): (Role, String) =
+            query_as("SELECT name, description FROM roles WHERE name = $1")
+                .bind(role)
+                .fetch_optional(&self.pool)
+                .await?
+                .ok_or(AppError::RoleDoesNotExist)?;
+
+        let permissions = self.get_role_permissions(&name).await?;
+
+        Ok(RoleEntity {
+            name,
+            description,
+            permissions,
+        })
+    }
+
+    async fn list_roles(&self) -> Result<Vec<RoleEntity>, AppError> {
+        let names: Vec<Role> = query_scalar("SELECT name FROM roles ORDER BY name")
+            .fetch_all(&self.pool)
+            .await?;
+
+        let mut roles = Vec::with_capacity(names.len());
+        for name in names {
+            roles.push(self.get_role(&name).await?);
+        }
+
+        Ok(roles)
+    }
+
+    async fn upsert_role(&self, role: &Role, payload: &RoleUpsert) -> Result<RoleEntity, AppError> {
+        let mut tx = self.pool.begin().await?;
+
+        query(
+            "INSERT INTO roles (name, description) VALUES ($1, $2)
+            ON CONFLICT (name) DO UPDATE SET description = excluded.description",
+        )
+        .bind(role)
+        .bind(&payload.description)
+        .execute(&mut tx)
+        .await?;
+
+        query("DELETE FROM role_permissions WHERE role = $1")
+            .bind(role)
+            .execute(&mut tx)
+            .await?;
+
+        for permission in &payload.permissions {
+            query(
+                "INSERT INTO role_permissions (role, permission) VALUES ($1, $2)
+                ON CONFLICT DO NOTHING",
+            )
+            .bind(role)
+            .bind(permission)
+            .execute(&mut tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        self.get_role(role).await
+    }
+
+    async fn delete_role(&self, role: &Role) -> Result<(), AppError> {
+        let in_use: bool = query_scalar("SELECT EXISTS(SELECT 1 FROM users WHERE role = $1)")
+            .bind(role)
+            .fetch_one(&self.pool)
+            .await?;
+
+        if in_use {
+            return Err(AppError::RoleInUse);
+        }
+
+        let result = query("DELETE FROM roles WHERE name = $1")
+            .bind(role)
+            .execute(&self.pool)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(AppError::RoleDoesNotExist);
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SessionRepository for PostgresRepository {
+    async fn create_session_until(
+        &self,
+        user_id: i64,
+        client_id: Option<&str>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<String, AppError> {
+        let id = generate_token(32);
+
+        query(
+            "INSERT INTO sessions (id, user_id, created_at, expires_at, client_id)
+            VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(&id)
+        .bind(user_id)
+        .bind(Utc::now())
+        .bind(expires_at)
+        .bind(client_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(id)
+    }
+
+    async fn is_session_active(&self, id: &str, user_id: i64) -> Result<bool, AppError> {
+        let active = query_scalar(
+            "SELECT EXISTS(SELECT 1 FROM sessions
+                WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL AND expires_at > $3)",
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(Utc::now())
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(active)
+    }
+
+    async fn extend_session(&self, id: &str, expires_at: DateTime<Utc>) -> Result<(), AppError> {
+        query("UPDATE sessions SET expires_at = $1 WHERE id = $2 AND revoked_at IS NULL")
+            .bind(expires_at)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn revoke_user_sessions(&self, user_id: i64) -> Result<u64, AppError> {
+        let result =
+            query("UPDATE sessions SET revoked_at = $1 WHERE user_id = $2 AND revoked_at IS NULL")
+                .bind(Utc::now())
+                .bind(user_id)
+                .execute(&self.pool)
+                .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn revoke_other_sessions(&self, user_id: i64, keep: &str) -> Result<u64, AppError> {
+        let result = query(
+            "UPDATE sessions SET revoked_at = $1
+            WHERE user_id = $2 AND id != $3 AND revoked_at IS NULL",
+        )
+        .bind(Utc::now())
+        .bind(user_id)
+        .bind(keep)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn revoke_device_sessions(&self, user_id: i64, client_id: &str) -> Result<u64, AppError> {
+        let result = query(
+            "UPDATE sessions SET revoked_at = $1
+            WHERE user_id = $2 AND client_id = $3 AND revoked_at IS NULL",
+        )
+        .bind(Utc::now())
+        .bind(user_id)
+        .bind(client_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn bind_session_client(
+        &self,
+        id: &str,
+        user_id: i64,
+        client_id: &str,
+    ) -> Result<bool, AppError> {
+        let result = query(
+            "UPDATE sessions SET client_id = $1 WHERE id = $2 AND user_id = $3 AND (client_id = $1
+                OR client_id IS NULL AND NOT EXISTS (SELECT 1 FROM sessions AS other
+                    WHERE other.user_id = $3 AND other.client_id = $1
+                    AND other.revoked_at IS NULL AND other.expires_at > $4))",
+        )
+        .bind(client_id)
+        .bind(id)
+        .bind(user_id)
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn list_user_sessions(&self, user_id: i64) -> Result<Vec<SessionEntity>, AppError> {
+        let sessions = query_as(
+            "SELECT id, user_id, created_at, expires_at, revoked_at, client_id
+            FROM sessions WHERE user_id = $1 ORDER BY created_at",
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(sessions)
+    }
+}
+
+#[async_trait]
+impl TotpRepository for PostgresRepository {
+    async fn get_totp(&self, user_id: i64) -> Result<Option<TotpEntity>, AppError> {
+        let totp = query_as(
+            "SELECT user_id, secret, enabled, last_used_step FROM user_totp WHERE user_id = $1",
+        )
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(totp)
+    }
+
+    async fn set_pending_totp(&self, user_id: i64, secret: &str) -> Result<(), AppError> {
+        query(
+            "INSERT INTO user_totp (user_id, secret, enabled, last_used_step)
+            VALUES ($1, $2, FALSE, 0)
+            ON CONFLICT (user_id) DO UPDATE
+                SET secret = excluded.secret, enabled = FALSE, last_used_step = 0",
+        )
+        .bind(user_id)
+        .bind(secret)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn enable_totp(
+        &self,
+        user_id: i64,
+        step: i64,
+        code_hashes: &[String],
+    ) -> Result<(), AppError> {
+        let mut tx = self.pool.begin().await?;
+
+        query("UPDATE user_totp SET enabled = TRUE, last_used_step = $1 WHERE user_id = $2")
+            .bind(step)
+            .bind(user_id)
+            .execute(&mut tx)
+            .await?;
+
+        query("DELETE FROM recovery_codes WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut tx)
+            .await?;
+
+        for code_hash in code_hashes {
+            query("INSERT INTO recovery_codes (user_id, code_hash) VALUES ($1, $2)")
+                .bind(user_id)
+                .bind(code_hash)
+                .execute(&mut tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn set_totp_last_step(&self, user_id: i64, step: i64) -> Result<(), AppError> {
+        query("UPDATE user_totp SET last_used_step = $1 WHERE user_id = $2")
+            .bind(step)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn use_recovery_code(&self, user_id: i64, code_hash: &str) -> Result<bool, AppError> {
+        let result = query(
+            "UPDATE recovery_codes SET used_at = CURRENT_TIMESTAMP
+            WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL",
+        )
+        .bind(user_id)
+        .bind(code_hash)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_totp(&self, user_id: i64) -> Result<(), AppError> {
+        let mut tx = self.pool.begin().await?;
+
+        query("DELETE FROM recovery_codes WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut tx)
+            .await?;
+
+        query("DELETE FROM user_totp WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl PasswordResetRepository for PostgresRepository {
+    async fn create_password_reset(
+        &self,
+        user_id: i64,
+        created_by: i64,
+        token_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), AppError> {
+        let mut tx = self.pool.begin().await?;
+
+        query("DELETE FROM password_reset_tokens WHERE user_id = $1 AND used_at IS NULL")
+            .bind(user_id)
+            .execute(&mut tx)
+            .await?;
+
+        query(
+            "INSERT INTO password_reset_tokens (token_hash, user_id, created_by, created_at, expires_at)
+            VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(token_hash)
+        .bind(user_id)
+        .bind(created_by)
+        .bind(Utc::now())
+        .bind(expires_at)
+        .execute(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn get_password_reset_user(&self, token_hash: &str) -> Result<i64, AppError> {
+        let user_id: Option<i64> = query_scalar(
+            "SELECT user_id FROM password_reset_tokens
+            WHERE token_hash = $1 AND used_at IS NULL AND expires_at > $2",
+        )
+        .bind(token_hash)
+        .bind(Utc::now())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        user_id.ok_or(AppError::InvalidToken)
+    }
+
+    async fn consume_password_reset(
+        &self,
+        token_hash: &str,
+        password_hash: &str,
+    ) -> Result<i64, AppError> {
+        let now = Utc::now();
+        let mut tx = self.pool.begin().await?;
+
+        let user_id: i64 = query_scalar(
+            "UPDATE password_reset_tokens SET used_at = $1
+            WHERE token_hash = $2 AND used_at IS NULL AND expires_at > $1
+            RETURNING user_id",
+        )
+        .bind(now)
+        .bind(token_hash)
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or(AppError::InvalidToken)?;
+
+        query("UPDATE users SET password = $1 WHERE id = $2")
+            .bind(password_hash)
+            .bind(user_id)
+            .execute(&mut tx)
+            .await?;
+
+        query("UPDATE sessions SET revoked_at = $1 WHERE user_id = $2 AND revoked_at IS NULL")
+            .bind(now)
+            .bind(user_id)
+            .execute(&mut tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(user_id)
+    }
+}
+
+#[async_trait]
+impl InviteRepository for PostgresRepository {
+    async fn create_invite(
+        &self,
+        code_hash: &str,
+        role: &Role,
+        max_uses: i64,
+        created_by: i64,
+        expires_at: DateTime<Utc>,
+    ) -> Result<InviteEntity, AppError> {
+        let invite = query_as(&format!(
+            "INSERT INTO invites (code_hash, role, max_uses, created_by, created_at, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING {}",
+            INVITE_COLUMNS
+        ))
+        .bind(code_hash)
+        .bind(role)
+        .bind(max_uses)
+        .bind(created_by)
+        .bind(Utc::now())
+        .bind(expires_at)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(invite)
+    }
+
+    async fn list_outstanding_invites(&self) -> Result<Vec<InviteEntity>, AppError> {
+        let invites = query_as(&format!(
+            "SELECT {} FROM invites
+            WHERE revoked_at IS NULL AND expires_at > $1 AND uses < max_uses
+            ORDER BY id",
+            INVITE_COLUMNS
+        ))
+        .bind(Utc::now())
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(invites)
+    }
+
+    async fn revoke_invite(&self, id: i64) -> Result<(), AppError> {
+        let result =
+            query("UPDATE invites SET revoked_at = $1 WHERE id = $2 AND revoked_at IS NULL")
+                .bind(Utc::now())
+                .bind(id)
+                .execute(&self.pool)
+                .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(AppError::InvalidInvite);
+        }
+
+        Ok(())
+    }
+
+    async fn get_invite_role(&self, code_hash: &str) -> Result<Role, AppError> {
+        let role: Option<Role> = query_scalar(
+            "SELECT role FROM invites
+            WHERE code_hash = $1 AND revoked_at IS NULL AND expires_at > $2 AND uses < max_uses",
+        )
+        .bind(code_hash)
+        .bind(Utc::now())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        role.ok_or(AppError::InvalidInvite)
+    }
+
+    async fn use_invite(&self, code_hash: &str) -> Result<Role, AppError> {
+        let role: Option<Role> = query_scalar(
+            "UPDATE invites SET uses = uses + 1
+            WHERE code_hash = $1 AND revoked_at IS NULL AND expires_at > $2 AND uses < max_uses
+            RETURNING role",
+        )
+        .bind(code_hash)
+        .bind(Utc::now())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        role.ok_or(AppError::InvalidInvite)
+    }
+
+    async fn release_invite(&self, code_hash: &str) -> Result<(), AppError> {
+        query("UPDATE invites SET uses = uses - 1 WHERE code_hash = $1 AND uses > 0")
+            .bind(code_hash)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn list_invites_created_by(&self, user_id: i64) -> Result<Vec<InviteEntity>, AppError> {
+        let invites = query_as(&format!(
+            "SELECT {} FROM invites WHERE created_by = $1 ORDER BY id",
+            INVITE_COLUMNS
+        ))
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(invites)
+    }
+}
+
+#[async_trait]
+impl IdentityRepository for PostgresRepository {
+    async fn get_identity_user(
+        &self,
+        provider: &str,
+        subject: &str,
+    ) -> Result<Option<i64>, AppError> {
+        let user_id = query_scalar(
+            "SELECT user_id FROM user_identities WHERE provider = $1 AND subject = $2",
+        )
+        .bind(provider)
+        .bind(subject)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(user_id)
+    }
+
+    async fn link_identity(
+        &self,
+        provider: &str,
+        subject: &str,
+        user_id: i64,
+        email: Option<&str>,
+    ) -> Result<(), AppError> {
+        query(
+            "INSERT INTO user_identities (provider, subject, user_id, email, created_at, last_login_at)
+            VALUES ($1, $2, $3, $4, $5, $5)",
+        )
+        .bind(provider)
+        .bind(subject)
+        .bind(user_id)
+        .bind(email)
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn touch_identity(
+        &self,
+        provider: &str,
+        subject: &str,
+        email: Option<&str>,
+    ) -> Result<(), AppError> {
+        query(
+            "UPDATE user_identities SET last_login_at = $1, email = COALESCE($2, email)
+            WHERE provider = $3 AND subject = $4",
+        )
+        .bind(Utc::now())
+        .bind(email)
+        .bind(provider)
+        .bind(subject)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn list_user_identities(&self, user_id: i64) -> Result<Vec<IdentityEntity>, AppError> {
+        let identities = query_as(
+            "SELECT provider, subject, user_id, email, created_at, last_login_at
+            FROM user_identities WHERE user_id = $1",
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(identities)
+    }
+}
+
+#[async_trait]
+impl AuditRepository for PostgresRepository {
+    async fn record_audit(&self, event: &AuditEvent) -> Result<(), AppError> {
+        query(
+            "INSERT INTO audit_log
+                (created_at, actor_id, actor_email, action, target, ip, user_agent, result, detail)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+        )
+        .bind(Utc::now())
+        .bind(event.actor_id)
+        .bind(&event.actor_email)
+        .bind(event.action)
+        .bind(&event.target)
+        .bind(&event.ip)
+        .bind(&event.user_agent)
+        .bind(event.result)
+        .bind(&event.detail)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn count_audit(&self, filter: &AuditQuery) -> Result<i64, AppError> {
+        let count = query_scalar(&format!(
+            "SELECT COUNT(*) FROM audit_log WHERE {}",
+            AUDIT_FILTER
+        ))
+        .bind(filter.actor_id)
+        .bind(&filter.actor_email)
+        .bind(filter.action)
+        .bind(&filter.target)
+        .bind(filter.result)
+        .bind(filter.since)
+        .bind(filter.until)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(count)
+    }
+
+    /// `LIMIT NULL` is no limit in Postgres.
+    async fn list_audit(
+        &self,
+        filter: &AuditQuery,
+        before_id: Option<i64>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<AuditEntry>, AppError> {
+        let entries = query_as(&format!(
+            "SELECT id, created_at, actor_id, actor_email, action, target, ip, user_agent,
+                result, detail
+            FROM audit_log
+            WHERE {} AND ($8::BIGINT IS NULL OR id < $8)
+            ORDER BY id DESC
+            LIMIT $9 OFFSET $10",
+            AUDIT_FILTER
+        ))
+        .bind(filter.actor_id)
+        .bind(&filter.actor_email)
+        .bind(filter.action)
+        .bind(&filter.target)
+        .bind(filter.result)
+        .bind(filter.since)
+        .bind(filter.until)
+        .bind(before_id)
+        .bind((limit >= 0).then_some(limit))
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(entries)
+    }
+}
+
+#[async_trait]
+impl DeviceRepository for PostgresRepository {
+    async fn get_device(
+        &self,
+        user_id: i64,
+        client_id: &str,
+    ) -> Result<Option<DeviceEntity>, AppError> {
+        let device = query_as(&format!(
+            "SELECT {} FROM devices WHERE user_id = $1 AND client_id = $2",
+            DEVICE_COLUMNS
+        ))
+        .bind(user_id)
+        .bind(client_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(device)
+    }
+
+    async fn upsert_device(
+        &self,
+        user_id: i64,
+        param: &DeviceRegistration,
+        ip: Option<IpAddr>,
+    ) -> Result<DeviceEntity, AppError> {
+        let device = query_as(&format!(
+            "INSERT INTO devices (user_id, client_id, name, platform, app_version, first_seen_at,
+                last_seen_at, last_ip)
+            VALUES ($1, $2, $3, $4, $5, $6, $6, $7)
+            ON CONFLICT (user_id, client_id) DO UPDATE SET
+                platform = COALESCE(excluded.platform, devices.platform),
+                app_version = COALESCE(excluded.app_version, devices.app_version),
+                last_seen_at = excluded.last_seen_at, last_ip = excluded.last_ip
+            RETURNING {}",
+            DEVICE_COLUMNS
+        ))
+        .bind(user_id)
+        .bind(&param.id)
+        .bind(&param.name)
+        .bind(&param.platform)
+        .bind(&param.app_version)
+        .bind(Utc::now())
+        .bind(ip.map(|ip| ip.to_string()))
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(device)
+    }
+
+    async fn create_guest_device(
+        &self,
+        user_id: i64,
+        client_id: &str,
+        name: &str,
+        access: DeviceAccess,
+        expires_at: DateTime<Utc>,
+    ) -> Result<DeviceEntity, AppError> {
+        let device = query_as(&format!(
+            "INSERT INTO devices
+                (user_id, client_id, name, first_seen_at, last_seen_at, access, expires_at)
+            VALUES ($1, $2, $3, $4, $4, $5, $6)
+            RETURNING {}",
+            DEVICE_COLUMNS
+        ))
+        .bind(user_id)
+        .bind(client_id)
+        .bind(name)
+        .bind(Utc::now())
+        .bind(access)
+        .bind(expires_at)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(device)
+    }
+
+    async fn is_device_revoked(&self, user_id: i64, client_id: &str) -> Result<bool, AppError> {
+        let revoked = query_scalar(
+            "SELECT EXISTS(SELECT 1 FROM devices
+                WHERE user_id = $1 AND client_id = $2 AND revoked_at IS NOT NULL)",
+        )
+        .bind(user_id)
+        .bind(client_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(revoked)
+    }
+
+    async fn touch_device(&self, user_id: i64, client_id: &str) -> Result<(), AppError> {
+        query("UPDATE devices SET last_seen_at = $1 WHERE user_id = $2 AND client_id = $3")
+            .bind(Utc::now())
+            .bind(user_id)
+            .bind(client_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn list_devices(&self, user_id: i64) -> Result<Vec<DeviceEntity>, AppError> {
+        let devices = query_as(&format!(
+            "SELECT {} FROM devices WHERE user_id = $1 ORDER BY last_seen_at DESC",
+            DEVICE_COLUMNS
+        ))
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(devices)
+    }
+
+    async fn rename_device(
+        &self,
+        user_id: i64,
+        client_id: &str,
+        name: &str,
+    ) -> Result<DeviceEntity, AppError> {
+        query_as(&format!(
+            "UPDATE devices SET name = $1 WHERE user_id = $2 AND client_id = $3 RETURNING {}",
+            DEVICE_COLUMNS
+        ))
+        .bind(name)
+        .bind(user_id)
+        .bind(client_id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(AppError::DeviceDoesNotExist)
+    }
+
+    async fn revoke_device(&self, user_id: i64, client_id: &str) -> Result<DeviceEntity, AppError> {
+        query_as(&format!(
+            "UPDATE devices SET revoked_at = COALESCE(revoked_at, $1)
+            WHERE user_id = $2 AND client_id = $3
+            RETURNING {}",
+            DEVICE_COLUMNS
+        ))
+        .bind(Utc::now())
+        .bind(user_id)
+        .bind(client_id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(AppError::DeviceDoesNotExist)
+    }
+}
+
+#[async_trait]
+impl DataKeyRepository for PostgresRepository {
+    async fn get_data_key(&self, user_id: i64) -> Result<Option<DataKeyEntity>, AppError> {
+        let data_key = query_as(&format!(
+            "SELECT {} FROM data_keys WHERE user_id = $1",
+            DATA_KEY_COLUMNS
+        ))
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(data_key)
+    }
+
+    async fn insert_data_key(
+        &self,
+        user_id: i64,
+        master_key_id: &str,
+        wrapped_key: &[u8],
+    ) -> Result<DataKeyEntity, AppError> {
+        query(
+            "INSERT INTO data_keys (user_id, master_key_id, wrapped_key, created_at)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (user_id) DO NOTHING",
+        )
+        .bind(user_id)
+        .bind(master_key_id)
+        .bind(wrapped_key)
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await?;
+
+        self.get_data_key(user_id)
+            .await?
+            .ok_or(AppError::UserDoesNotExist)
+    }
+
+    async fn list_data_keys_wrapped_by(
+        &self,
+        master_key_id: &str,
+        limit: i64,
+    ) -> Result<Vec<DataKeyEntity>, AppError> {
+        let data_keys = query_as(&format!(
+            "SELECT {} FROM data_keys WHERE master_key_id = $1 ORDER BY user_id LIMIT $2",
+            DATA_KEY_COLUMNS
+        ))
+        .bind(master_key_id)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(data_keys)
+    }
+
+    async fn count_data_keys_not_wrapped_by(&self, master_key_id: &str) -> Result<i64, AppError> {
+        let count = query_scalar("SELECT COUNT(*) FROM data_keys WHERE master_key_id <> $1")
+            .bind(master_key_id)
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(count)
+    }
+
+    async fn rewrap_data_key(
+        &self,
+        user_id: i64,
+        old_master_key_id: &str,
+        new_master_key_id: &str,
+        wrapped_key: &[u8],
+    ) -> Result<bool, AppError> {
+        let result = query(
+            "UPDATE data_keys SET master_key_id = $1, wrapped_key = $2, rotated_at = $3
+            WHERE user_id = $4 AND master_key_id = $5",
+        )
+        .bind(new_master_key_id)
+        .bind(wrapped_key)
+        .bind(Utc::now())
+        .bind(user_id)
+        .bind(old_master_key_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[async_trait]
+impl ClipRepository for PostgresRepository {
+    async fn insert_clip(&self, clip: &ClipEntity) -> Result<(), AppError> {
+        query(
+            "INSERT INTO clips (id, user_id, client_id, content, created_at)
+                VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(&clip.id)
+        .bind(clip.user_id)
+        .bind(&clip.client_id)
+        .bind(&clip.content)
+        .bind(clip.created_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn queue_clip(
+        &self,
+        clip_id: &str,
+        user_id: i64,
+        client_id: &str,
+    ) -> Result<(), AppError> {
+        query(
+            "INSERT INTO clip_queue (clip_id, user_id, client_id, queued_at)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (clip_id, client_id) DO NOTHING",
+        )
+        .bind(clip_id)
+        .bind(user_id)
+        .bind(client_id)
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn list_queued_clips(
+        &self,
+        user_id: i64,
+        client_id: &str,
+    ) -> Result<Vec<ClipEntity>, AppError> {
+        let clips = query_as(&format!(
+            "SELECT {} FROM clips WHERE id IN (
+                SELECT clip_id FROM clip_queue WHERE user_id = $1 AND client_id = $2
+            ) ORDER BY created_at",
+            CLIP_COLUMNS
+        ))
+        .bind(user_id)
+        .bind(client_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(clips)
+    }
+
+    async fn dequeue_clip(&self, clip_id: &str, client_id: &str) -> Result<(), AppError> {
+        query("DELETE FROM clip_queue WHERE clip_id = $1 AND client_id = $2")
+            .bind(clip_id)
+            .bind(client_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn list_clips(&self, user_id: i64) -> Result<Vec<ClipEntity>, AppError> {
+        let clips = query_as(&format!(
+            "SELECT {} FROM clips WHERE user_id = $1 ORDER BY created_at",
+            CLIP_COLUMNS
+        ))
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(clips)
+    }
+
+    async fn list_clip_queue(&self, user_id: i64) -> Result<Vec<QueuedClipEntity>, AppError> {
+        let queue = query_as(
+            "SELECT clip_id, user_id, client_id, queued_at FROM clip_queue
+                WHERE user_id = $1 ORDER BY queued_at",
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(queue)
+    }
+}
+
+#[async_trait]
+impl BackupRepository for PostgresRepository {
+    /// A zip archive with the applied migrations and a `COPY` of every table,
+    /// all read from the same snapshot.
+    async fn backup(&self, path: &Path) -> Result<(), AppError> {
+        // created up front so that nothing is overwritten and only the owner
+        // can read the password hashes in it
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let file = options.open(path).map_err(|err| {
+            AppError::Backup(format!("unable to create {}: {}", path.display(), err))
+        })?;
+
+        // the tables are copied out straight into the archive, which is
+        // written on a blocking thread
+        let (sender, receiver) = tokio::sync::mpsc::channel(16);
+        let writer = tokio::task::spawn_blocking(move || write_archive(file, receiver));
+        let copied = self.copy_out(&sender).await;
+        drop(sender);
+        let result = writer
+            .await
+            .map_err(|err| AppError::Backup(err.to_string()))
+            .and_then(|written| {
+                written.map_err(|err| {
+                    AppError::Backup(format!("unable to write {}: {}", path.display(), err))
+                })
+            })
+            .and(copied);
+        if result.is_err() {
+            let _ = tokio::fs::remove_file(path).await;
+        }
+
+        result
+    }
+}
+
+/// Part of an archive sent to [`write_archive`].
+enum ArchiveChunk {
+    File(&'static str),
+    Data(Vec<u8>),
+}
+
+impl PostgresRepository {
+    /// Sends the applied migrations and the rows of [`BACKUP_TABLES`], read in
+    /// one snapshot, to the archive writer.
+    async fn copy_out(
+        &self,
+        sender: &tokio::sync::mpsc::Sender<ArchiveChunk>,
+    ) -> Result<(), AppError> {
+        let send = |chunk| async move {
+            sender
+                .send(chunk)
+                .await
+                .map_err(|_| AppError::Backup("the archive writer stopped".to_string()))
+        };
+
+        let mut tx = self.pool.begin().await?;
+        query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY")
+            .execute(&mut tx)
+            .await?;
+
+        let applied: Vec<(i64, Vec<u8>)> = query_as(
+            "SELECT version, checksum FROM _sqlx_migrations WHERE success ORDER BY version",
+        )
+        .fetch_all(&mut tx)
+        .await?;
+        let applied = serde_json::to_vec(&applied).map_err(|err| {
+            AppError::Backup(format!("unable to serialize the migrations: {}", err))
+        })?;
+        send(ArchiveChunk::File(BACKUP_MIGRATIONS_FILE)).await?;
+        send(ArchiveChunk::Data(applied)).await?;
+
+        for table in BACKUP_TABLES {
+            send(ArchiveChunk::File(table)).await?;
+            let mut stream = tx
+                .copy_out_raw(&format!("COPY {} TO STDOUT", table))
+                .await?;
+            while let Some(chunk) = stream.next().await {
+                send(ArchiveChunk::Data(chunk?.to_vec())).await?;
+            }
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+/// Writes the chunks received from [`PostgresRepository::copy_out`] into a
+/// zip archive in `file`, finishing it once the sender is gone.
+fn write_archive(
+    file: std::fs::File,
+    mut receiver: tokio::sync::mpsc::Receiver<ArchiveChunk>,
+) -> zip::result::ZipResult<()> {
+    let mut archive = zip::ZipWriter::new(std::io::BufWriter::new(file));
+    let options =
+        zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    while let Some(chunk) = receiver.blocking_recv() {
+        match chunk {
+            ArchiveChunk::File(name) => archive.start_file(name, options)?,
+            ArchiveChunk::Data(data) => std::io::Write::write_all(&mut archive, &data)?,
+        }
+    }
+    std::io::Write::flush(&mut archive.finish()?)?;
+
+    Ok(())
+}
+
+#[async_trait]
+impl RetentionRepository for PostgresRepository {
+    async fn list_user_retention(&self) -> Result<Vec<(i64, i64)>, AppError> {
+        let rows = query_as("SELECT user_id, days FROM user_retention ORDER BY user_id")
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(rows)
+    }
+
+    async fn get_user_retention(&self, user_id: i64) -> Result<Option<i64>, AppError> {
+        let days = query_scalar("SELECT days FROM user_retention WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(days)
+    }
+
+    async fn set_user_retention(&self, user_id: i64, days: Option<i64>) -> Result<(), AppError> {
+        match days {
+            Some(days) => {
+                query(
+                    "INSERT INTO user_retention (user_id, days) VALUES ($1, $2)
+                    ON CONFLICT (user_id) DO UPDATE SET days = excluded.days",
+                )
+                .bind(user_id)
+                .bind(days)
+                .execute(&self.pool)
+                .await?;
+            }
+            None => {
+                query("DELETE FROM user_retention WHERE user_id = $1")
+                    .bind(user_id)
+                    .execute(&self.pool)
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn purge_sessions(
+        &self,
+        user_id: Option<i64>,
+        before: DateTime<Utc>,
+    ) -> Result<u64, AppError> {
+        let result = query(&format!(
+            "DELETE FROM sessions WHERE (expires_at < $1 OR revoked_at < $1) AND {}",
+            RETENTION_FILTER
+        ))
+        .bind(before)
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn purge_password_resets(
+        &self,
+        user_id: Option<i64>,
+        before: DateTime<Utc>,
+    ) -> Result<u64, AppError> {
+        let result = query(&format!(
+            "DELETE FROM password_reset_tokens WHERE (expires_at < $1 OR used_at < $1) AND {}",
+            RETENTION_FILTER
+        ))
+        .bind(before)
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn purge_guest_devices(
+        &self,
+        user_id: Option<i64>,
+        before: DateTime<Utc>,
+    ) -> Result<u64, AppError> {
+        let result = query(&format!(
+            "DELETE FROM devices WHERE expires_at < $1 AND {}",
+            RETENTION_FILTER
+        ))
+        .bind(before)
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn purge_clips(
+        &self,
+        user_id: Option<i64>,
+        before: DateTime<Utc>,
+    ) -> Result<u64, AppError> {
+        let result = query(&format!(
+            "DELETE FROM clips WHERE created_at < $1 AND {}",
+            RETENTION_FILTER
+        ))
+        .bind(before)
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn purge_clip_queue(
+        &self,
+        user_id: Option<i64>,
+        before: DateTime<Utc>,
+    ) -> Result<u64, AppError> {
+        let result = query(&format!(
+            "DELETE FROM clip_queue WHERE queued_at < $1 AND {}",
+            RETENTION_FILTER
+        ))
+        .bind(before)
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn purge_invites(&self, before: DateTime<Utc>) -> Result<u64, AppError> {
+        let result = query("DELETE FROM invites WHERE expires_at < $1 OR revoked_at < $1")
+            .bind(before)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn vacuum(&self) -> Result<(), AppError> {
+        // a plain statement, VACUUM cannot be prepared
+        self.pool.execute("VACUUM").await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,1786 @@
+use std::{
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::{
+    migrate::MigrateDatabase, query, query_as, query_scalar, sqlite::SqliteConnectOptions, Sqlite,
+    SqlitePool,
+};
+
+use super::{
+    check_backup_migrations, check_schema, AuditRepository, BackupRepository, ClipRepository,
+    DataKeyRepository, DeviceRepository, IdentityRepository, InviteRepository,
+    PasswordResetRepository, RetentionRepository, RoleRepository, SessionRepository,
+    TotpRepository, UserRepository,
+};
+use crate::{
+    error::AppError,
+    models::{
+        audit::{AuditAction, AuditEntry, AuditEvent, AuditQuery, AuditResult},
+        clip::{ClipEntity, QueuedClipEntity},
+        device::{DeviceAccess, DeviceEntity, DeviceRegistration},
+        encryption::DataKeyEntity,
+        invite::InviteEntity,
+        oidc::IdentityEntity,
+        role::{Permission, RoleEntity, RoleUpsert},
+        session::SessionEntity,
+        totp::TotpEntity,
+        user::{Role, UserCreate, UserDetails, UserEntity, UserListQuery},
+    },
+    utils::generate_token,
+};
+
+/// The default backend, also used for `sqlite::memory:` databases.
+#[derive(Clone)]
+pub struct SqliteRepository {
+    pool: SqlitePool,
+}
+
+impl SqliteRepository {
+    pub async fn connect(db_url: &str) -> Result<Self, sqlx::Error> {
+        if Sqlite::database_exists(db_url).await? {
+            tracing::debug!("Database exists");
+        } else {
+            tracing::debug!("Database does not exist");
+            Sqlite::create_database(db_url).await?;
+        }
+
+        let pool = SqlitePool::connect(db_url).await?;
+        sqlx::migrate!("migrations/sqlite").run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+
+    /// Connects to the existing database without migrating it, see
+    /// [`super::open`].
+    pub async fn open(db_url: &str) -> Result<Self, sqlx::Error> {
+        if !Sqlite::database_exists(db_url).await? {
+            return Err(sqlx::Error::Configuration(
+                format!("database {} does not exist", db_url).into(),
+            ));
+        }
+
+        let pool = SqlitePool::connect(db_url).await?;
+        let applied = query_as::<_, (i64, Vec<u8>)>(
+            "SELECT version, checksum FROM _sqlx_migrations WHERE success ORDER BY version",
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap_or_default();
+        check_schema(&sqlx::migrate!("migrations/sqlite"), &applied)?;
+
+        Ok(Self { pool })
+    }
+
+    pub fn pool(&self) -> &SqlitePool {
+        &self.pool
+    }
+
+    /// Swaps the database file of `db_url` for a migrated copy of the backup,
+    /// the file is left untouched if the backup does not fit this version.
+    pub async fn restore(db_url: &str, path: &Path) -> Result<(), AppError> {
+        let target = database_file(db_url)?;
+        let mut staged = target.clone().into_os_string();
+        staged.push(".restore");
+        let staged = PathBuf::from(staged);
+
+        tokio::fs::copy(path, &staged).await.map_err(|err| {
+            AppError::Backup(format!("unable to read {}: {}", path.display(), err))
+        })?;
+        if let Err(err) = Self::migrate_backup(&staged).await {
+            let _ = tokio::fs::remove_file(&staged).await;
+            return Err(err);
+        }
+
+        for suffix in ["-wal", "-shm"] {
+            let mut journal = target.clone().into_os_string();
+            journal.push(suffix);
+            let _ = tokio::fs::remove_file(journal).await;
+        }
+        tokio::fs::rename(&staged, &target)
+            .await
+            .map_err(|err| AppError::Backup(format!("unable to replace the database: {}", err)))
+    }
+
+    async fn migrate_backup(path: &Path) -> Result<(), AppError> {
+        let pool = SqlitePool::connect_with(SqliteConnectOptions::new().filename(path)).await?;
+        let applied = query_as::<_, (i64, Vec<u8>)>(
+            "SELECT version, checksum FROM _sqlx_migrations WHERE success ORDER BY version",
+        )
+        .fetch_all(&pool)
+        .await
+        .map_err(|_| AppError::Backup(format!("{} is not a scytale backup", path.display())));
+
+        let migrator = sqlx::migrate!("migrations/sqlite");
+        let result = match applied.and_then(|applied| check_backup_migrations(&migrator, &applied))
+        {
+            Ok(_) => migrator
+                .run(&pool)
+                .await
+                .map_err(|err| AppError::DatabaseError(err.into())),
+            Err(err) => Err(err),
+        };
+        pool.close().await;
+
+        result
+    }
+}
+
+/// Copies the database file `source` into the empty file `target` with
+/// SQLite's online backup API. The copy is taken in a single step, i.e. in one
+/// read transaction, so that it is consistent while writers carry on.
+fn online_backup(source: &Path, target: &Path) -> Result<(), String> {
+    use libsqlite3_sys as ffi;
+    use std::ffi::{CStr, CString};
+
+    /// Closes the handle on drop, also the one of a failed open.
+    struct Handle(*mut ffi::sqlite3);
+
+    impl Drop for Handle {
+        fn drop(&mut self) {
+            // SAFETY: the handle came from sqlite3_open_v2 and is closed once
+            unsafe { ffi::sqlite3_close(self.0) };
+        }
+    }
+
+    fn message(handle: &Handle) -> String {
+        // SAFETY: the handle is open, the message lives until the next call on it
+        unsafe { CStr::from_ptr(ffi::sqlite3_errmsg(handle.0)) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn open(path: &Path, flags: std::os::raw::c_int) -> Result<Handle, String> {
+        let name = CString::new(path.to_string_lossy().into_owned())
+            .map_err(|_| format!("invalid path {}", path.display()))?;
+        let mut handle = Handle(std::ptr::null_mut());
+        // SAFETY: name is a valid C string and handle.0 a valid out pointer
+        let code =
+            unsafe { ffi::sqlite3_open_v2(name.as_ptr(), &mut handle.0, flags, std::ptr::null()) };
+        if code != ffi::SQLITE_OK {
+            return Err(format!(
+                "unable to open {}: {}",
+                path.display(),
+                message(&handle)
+            ));
+        }
+
+        Ok(handle)
+    }
+
+    let source = open(source, ffi::SQLITE_OPEN_READONLY)?;
+    let target = open(target, ffi::SQLITE_OPEN_READWRITE)?;
+    let main = CString::new("main").expect("no nul byte");
+    // SAFETY: both handles are open and outlive the backup, which is finished
+    // before they are closed
+    unsafe {
+        let backup = ffi::sqlite3_backup_init(target.0, main.as_ptr(), source.0, main.as_ptr());
+        if backup.is_null() {
+            return Err(message(&target));
+        }
+        let code = loop {
+            match ffi::sqlite3_backup_step(backup, -1) {
+                // a writer holds a lock that keeps the read transaction out
+                ffi::SQLITE_BUSY | ffi::SQLITE_LOCKED => {
+                    std::thread::sleep(std::time::Duration::from_millis(50))
+                }
+                code => break code,
+            }
+        };
+        ffi::sqlite3_backup_finish(backup);
+        if code != ffi::SQLITE_DONE {
+            return Err(message(&target));
+        }
+        // the copy takes over the WAL mode of the source, a backup is a single file
+        let pragma = CString::new("PRAGMA journal_mode = DELETE").expect("no nul byte");
+        let code = ffi::sqlite3_exec(
+            target.0,
+            pragma.as_ptr(),
+            None,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        );
+        if code != ffi::SQLITE_OK {
+            return Err(message(&target));
+        }
+    }
+
+    Ok(())
+}
+
+/// Path of the file behind a `sqlite:` url, in-memory databases have none.
+fn database_file(db_url: &str) -> Result<PathBuf, AppError> {
+    let path = db_url
+        .strip_prefix("sqlite://")
+        .or_else(|| db_url.strip_prefix("sqlite:"))
+        .unwrap_or(db_url);
+    let path = path.split_once('?').map_or(path, |(path, _)| path);
+    if path.is_empty() || path == ":memory:" {
+        return Err(AppError::Backup(
+            "cannot restore into an in-memory database".to_string(),
+        ));
+    }
+
+    Ok(PathBuf::from(path))
+}
+
+#[async_trait]
+impl UserRepository for SqliteRepository {
+    async fn create_user(&self, user: &UserCreate) -> Result<UserEntity, AppError> {
+        // no RETURNING, sqlx's compile time analysis of it grows with every table in the schema
+        let id = query!(
+            "INSERT INTO users (email, name, password, role) VALUES (?, ?, ?, ?)",
+            user.email,
+            user.name,
+            user.password,
+            user.role
+        )
+        .execute(&self.pool)
+        .await?
+        .last_insert_rowid();
+
+        self.get_user_by_id(id).await
+    }
+
+    async fn get_user_by_id(&self, id: i64) -> Result<UserEntity, AppError> {
+        let user = query_as!(
+            UserEntity,
+            r#"SELECT id, name, email, password , role as "role!: Role" FROM users WHERE id = ?"#,
+            id
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|err| AppError::UserDoesNotExist)?;
+
+        Ok(user)
+    }
+
+    async fn get_user_by_id_email(&self, id: i64, email: &str) -> Result<UserEntity, AppError> {
+        let user = query_as!(
+            UserEntity,
+            r#"SELECT id, name, email, password, role as "role!: Role" FROM users WHERE id = ? AND email = ?"#,
+            id, email
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|err| AppError::UserDoesNotExist)?;
+
+        Ok(user)
+    }
+
+    async fn get_user_by_email(&self, email: &str) -> Result<Option<UserEntity>, AppError> {
+        let user = query_as!(
+            UserEntity,
+            r#"SELECT
+                id as "id!: i64",
+                name as "name!:String",
+                email as "email!: String",
+                password as "password!: String",
+                role as "role!: Role"
+            FROM users WHERE email = ?"#,
+            email
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(user)
+    }
+
+    async fn update_user_password(&self, id: i64, password_hash: &str) -> Result<(), AppError> {
+        query!(
+            "UPDATE users SET password = ? WHERE id = ?",
+            password_hash,
+            id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn update_user_role(&self, id: i64, role: &Role) -> Result<(), AppError> {
+        query!("UPDATE users SET role = ? WHERE id = ?", role, id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_user_auth_provider(&self, email: &str) -> Result<Option<String>, AppError> {
+        let provider = query_scalar!("SELECT auth_provider FROM users WHERE email = ?", email)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(provider.flatten())
+    }
+
+    async fn set_user_auth_provider(
+        &self,
+        id: i64,
+        provider: Option<&str>,
+    ) -> Result<(), AppError> {
+        let result = query!(
+            "UPDATE users SET auth_provider = ? WHERE id = ?",
+            provider,
+            id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(AppError::UserDoesNotExist);
+        }
+
+        Ok(())
+    }
+
+    async fn is_user_disabled(&self, id: i64) -> Result<bool, AppError> {
+        let disabled = query_scalar!(
+            r#"SELECT disabled_at IS NOT NULL as "disabled!: bool" FROM users WHERE id = ?"#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        disabled.ok_or(AppError::UserDoesNotExist)
+    }
+
+    async fn count_users(&self, filter: &UserListQuery) -> Result<i64, AppError> {
+        let count = query_scalar!(
+            r#"SELECT COUNT(*) as "count!: i64" FROM users
+            WHERE (?1 IS NULL OR instr(lower(email), lower(?1)) > 0 OR instr(lower(name), lower(?1)) > 0)
+                AND (?2 IS NULL OR role = ?2)
+                AND (?3 IS NULL OR (disabled_at IS NOT NULL) = ?3)"#,
+            filter.q,
+            filter.role,
+            filter.disabled
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(count)
+    }
+
+    async fn list_users(
+        &self,
+        filter: &UserListQuery,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<UserDetails>, AppError> {
+        let users = query_as!(
+            UserDetails,
+            r#"SELECT id as "id!: i64", email, name, role as "role!: Role", auth_provider,
+                EXISTS(SELECT 1 FROM user_totp WHERE user_id = users.id AND enabled) as "totp_enabled!: bool",
+                disabled_at as "disabled_at: DateTime<Utc>"
+            FROM users
+            WHERE (?1 IS NULL OR instr(lower(email), lower(?1)) > 0 OR instr(lower(name), lower(?1)) > 0)
+                AND (?2 IS NULL OR role = ?2)
+                AND (?3 IS NULL OR (disabled_at IS NOT NULL) = ?3)
+            ORDER BY id
+            LIMIT ?4 OFFSET ?5"#,
+            filter.q,
+            filter.role,
+            filter.disabled,
+            limit,
+            offset
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(users)
+    }
+
+    async fn get_user_details(&self, id: i64) -> Result<UserDetails, AppError> {
+        let user = query_as!(
+            UserDetails,
+            r#"SELECT id as "id!: i64", email, name, role as "role!: Role", auth_provider,
+                EXISTS(SELECT 1 FROM user_totp WHERE user_id = users.id AND enabled) as "totp_enabled!: bool",
+                disabled_at as "disabled_at: DateTime<Utc>"
+            FROM users WHERE id = ?"#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        user.ok_or(AppError::UserDoesNotExist)
+    }
+
+    async fn update_user_profile(
+        &self,
+        id: i64,
+        email: &str,
+        name: &str,
+        role: &Role,
+    ) -> Result<(), AppError> {
+        query!(
+            "UPDATE users SET email = ?, name = ?, role = ? WHERE id = ?",
+            email,
+            name,
+            role,
+            id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn set_user_disabled(&self, id: i64, disabled: bool) -> Result<(), AppError> {
+        let disabled_at = disabled.then(Utc::now);
+
+        let result = query!(
+            "UPDATE users SET disabled_at = ? WHERE id = ?",
+            disabled_at,
+            id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(AppError::UserDoesNotExist);
+        }
+
+        Ok(())
+    }
+
+    /// Through `ON DELETE CASCADE`.
+    async fn delete_user(&self, id: i64) -> Result<(), AppError> {
+        let result = query!("DELETE FROM users WHERE id = ?", id)
+            .execute(&self.pool)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(AppError::UserDoesNotExist);
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl RoleRepository for SqliteRepository {
+    async fn role_exists(&self, role: &Role) -> Result<bool, AppError> {
+        let count = query_scalar!(
+            r#"SELECT COUNT(*) as "count!: i64" FROM roles WHERE name = ?"#,
+            role
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(count > 0)
+    }
+
+    async fn get_role_permissions(&self, role: &Role) -> Result<Vec<Permission>, AppError> {
+        let permissions = query_scalar!(
+            r#"SELECT permission as "permission!: Permission" FROM role_permissions WHERE role = ?"#,
+            role
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(permissions)
+    }
+
+    async fn has_permission(&self, role: &Role, permission: Permission) -> Result<bool, AppError> {
+        let count = query_scalar!(
+            r#"SELECT COUNT(*) as "count!: i64" FROM role_permissions WHERE role = ? AND permission = ?"#,
+            role,
+            permission
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(count > 0)
+    }
+
+    async fn get_role(&self, role: &Role) -> Result<RoleEntity, AppError> {
+        let record = query!(
+            r#"SELECT name as "name!: Role", description FROM roles WHERE name = ?"#,
+            role
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(AppError::RoleDoesNotExist)?;
+
+        let permissions = self.get_role_permissions(&record.name).await?;
+
+        Ok(RoleEntity {
+            name: record.name,
+            description: record.description,
+            permissions,
+        })
+    }
+
+    async fn list_roles(&self) -> Result<Vec<RoleEntity>, AppError> {
+        let names = query_scalar!(r#"SELECT name as "name!: Role" FROM roles ORDER BY name"#)
+            .fetch_all(&self.pool)
+            .await?;
+
+        let mut roles = Vec::with_capacity(names.len());
+        for name in names {
+            roles.push(self.get_role(&name).await?);
+        }
+
+        Ok(roles)
+    }
+
+    async fn upsert_role(&self, role: &Role, payload: &RoleUpsert) -> Result<RoleEntity, AppError> {
+        let mut tx = self.pool.begin().await?;
+
+        query!(
+            r#"INSERT INTO roles (name, description) VALUES (?, ?)
+                ON CONFLICT (name) DO UPDATE SET description = excluded.description"#,
+            role,
+            payload.description
+        )
+        .execute(&mut tx)
+        .await?;
+
+        query!("DELETE FROM role_permissions WHERE role = ?", role)
+            .execute(&mut tx)
+            .await?;
+
+        for permission in &payload.permissions {
+            query!(
+                "INSERT OR IGNORE INTO role_permissions (role, permission) VALUES (?, ?)",
+                role,
+                permission
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        self.get_role(role).await
+    }
+
+    async fn delete_role(&self, role: &Role) -> Result<(), AppError> {
+        let in_use = query_scalar!(
+            r#"SELECT COUNT(*) as "count!: i64" FROM users WHERE role = ?"#,
+            role
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        if in_use > 0 {
+            return Err(AppError::RoleInUse);
+        }
+
+        let result = query!("DELETE FROM roles WHERE name = ?", role)
+            .execute(&self.pool)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(AppError::RoleDoesNotExist);
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SessionRepository for SqliteRepository {
+    async fn create_session_until(
+        &self,
+        user_id: i64,
+        client_id: Option<&str>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<String, AppError> {
+        let id = generate_token(32);
+        let now = Utc::now();
+
+        query!(
+            "INSERT INTO sessions (id, user_id, created_at, expires_at, client_id) VALUES (?, ?, ?, ?, ?)",
+            id,
+            user_id,
+            now,
+            expires_at,
+            client_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(id)
+    }
+
+    async fn is_session_active(&self, id: &str, user_id: i64) -> Result<bool, AppError> {
+        let now = Utc::now();
+        let count = query_scalar!(
+            r#"SELECT COUNT(*) as "count!: i64" FROM sessions
+            WHERE id = ? AND user_id = ? AND revoked_at IS NULL AND expires_at > ?"#,
+            id,
+            user_id,
+            now
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(count > 0)
+    }
+
+    async fn extend_session(&self, id: &str, expires_at: DateTime<Utc>) -> Result<(), AppError> {
+        query!(
+            "UPDATE sessions SET expires_at = ? WHERE id = ? AND revoked_at IS NULL",
+            expires_at,
+            id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn revoke_user_sessions(&self, user_id: i64) -> Result<u64, AppError> {
+        let now = Utc::now();
+
+        let result = query!(
+            "UPDATE sessions SET revoked_at = ? WHERE user_id = ? AND revoked_at IS NULL",
+            now,
+            user_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn revoke_other_sessions(&self, user_id: i64, keep: &str) -> Result<u64, AppError> {
+        let now = Utc::now();
+
+        let result = query!(
+            "UPDATE sessions SET revoked_at = ? WHERE user_id = ? AND id != ? AND revoked_at IS NULL",
+            now,
+            user_id,
+            keep
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn revoke_device_sessions(&self, user_id: i64, client_id: &str) -> Result<u64, AppError> {
+        let now = Utc::now();
+
+        let result = query!(
+            "UPDATE sessions SET revoked_at = ? WHERE user_id = ? AND client_id = ? AND revoked_at IS NULL",
+            now,
+            user_id,
+            client_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn bind_session_client(
+        &self,
+        id: &str,
+        user_id: i64,
+        client_id: &str,
+    ) -> Result<bool, AppError> {
+        let now = Utc::now();
+
+        let result = query!(
+            "UPDATE sessions SET client_id = ? WHERE id = ? AND user_id = ? AND (client_id = ?
+                OR client_id IS NULL AND NOT EXISTS (SELECT 1 FROM sessions AS other
+                    WHERE other.user_id = ? AND other.client_id = ?
+                    AND other.revoked_at IS NULL AND other.expires_at > ?))",
+            client_id,
+            id,
+            user_id,
+            client_id,
+            user_id,
+            client_id,
+            now
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn list_user_sessions(&self, user_id: i64) -> Result<Vec<SessionEntity>, AppError> {
+        let sessions = query_as!(
+            SessionEntity,
+            r#"SELECT id as "id!: String", user_id, created_at as "created_at!: DateTime<Utc>",
+                expires_at as "expires_at!: DateTime<Utc>", revoked_at as "revoked_at: DateTime<Utc>",
+                client_id
+            FROM sessions WHERE user_id = ? ORDER BY created_at"#,
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(sessions)
+    }
+}
+
+#[async_trait]
+impl TotpRepository for SqliteRepository {
+    async fn get_totp(&self, user_id: i64) -> Result<Option<TotpEntity>, AppError> {
+        let totp = query_as!(
+            TotpEntity,
+            r#"SELECT user_id, secret, enabled as "enabled!: bool", last_used_step
+            FROM user_totp WHERE user_id = ?"#,
+            user_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(totp)
+    }
+
+    async fn set_pending_totp(&self, user_id: i64, secret: &str) -> Result<(), AppError> {
+        query!(
+            r#"INSERT INTO user_totp (user_id, secret, enabled, last_used_step) VALUES (?, ?, FALSE, 0)
+                ON CONFLICT (user_id) DO UPDATE SET secret = excluded.secret, enabled = FALSE, last_used_step = 0"#,
+            user_id,
+            secret
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn enable_totp(
+        &self,
+        user_id: i64,
+        step: i64,
+        code_hashes: &[String],
+    ) -> Result<(), AppError> {
+        let mut tx = self.pool.begin().await?;
+
+        query!(
+            "UPDATE user_totp SET enabled = TRUE, last_used_step = ? WHERE user_id = ?",
+            step,
+            user_id
+        )
+        .execute(&mut tx)
+        .await?;
+
+        query!("DELETE FROM recovery_codes WHERE user_id = ?", user_id)
+            .execute(&mut tx)
+            .await?;
+
+        for code_hash in code_hashes {
+            query!(
+                "INSERT INTO recovery_codes (user_id, code_hash) VALUES (?, ?)",
+                user_id,
+                code_hash
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn set_totp_last_step(&self, user_id: i64, step: i64) -> Result<(), AppError> {
+        query!(
+            "UPDATE user_totp SET last_used_step = ? WHERE user_id = ?",
+            step,
+            user_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn use_recovery_code(&self, user_id: i64, code_hash: &str) -> Result<bool, AppError> {
+        let result = query!(
+            r#"UPDATE recovery_codes SET used_at = CURRENT_TIMESTAMP
+            WHERE user_id = ? AND code_hash = ? AND used_at IS NULL"#,
+            user_id,
+            code_hash
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_totp(&self, user_id: i64) -> Result<(), AppError> {
+        let mut tx = self.pool.begin().await?;
+
+        query!("DELETE FROM recovery_codes WHERE user_id = ?", user_id)
+            .execute(&mut tx)
+            .await?;
+
+        query!("DELETE FROM user_totp WHERE user_id = ?", user_id)
+            .execute(&mut tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl PasswordResetRepository for SqliteRepository {
+    async fn create_password_reset(
+        &self,
+        user_id: i64,
+        created_by: i64,
+        token_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), AppError> {
+        let now = Utc::now();
+        let mut tx = self.pool.begin().await?;
+
+        query!(
+            "DELETE FROM password_reset_tokens WHERE user_id = ? AND used_at IS NULL",
+            user_id
+        )
+        .execute(&mut tx)
+        .await?;
+
+        query!(
+            r#"INSERT INTO password_reset_tokens (token_hash, user_id, created_by, created_at, expires_at)
+                VALUES (?, ?, ?, ?, ?)"#,
+            token_hash,
+            user_id,
+            created_by,
+            now,
+            expires_at
+        )
+        .execute(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn get_password_reset_user(&self, token_hash: &str) -> Result<i64, AppError> {
+        let now = Utc::now();
+
+        let user_id = query_scalar!(
+            r#"SELECT user_id as "user_id!: i64" FROM password_reset_tokens
+            WHERE token_hash = ? AND used_at IS NULL AND expires_at > ?"#,
+            token_hash,
+            now
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        user_id.ok_or(AppError::InvalidToken)
+    }
+
+    async fn consume_password_reset(
+        &self,
+        token_hash: &str,
+        password_hash: &str,
+    ) -> Result<i64, AppError> {
+        let now = Utc::now();
+        let mut tx = self.pool.begin().await?;
+
+        let user_id = query_scalar!(
+            r#"UPDATE password_reset_tokens SET used_at = ?
+            WHERE token_hash = ? AND used_at IS NULL AND expires_at > ?
+            RETURNING user_id as "user_id!: i64""#,
+            now,
+            token_hash,
+            now
+        )
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or(AppError::InvalidToken)?;
+
+        query!(
+            "UPDATE users SET password = ? WHERE id = ?",
+            password_hash,
+            user_id
+        )
+        .execute(&mut tx)
+        .await?;
+
+        query!(
+            "UPDATE sessions SET revoked_at = ? WHERE user_id = ? AND revoked_at IS NULL",
+            now,
+            user_id
+        )
+        .execute(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(user_id)
+    }
+}
+
+#[async_trait]
+impl InviteRepository for SqliteRepository {
+    async fn create_invite(
+        &self,
+        code_hash: &str,
+        role: &Role,
+        max_uses: i64,
+        created_by: i64,
+        expires_at: DateTime<Utc>,
+    ) -> Result<InviteEntity, AppError> {
+        let now = Utc::now();
+
+        let invite = query_as!(
+            InviteEntity,
+            r#"INSERT INTO invites (code_hash, role, max_uses, created_by, created_at, expires_at)
+                VALUES (?, ?, ?, ?, ?, ?)
+                RETURNING id as "id!: i64", role as "role!: Role", max_uses as "max_uses!: i64",
+                    uses as "uses!: i64", created_by, created_at as "created_at!: DateTime<Utc>",
+                    expires_at as "expires_at!: DateTime<Utc>""#,
+            code_hash,
+            role,
+            max_uses,
+            created_by,
+            now,
+            expires_at
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(invite)
+    }
+
+    async fn list_outstanding_invites(&self) -> Result<Vec<InviteEntity>, AppError> {
+        let now = Utc::now();
+
+        let invites = query_as!(
+            InviteEntity,
+            r#"SELECT id as "id!: i64", role as "role!: Role", max_uses, uses, created_by,
+                created_at as "created_at!: DateTime<Utc>", expires_at as "expires_at!: DateTime<Utc>"
+            FROM invites
+            WHERE revoked_at IS NULL AND expires_at > ? AND uses < max_uses
+            ORDER BY id"#,
+            now
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(invites)
+    }
+
+    async fn revoke_invite(&self, id: i64) -> Result<(), AppError> {
+        let now = Utc::now();
+
+        let result = query!(
+            "UPDATE invites SET revoked_at = ? WHERE id = ? AND revoked_at IS NULL",
+            now,
+            id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(AppError::InvalidInvite);
+        }
+
+        Ok(())
+    }
+
+    async fn get_invite_role(&self, code_hash: &str) -> Result<Role, AppError> {
+        let now = Utc::now();
+
+        let role = query_scalar!(
+            r#"SELECT role as "role!: Role" FROM invites
+            WHERE code_hash = ? AND revoked_at IS NULL AND expires_at > ? AND uses < max_uses"#,
+            code_hash,
+            now
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        role.ok_or(AppError::InvalidInvite)
+    }
+
+    async fn use_invite(&self, code_hash: &str) -> Result<Role, AppError> {
+        let now = Utc::now();
+
+        let role = query_scalar!(
+            r#"UPDATE invites SET uses = uses + 1
+            WHERE code_hash = ? AND revoked_at IS NULL AND expires_at > ? AND uses < max_uses
+            RETURNING role as "role!: Role""#,
+            code_hash,
+            now
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        role.ok_or(AppError::InvalidInvite)
+    }
+
+    async fn release_invite(&self, code_hash: &str) -> Result<(), AppError> {
+        query!(
+            "UPDATE invites SET uses = uses - 1 WHERE code_hash = ? AND uses > 0",
+            code_hash
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn list_invites_created_by(&self, user_id: i64) -> Result<Vec<InviteEntity>, AppError> {
+        let invites = query_as!(
+            InviteEntity,
+            r#"SELECT id as "id!: i64", role as "role!: Role", max_uses, uses, created_by,
+                created_at as "created_at!: DateTime<Utc>", expires_at as "expires_at!: DateTime<Utc>"
+            FROM invites WHERE created_by = ? ORDER BY id"#,
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(invites)
+    }
+}
+
+#[async_trait]
+impl IdentityRepository for SqliteRepository {
+    async fn get_identity_user(
+        &self,
+        provider: &str,
+        subject: &str,
+    ) -> Result<Option<i64>, AppError> {
+        let user_id = query_scalar!(
+            r#"SELECT user_id as "user_id!: i64" FROM user_identities WHERE provider = ? AND subject = ?"#,
+            provider,
+            subject
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(user_id)
+    }
+
+    async fn link_identity(
+        &self,
+        provider: &str,
+        subject: &str,
+        user_id: i64,
+        email: Option<&str>,
+    ) -> Result<(), AppError> {
+        let now = Utc::now();
+
+        query!(
+            r#"INSERT INTO user_identities (provider, subject, user_id, email, created_at, last_login_at)
+                VALUES (?, ?, ?, ?, ?, ?)"#,
+            provider,
+            subject,
+            user_id,
+            email,
+            now,
+            now
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn touch_identity(
+        &self,
+        provider: &str,
+        subject: &str,
+        email: Option<&str>,
+    ) -> Result<(), AppError> {
+        let now = Utc::now();
+
+        query!(
+            r#"UPDATE user_identities SET last_login_at = ?, email = COALESCE(?, email)
+            WHERE provider = ? AND subject = ?"#,
+            now,
+            email,
+            provider,
+            subject
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn list_user_identities(&self, user_id: i64) -> Result<Vec<IdentityEntity>, AppError> {
+        let identities = query_as!(
+            IdentityEntity,
+            r#"SELECT provider, subject, user_id, email, created_at as "created_at!: DateTime<Utc>",
+                last_login_at as "last_login_at!: DateTime<Utc>"
+            FROM user_identities WHERE user_id = ?"#,
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(identities)
+    }
+}
+
+#[async_trait]
+impl AuditRepository for SqliteRepository {
+    async fn record_audit(&self, event: &AuditEvent) -> Result<(), AppError> {
+        let now = Utc::now();
+
+        query!(
+            r#"INSERT INTO audit_log
+                (created_at, actor_id, actor_email, action, target, ip, user_agent, result, detail)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)"#,
+            now,
+            event.actor_id,
+            event.actor_email,
+            event.action,
+            event.target,
+            event.ip,
+            event.user_agent,
+            event.result,
+            event.detail
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn count_audit(&self, filter: &AuditQuery) -> Result<i64, AppError> {
+        let count = query_scalar!(
+            r#"SELECT COUNT(*) as "count!: i64" FROM audit_log
+            WHERE (?1 IS NULL OR actor_id = ?1)
+                AND (?2 IS NULL OR actor_email = ?2)
+                AND (?3 IS NULL OR action = ?3)
+                AND (?4 IS NULL OR target = ?4)
+                AND (?5 IS NULL OR result = ?5)
+                AND (?6 IS NULL OR created_at >= ?6)
+                AND (?7 IS NULL OR created_at < ?7)"#,
+            filter.actor_id,
+            filter.actor_email,
+            filter.action,
+            filter.target,
+            filter.result,
+            filter.since,
+            filter.until
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(count)
+    }
+
+    /// A negative limit is no limit in SQLite.
+    async fn list_audit(
+        &self,
+        filter: &AuditQuery,
+        before_id: Option<i64>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<AuditEntry>, AppError> {
+        let entries = query_as!(
+            AuditEntry,
+            r#"SELECT id as "id!: i64", created_at as "created_at!: DateTime<Utc>", actor_id,
+                actor_email, action as "action!: AuditAction", target, ip, user_agent,
+                result as "result!: AuditResult", detail
+            FROM audit_log
+            WHERE (?1 IS NULL OR actor_id = ?1)
+                AND (?2 IS NULL OR actor_email = ?2)
+                AND (?3 IS NULL OR action = ?3)
+                AND (?4 IS NULL OR target = ?4)
+                AND (?5 IS NULL OR result = ?5)
+                AND (?6 IS NULL OR created_at >= ?6)
+                AND (?7 IS NULL OR created_at < ?7)
+                AND (?8 IS NULL OR id < ?8)
+            ORDER BY id DESC
+            LIMIT ?9 OFFSET ?10"#,
+            filter.actor_id,
+            filter.actor_email,
+            filter.action,
+            filter.target,
+            filter.result,
+            filter.since,
+            filter.until,
+            before_id,
+            limit,
+            offset
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(entries)
+    }
+}
+
+#[async_trait]
+impl DeviceRepository for SqliteRepository {
+    async fn get_device(
+        &self,
+        user_id: i64,
+        client_id: &str,
+    ) -> Result<Option<DeviceEntity>, AppError> {
+        let device = query_as!(
+            DeviceEntity,
+            r#"SELECT client_id as "client_id!: String", user_id as "user_id!: i64", name,
+                platform, app_version, first_seen_at as "first_seen_at!: DateTime<Utc>",
+                last_seen_at as "last_seen_at!: DateTime<Utc>", last_ip,
+                revoked_at as "revoked_at: DateTime<Utc>", access as "access!: DeviceAccess",
+                expires_at as "expires_at: DateTime<Utc>"
+            FROM devices WHERE user_id = ? AND client_id = ?"#,
+            user_id,
+            client_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(device)
+    }
+
+    async fn upsert_device(
+        &self,
+        user_id: i64,
+        param: &DeviceRegistration,
+        ip: Option<IpAddr>,
+    ) -> Result<DeviceEntity, AppError> {
+        let now = Utc::now();
+        let ip = ip.map(|ip| ip.to_string());
+
+        query!(
+            r#"INSERT OR IGNORE INTO devices
+                (user_id, client_id, name, first_seen_at, last_seen_at)
+                VALUES (?, ?, ?, ?, ?)"#,
+            user_id,
+            param.id,
+            param.name,
+            now,
+            now
+        )
+        .execute(&self.pool)
+        .await?;
+
+        query!(
+            r#"UPDATE devices SET platform = COALESCE(?, platform),
+                app_version = COALESCE(?, app_version), last_seen_at = ?, last_ip = ?
+            WHERE user_id = ? AND client_id = ?"#,
+            param.platform,
+            param.app_version,
+            now,
+            ip,
+            user_id,
+            param.id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        self.get_device(user_id, &param.id)
+            .await?
+            .ok_or(AppError::DeviceDoesNotExist)
+    }
+
+    async fn create_guest_device(
+        &self,
+        user_id: i64,
+        client_id: &str,
+        name: &str,
+        access: DeviceAccess,
+        expires_at: DateTime<Utc>,
+    ) -> Result<DeviceEntity, AppError> {
+        let now = Utc::now();
+
+        query!(
+            r#"INSERT INTO devices
+                (user_id, client_id, name, first_seen_at, last_seen_at, access, expires_at)
+                VALUES (?, ?, ?, ?, ?, ?, ?)"#,
+            user_id,
+            client_id,
+            name,
+            now,
+            now,
+            access,
+            expires_at
+        )
+        .execute(&self.pool)
+        .await?;
+
+        self.get_device(user_id, client_id)
+            .await?
+            .ok_or(AppError::DeviceDoesNotExist)
+    }
+
+    async fn is_device_revoked(&self, user_id: i64, client_id: &str) -> Result<bool, AppError> {
+        let count = query_scalar!(
+            r#"SELECT COUNT(*) as "count!: i64" FROM devices
+            WHERE user_id = ? AND client_id = ? AND revoked_at IS NOT NULL"#,
+            user_id,
+            client_id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(count > 0)
+    }
+
+    async fn touch_device(&self, user_id: i64, client_id: &str) -> Result<(), AppError> {
+        let now = Utc::now();
+
+        query!(
+            "UPDATE devices SET last_seen_at = ? WHERE user_id = ? AND client_id = ?",
+            now,
+            user_id,
+            client_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn list_devices(&self, user_id: i64) -> Result<Vec<DeviceEntity>, AppError> {
+        let devices = query_as!(
+            DeviceEntity,
+            r#"SELECT client_id as "client_id!: String", user_id as "user_id!: i64", name,
+                platform, app_version, first_seen_at as "first_seen_at!: DateTime<Utc>",
+                last_seen_at as "last_seen_at!: DateTime<Utc>", last_ip,
+                revoked_at as "revoked_at: DateTime<Utc>", access as "access!: DeviceAccess",
+                expires_at as "expires_at: DateTime<Utc>"
+            FROM devices WHERE user_id = ? ORDER BY last_seen_at DESC"#,
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(devices)
+    }
+
+    async fn rename_device(
+        &self,
+        user_id: i64,
+        client_id: &str,
+        name: &str,
+    ) -> Result<DeviceEntity, AppError> {
+        let result = query!(
+            "UPDATE devices SET name = ? WHERE user_id = ? AND client_id = ?",
+            name,
+            user_id,
+            client_id
+        )
+        .execute(&self.pool)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(AppError::DeviceDoesNotExist);
+        }
+
+        self.get_device(user_id, client_id)
+            .await?
+            .ok_or(AppError::DeviceDoesNotExist)
+    }
+
+    async fn revoke_device(&self, user_id: i64, client_id: &str) -> Result<DeviceEntity, AppError> {
+        let now = Utc::now();
+
+        query!(
+            "UPDATE devices SET revoked_at = COALESCE(revoked_at, ?) WHERE user_id = ? AND client_id = ?",
+            now,
+            user_id,
+            client_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        self.get_device(user_id, client_id)
+            .await?
+            .ok_or(AppError::DeviceDoesNotExist)
+    }
+}
+
+#[async_trait]
+impl DataKeyRepository for SqliteRepository {
+    async fn get_data_key(&self, user_id: i64) -> Result<Option<DataKeyEntity>, AppError> {
+        let data_key = query_as!(
+            DataKeyEntity,
+            r#"SELECT user_id as "user_id!: i64", master_key_id, wrapped_key,
+                created_at as "created_at!: DateTime<Utc>", rotated_at as "rotated_at: DateTime<Utc>"
+            FROM data_keys WHERE user_id = ?"#,
+            user_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(data_key)
+    }
+
+    async fn insert_data_key(
+        &self,
+        user_id: i64,
+        master_key_id: &str,
+        wrapped_key: &[u8],
+    ) -> Result<DataKeyEntity, AppError> {
+        let now = Utc::now();
+
+        query!(
+            r#"INSERT OR IGNORE INTO data_keys (user_id, master_key_id, wrapped_key, created_at)
+                VALUES (?, ?, ?, ?)"#,
+            user_id,
+            master_key_id,
+            wrapped_key,
+            now
+        )
+        .execute(&self.pool)
+        .await?;
+
+        self.get_data_key(user_id)
+            .await?
+            .ok_or(AppError::UserDoesNotExist)
+    }
+
+    async fn list_data_keys_wrapped_by(
+        &self,
+        master_key_id: &str,
+        limit: i64,
+    ) -> Result<Vec<DataKeyEntity>, AppError> {
+        let data_keys = query_as!(
+            DataKeyEntity,
+            r#"SELECT user_id as "user_id!: i64", master_key_id, wrapped_key,
+                created_at as "created_at!: DateTime<Utc>", rotated_at as "rotated_at: DateTime<Utc>"
+            FROM data_keys WHERE master_key_id = ? ORDER BY user_id LIMIT ?"#,
+            master_key_id,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(data_keys)
+    }
+
+    async fn count_data_keys_not_wrapped_by(&self, master_key_id: &str) -> Result<i64, AppError> {
+        let count = query_scalar!(
+            r#"SELECT COUNT(*) as "count!: i64" FROM data_keys WHERE master_key_id <> ?"#,
+            master_key_id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(count)
+    }
+
+    async fn rewrap_data_key(
+        &self,
+        user_id: i64,
+        old_master_key_id: &str,
+        new_master_key_id: &str,
+        wrapped_key: &[u8],
+    ) -> Result<bool, AppError> {
+        let now = Utc::now();
+
+        let result = query!(
+            r#"UPDATE data_keys SET master_key_id = ?, wrapped_key = ?, rotated_at = ?
+            WHERE user_id = ? AND master_key_id = ?"#,
+            new_master_key_id,
+            wrapped_key,
+            now,
+            user_id,
+            old_master_key_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[async_trait]
+impl ClipRepository for SqliteRepository {
+    async fn insert_clip(&self, clip: &ClipEntity) -> Result<(), AppError> {
+        query!(
+            r#"INSERT INTO clips (id, user_id, client_id, content, created_at)
+                VALUES (?, ?, ?, ?, ?)"#,
+            clip.id,
+            clip.user_id,
+            clip.client_id,
+            clip.content,
+            clip.created_at
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn queue_clip(
+        &self,
+        clip_id: &str,
+        user_id: i64,
+        client_id: &str,
+    ) -> Result<(), AppError> {
+        let now = Utc::now();
+
+        query!(
+            r#"INSERT OR IGNORE INTO clip_queue (clip_id, user_id, client_id, queued_at)
+                VALUES (?, ?, ?, ?)"#,
+            clip_id,
+            user_id,
+            client_id,
+            now
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn list_queued_clips(
+        &self,
+        user_id: i64,
+        client_id: &str,
+    ) -> Result<Vec<ClipEntity>, AppError> {
+        let clips = query_as!(
+            ClipEntity,
+            r#"SELECT clips.id as "id!", clips.user_id as "user_id!: i64", clips.client_id,
+                content, created_at as "created_at!: DateTime<Utc>"
+            FROM clips JOIN clip_queue ON clip_queue.clip_id = clips.id
+            WHERE clip_queue.user_id = ? AND clip_queue.client_id = ?
+            ORDER BY clips.created_at"#,
+            user_id,
+            client_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(clips)
+    }
+
+    async fn dequeue_clip(&self, clip_id: &str, client_id: &str) -> Result<(), AppError> {
+        query!(
+            "DELETE FROM clip_queue WHERE clip_id = ? AND client_id = ?",
+            clip_id,
+            client_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn list_clips(&self, user_id: i64) -> Result<Vec<ClipEntity>, AppError> {
+        let clips = query_as!(
+            ClipEntity,
+            r#"SELECT id as "id!", user_id as "user_id!: i64", client_id, content,
+                created_at as "created_at!: DateTime<Utc>"
+            FROM clips WHERE user_id = ? ORDER BY created_at"#,
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(clips)
+    }
+
+    async fn list_clip_queue(&self, user_id: i64) -> Result<Vec<QueuedClipEntity>, AppError> {
+        let queue = query_as!(
+            QueuedClipEntity,
+            r#"SELECT clip_id, user_id as "user_id!: i64", client_id,
+                queued_at as "queued_at!: DateTime<Utc>"
+            FROM clip_queue WHERE user_id = ? ORDER BY queued_at"#,
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(queue)
+    }
+}
+
+#[async_trait]
+impl BackupRepository for SqliteRepository {
+    async fn backup(&self, path: &Path) -> Result<(), AppError> {
+        let file: String =
+            query_scalar("SELECT file FROM pragma_database_list WHERE name = 'main'")
+                .fetch_one(&self.pool)
+                .await?;
+        if file.is_empty() {
+            return Err(AppError::Backup(
+                "in-memory databases cannot be backed up".to_string(),
+            ));
+        }
+
+        // created up front so that nothing is overwritten and only the owner
+        // can read the password hashes in it
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options.open(path).map_err(|err| {
+            AppError::Backup(format!("unable to create {}: {}", path.display(), err))
+        })?;
+
+        let source = PathBuf::from(file);
+        let target = path.to_path_buf();
+        let result = tokio::task::spawn_blocking(move || online_backup(&source, &target))
+            .await
+            .map_err(|err| AppError::Backup(err.to_string()))
+            .and_then(|result| result.map_err(AppError::Backup));
+        if result.is_err() {
+            let _ = tokio::fs::remove_file(path).await;
+        }
+
+        result
+    }
+}
+
+#[async_trait]
+impl RetentionRepository for SqliteRepository {
+    async fn list_user_retention(&self) -> Result<Vec<(i64, i64)>, AppError> {
+        let rows = query!(
+            r#"SELECT user_id as "user_id!: i64", days FROM user_retention ORDER BY user_id"#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| (row.user_id, row.days))
+            .collect())
+    }
+
+    async fn get_user_retention(&self, user_id: i64) -> Result<Option<i64>, AppError> {
+        let days = query_scalar!("SELECT days FROM user_retention WHERE user_id = ?", user_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(days)
+    }
+
+    async fn set_user_retention(&self, user_id: i64, days: Option<i64>) -> Result<(), AppError> {
+        match days {
+            Some(days) => {
+                query!(
+                    "INSERT INTO user_retention (user_id, days) VALUES (?, ?)
+                    ON CONFLICT (user_id) DO UPDATE SET days = excluded.days",
+                    user_id,
+                    days
+                )
+                .execute(&self.pool)
+                .await?;
+            }
+            None => {
+                query!("DELETE FROM user_retention WHERE user_id = ?", user_id)
+                    .execute(&self.pool)
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn purge_sessions(
+        &self,
+        user_id: Option<i64>,
+        before: DateTime<Utc>,
+    ) -> Result<u64, AppError> {
+        let result = query!(
+            "DELETE FROM sessions WHERE (expires_at < ? OR revoked_at < ?)
+            AND (user_id = ? OR (? IS NULL AND user_id NOT IN (SELECT user_id FROM user_retention)))",
+            before,
+            before,
+            user_id,
+            user_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn purge_password_resets(
+        &self,
+        user_id: Option<i64>,
+        before: DateTime<Utc>,
+    ) -> Result<u64, AppError> {
+        let result = query!(
+            "DELETE FROM password_reset_tokens WHERE (expires_at < ? OR used_at < ?)
+            AND (user_id = ? OR (? IS NULL AND user_id NOT IN (SELECT user_id FROM user_retention)))",
+            before,
+            before,
+            user_id,
+            user_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn purge_guest_devices(
+        &self,
+        user_id: Option<i64>,
+        before: DateTime<Utc>,
+    ) -> Result<u64, AppError> {
+        let result = query!(
+            "DELETE FROM devices WHERE expires_at < ?
+            AND (user_id = ? OR (? IS NULL AND user_id NOT IN (SELECT user_id FROM user_retention)))",
+            before,
+            user_id,
+            user_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn purge_clips(
+        &self,
+        user_id: Option<i64>,
+        before: DateTime<Utc>,
+    ) -> Result<u64, AppError> {
+        let result = query!(
+            "DELETE FROM clips WHERE created_at < ?
+            AND (user_id = ? OR (? IS NULL AND user_id NOT IN (SELECT user_id FROM user_retention)))",
+            before,
+            user_id,
+            user_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn purge_clip_queue(
+        &self,
+        user_id: Option<i64>,
+        before: DateTime<Utc>,
+    ) -> Result<u64, AppError> {
+        let result = query!(
+            "DELETE FROM clip_queue WHERE queued_at < ?
+            AND (user_id = ? OR (? IS NULL AND user_id NOT IN (SELECT user_id FROM user_retention)))",
+            before,
+            user_id,
+            user_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn purge_invites(&self, before: DateTime<Utc>) -> Result<u64, AppError> {
+        let result = query!(
+            "DELETE FROM invites WHERE expires_at < ? OR revoked_at < ?",
+            before,
+            before
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn vacuum(&self) -> Result<(), AppError> {
+        query("VACUUM").execute(&self.pool).await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,201 @@
+//! A set up scytale and the handle of it serving, for running it inside
+//! another application such as the desktop app.
+
+use std::net::{SocketAddr, TcpListener};
+use std::time::Duration;
+
+use axum::Router;
+use axum_server::Handle;
+use tokio::task::JoinHandle;
+
+use crate::{
+    error::AppError,
+    models::{
+        config::{CorsConfig, LimitsConfig, TlsConfig},
+        state::AppStateType,
+    },
+    tls::{self, ClientCertAcceptor},
+    utils::get_router,
+};
+
+/// How long shutdown waits for WebSocket clients to receive their close frame.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Database, state and background jobs of a scytale, see
+/// [`Scytale::build`](crate::Scytale::build).
+pub struct ScytaleApp {
+    pub(crate) state: AppStateType,
+    pub(crate) addrs: Vec<SocketAddr>,
+    pub(crate) cors: CorsConfig,
+    pub(crate) limits: LimitsConfig,
+    pub(crate) tls: TlsConfig,
+}
+
+impl ScytaleApp {
+    /// The API under `/api`, for mounting in a host axum app that serves it
+    /// itself. Without `into_make_service_with_connect_info` the audit log
+    /// records no client addresses.
+    pub fn router(&self) -> Router {
+        get_router(self.state.clone(), &self.cors, &self.limits)
+    }
+
+    /// Listens on every configured address, port 0 picks a free one.
+    pub async fn serve(self) -> Result<ServerHandle, AppError> {
+        let rustls = match self.tls.enabled {
+            true => Some(tls::load(&self.tls)?),
+            false => None,
+        };
+        // bound up front so that nothing is served when one address fails
+        let listeners = self
+            .addrs
+            .iter()
+            .map(|addr| bind(*addr))
+            .collect::<Result<Vec<_>, _>>()?;
+        let redirect = match (&rustls, self.tls.redirect_from) {
+            (Some(_), Some(addr)) => Some(bind(addr)?),
+            _ => None,
+        };
+
+        let router = self.router();
+        let mut addrs = Vec::new();
+        let mut handles = Vec::new();
+        let mut servers = Vec::new();
+        for (listener, local_addr) in listeners {
+            let handle = Handle::new();
+            let server = axum_server::from_tcp(listener).handle(handle.clone());
+            let service = router
+                .clone()
+                .into_make_service_with_connect_info::<SocketAddr>();
+            tracing::info!(
+                "Starting server at {}://{}",
+                if rustls.is_some() { "https" } else { "http" },
+                local_addr
+            );
+
+            let server = match &rustls {
+                Some(rustls) => tokio::spawn(
+                    server
+                        .acceptor(ClientCertAcceptor::new(rustls.clone()))
+                        .serve(service),
+                ),
+                None => tokio::spawn(server.serve(service)),
+            };
+            addrs.push(local_addr);
+            handles.push(handle);
+            servers.push(log_error(local_addr, server));
+        }
+
+        let mut redirect_addr = None;
+        if let Some((listener, local_addr)) = redirect {
+            let handle = Handle::new();
+            let service = tls::redirect_router(addrs[0].port()).into_make_service();
+            tracing::info!("Redirecting http://{} to HTTPS", local_addr);
+
+            let server = axum_server::from_tcp(listener)
+                .handle(handle.clone())
+                .serve(service);
+            redirect_addr = Some(local_addr);
+            handles.push(handle);
+            servers.push(log_error(local_addr, tokio::spawn(server)));
+        }
+
+        let reload = rustls.and_then(|rustls| tls::spawn_reload(rustls, &self.tls));
+        Ok(ServerHandle {
+            app: self,
+            addrs,
+            redirect_addr,
+            handles,
+            servers,
+            reload,
+        })
+    }
+
+    /// Closes every WebSocket with "going away" and stops the background
+    /// jobs, for hosts serving [`ScytaleApp::router`] that are about to stop.
+    pub async fn close(&self) {
+        let state = self.state.clone();
+        {
+            let mut state = state.lock().await;
+            state.scheduler.stop();
+            state.disconnect_all("server shutting down").await;
+        }
+
+        // the socket tasks remove their client once the close frame is out
+        let closed = async {
+            while !state
+                .lock()
+                .await
+                .users
+                .values()
+                .all(|user| user.is_empty())
+            {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        };
+        if tokio::time::timeout(CLOSE_TIMEOUT, closed).await.is_err() {
+            tracing::warn!("WebSocket clients did not close in time");
+        }
+    }
+}
+
+fn bind(addr: SocketAddr) -> Result<(TcpListener, SocketAddr), AppError> {
+    let listener = TcpListener::bind(addr).map_err(|err| AppError::Bind(addr, err))?;
+    let local_addr = listener
+        .local_addr()
+        .map_err(|err| AppError::Bind(addr, err))?;
+    Ok((listener, local_addr))
+}
+
+fn log_error(addr: SocketAddr, server: JoinHandle<std::io::Result<()>>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        if let Ok(Err(err)) = server.await {
+            tracing::error!("Server at {} failed: {}", addr, err);
+        }
+    })
+}
+
+/// A running server, it keeps serving when the handle is dropped.
+pub struct ServerHandle {
+    app: ScytaleApp,
+    addrs: Vec<SocketAddr>,
+    redirect_addr: Option<SocketAddr>,
+    handles: Vec<Handle>,
+    servers: Vec<JoinHandle<()>>,
+    /// Watches the certificate files.
+    reload: Option<JoinHandle<()>>,
+}
+
+impl ServerHandle {
+    /// First bound address, with the actual port when listening on port 0.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addrs[0]
+    }
+
+    /// Every bound address except the one of the HTTP redirect.
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.addrs
+    }
+
+    pub fn redirect_addr(&self) -> Option<SocketAddr> {
+        self.redirect_addr
+    }
+
+    /// Stops accepting connections, closes the WebSockets and waits for the
+    /// requests in flight to finish, at most `limits.shutdown_timeout_seconds`.
+    pub async fn shutdown(self) {
+        if let Some(reload) = &self.reload {
+            reload.abort();
+        }
+        // nobody can connect while the WebSockets are being closed, the
+        // servers finish once those and the other requests are done
+        let timeout = Duration::from_secs(self.app.limits.shutdown_timeout_seconds);
+        for handle in &self.handles {
+            handle.graceful_shutdown(Some(timeout));
+        }
+        self.app.close().await;
+        for server in self.servers {
+            let _ = server.await;
+        }
+        tracing::info!("Server stopped");
+    }
+}
@@ -1 +1,19 @@
+pub mod admin;
+pub mod audit;
+pub mod auth_provider;
+pub mod backup;
+pub mod clip;
+pub mod device;
+pub mod encryption;
+pub mod export;
+pub mod identity;
+pub mod invite;
+pub mod ldap;
+pub mod oidc;
+pub mod password_reset;
+pub mod retention;
+pub mod role;
+pub mod scheduler;
+pub mod session;
+pub mod totp;
 pub mod user;
@@ -0,0 +1,190 @@
+//! Operations behind the `scytale user` and `scytale device` commands. They
+//! work on the database directly, so they also help when the server is down
+//! or no admin can log in anymore. Connections of a running server are not
+//! closed, revoked sessions end them on the next token refresh.
+
+use crate::{
+    error::AppError,
+    middleware::RequestMeta,
+    models::{
+        audit::{AuditAction, AuditEvent},
+        device::DeviceEntity,
+        password::{HashParams, PasswordPolicy},
+        user::{Role, UserCreate, UserDetails, UserEntity, UserListQuery, UserUpdate},
+    },
+    repository::Database,
+    service::{audit, device, session, totp, user},
+    utils::generate_token,
+};
+
+/// Audited as the user agent of the commands, they have no actor.
+fn cli_meta() -> RequestMeta {
+    RequestMeta {
+        ip: None,
+        user_agent: Some("scytale cli".into()),
+    }
+}
+
+/// Handed out when the commands are not given a password.
+pub fn generate_password() -> String {
+    generate_token(20)
+}
+
+/// How the commands are told which user to act on. The two are kept apart as
+/// an email may well be all digits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UserRef {
+    Id(i64),
+    Email(String),
+}
+
+pub async fn find_user(db: &Database, user: &UserRef) -> Result<UserEntity, AppError> {
+    match user {
+        UserRef::Id(id) => user::get_user_by_id(db, *id).await,
+        UserRef::Email(email) => user::get_user_by_email(db, email)
+            .await?
+            .ok_or(AppError::UserDoesNotExist),
+    }
+}
+
+pub async fn create_user(
+    db: &Database,
+    policy: &PasswordPolicy,
+    params: &HashParams,
+    email: &str,
+    name: &str,
+    password: &str,
+    role: &str,
+) -> Result<UserEntity, AppError> {
+    let mut payload = UserCreate {
+        email: email.trim().to_string(),
+        name: name.trim().to_string(),
+        password: password.to_string(),
+        role: Role::new(role),
+    };
+    user::validate_new_user(db, policy, &payload).await?;
+
+    let created = user::create_user(db, &mut payload, params).await?;
+    audit::record(
+        db,
+        AuditEvent::new(AuditAction::UserCreate, &cli_meta())
+            .target(format!("user:{}", created.id))
+            .detail(format!("role {}", created.role)),
+    )
+    .await;
+    Ok(created)
+}
+
+/// Every user matching the case-insensitive `query`, page by page.
+pub async fn list_users(
+    db: &Database,
+    query: Option<String>,
+) -> Result<Vec<UserDetails>, AppError> {
+    let filter = UserListQuery {
+        q: query,
+        role: None,
+        disabled: None,
+        page: 1,
+        per_page: UserListQuery::MAX_PER_PAGE,
+    };
+    let mut users = Vec::new();
+    loop {
+        let page = user::list_users(db, &filter, filter.per_page, users.len() as i64).await?;
+        let last = (page.len() as i64) < filter.per_page;
+        users.extend(page);
+        if last {
+            return Ok(users);
+        }
+    }
+}
+
+pub async fn set_role(db: &Database, user: &UserRef, role: &str) -> Result<UserDetails, AppError> {
+    let target = find_user(db, user).await?;
+    let update = UserUpdate {
+        role: Some(Role::new(role)),
+        ..Default::default()
+    };
+    let details = user::update_user(db, target.id, &update).await?;
+    audit::record(
+        db,
+        AuditEvent::new(AuditAction::UserUpdate, &cli_meta())
+            .target(format!("user:{}", target.id))
+            .detail(format!("role {}", details.role)),
+    )
+    .await;
+    Ok(details)
+}
+
+/// Sets a new password and ends every session of the user.
+pub async fn reset_password(
+    db: &Database,
+    policy: &PasswordPolicy,
+    params: &HashParams,
+    user: &UserRef,
+    password: &str,
+) -> Result<UserEntity, AppError> {
+    let target = find_user(db, user).await?;
+    policy.validate(password, &target.email)?;
+
+    let password_hash = params.hash(password.as_bytes())?;
+    user::update_user_password(db, target.id, &password_hash).await?;
+    session::revoke_user_sessions(db, target.id).await?;
+    audit::record(
+        db,
+        AuditEvent::new(AuditAction::PasswordReset, &cli_meta())
+            .target(format!("user:{}", target.id)),
+    )
+    .await;
+    Ok(target)
+}
+
+/// Blocks the user from logging in and ends all their sessions.
+pub async fn disable_user(db: &Database, user: &UserRef) -> Result<UserEntity, AppError> {
+    let target = find_user(db, user).await?;
+    user::set_user_disabled(db, target.id, true).await?;
+    session::revoke_user_sessions(db, target.id).await?;
+    audit::record(
+        db,
+        AuditEvent::new(AuditAction::UserDisable, &cli_meta())
+            .target(format!("user:{}", target.id)),
+    )
+    .await;
+    Ok(target)
+}
+
+/// Removes the second factor, e.g. of a user who lost their authenticator and
+/// recovery codes, so that they can log in with their password and enroll
+/// again.
+pub async fn reset_totp(db: &Database, user: &UserRef) -> Result<UserEntity, AppError> {
+    let target = find_user(db, user).await?;
+    totp::delete_totp(db, target.id).await?;
+    audit::record(
+        db,
+        AuditEvent::new(AuditAction::TotpReset, &cli_meta()).target(format!("user:{}", target.id)),
+    )
+    .await;
+    Ok(target)
+}
+
+pub async fn list_devices(db: &Database, user: &UserRef) -> Result<Vec<DeviceEntity>, AppError> {
+    let owner = find_user(db, user).await?;
+    device::list_devices(db, owner.id).await
+}
+
+/// Blocks the device from reconnecting and ends the sessions of its tokens.
+pub async fn revoke_device(
+    db: &Database,
+    user: &UserRef,
+    client_id: &str,
+) -> Result<DeviceEntity, AppError> {
+    let owner = find_user(db, user).await?;
+    let revoked = device::revoke_device(db, owner.id, client_id).await?;
+    session::revoke_device_sessions(db, owner.id, client_id).await?;
+    audit::record(
+        db,
+        AuditEvent::new(AuditAction::DeviceRevoke, &cli_meta())
+            .target(format!("client:{}", client_id)),
+    )
+    .await;
+    Ok(revoked)
+}
@@ -0,0 +1,29 @@
+use crate::{
+    error::AppError,
+    models::audit::{AuditEntry, AuditEvent, AuditQuery},
+    repository::{AuditRepository, Database},
+};
+
+/// Appends the event. A failed write is logged but does not fail the request
+/// being audited.
+pub async fn record(db: &Database, event: AuditEvent) {
+    if let Err(err) = db.record_audit(&event).await {
+        tracing::error!("Unable to write audit event {:?}: {:?}", event, err);
+    }
+}
+
+pub async fn count_audit(db: &Database, filter: &AuditQuery) -> Result<i64, AppError> {
+    db.count_audit(filter).await
+}
+
+/// Matching entries newest first. `before_id` continues a previous listing
+/// without the cost of a large offset, a negative `limit` lists everything.
+pub async fn list_audit(
+    db: &Database,
+    filter: &AuditQuery,
+    before_id: Option<i64>,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<AuditEntry>, AppError> {
+    db.list_audit(filter, before_id, limit, offset).await
+}
@@ -0,0 +1,170 @@
+use std::sync::Arc;
+
+use axum::async_trait;
+
+use crate::{
+    error::AppError,
+    models::{password::HashParams, user::UserEntity},
+    repository::Database,
+    service::user::{get_user_auth_provider, get_user_by_email, update_user_password},
+};
+
+/// What providers get to work with. Copied out of the state so that providers
+/// talking to remote servers do not hold the state lock.
+#[derive(Clone)]
+pub struct AuthContext {
+    pub db: Database,
+    pub hash_params: HashParams,
+    pub dummy_password_hash: String,
+}
+
+#[async_trait]
+pub trait AuthProvider: Send + Sync {
+    /// Identifies the provider, users are pinned to it by this name.
+    fn name(&self) -> &str;
+
+    /// Returns the local user the credentials belong to, or `None` if this
+    /// provider does not accept them.
+    async fn authenticate(
+        &self,
+        ctx: &AuthContext,
+        email: &str,
+        password: &str,
+    ) -> Result<Option<UserEntity>, AppError>;
+
+    /// Whether the provider creates local accounts for credentials without
+    /// one, only such providers are asked about unknown emails.
+    fn provisions(&self) -> bool {
+        false
+    }
+}
+
+/// Ordered chain of providers. Users pinned to a provider are only checked
+/// against that one and other local users only against their password.
+/// Unknown emails go to each provisioning provider in turn until one accepts.
+#[derive(Clone)]
+pub struct AuthProviders {
+    providers: Vec<Arc<dyn AuthProvider>>,
+}
+
+impl Default for AuthProviders {
+    fn default() -> Self {
+        Self::new(vec![Arc::new(PasswordProvider)])
+    }
+}
+
+impl AuthProviders {
+    pub fn new(providers: Vec<Arc<dyn AuthProvider>>) -> Self {
+        Self { providers }
+    }
+
+    pub fn push(&mut self, provider: Arc<dyn AuthProvider>) {
+        self.providers.push(provider);
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.providers
+            .iter()
+            .any(|provider| provider.name() == name)
+    }
+
+    /// A failing provider does not stop the chain. Its error is only returned
+    /// if no provider accepts nor rejects the credentials, so that an outage
+    /// of one provider still counts as a failed login.
+    pub async fn authenticate(
+        &self,
+        ctx: &AuthContext,
+        email: &str,
+        password: &str,
+    ) -> Result<Option<UserEntity>, AppError> {
+        let known = get_user_by_email(&ctx.db, email).await?.is_some();
+        let pinned = get_user_auth_provider(&ctx.db, email).await?;
+        let mut error = None;
+        let mut rejected = false;
+
+        for provider in &self.providers {
+            let eligible = match pinned.as_deref() {
+                Some(name) => name == provider.name(),
+                None if known => provider.name() == PasswordProvider.name(),
+                // the password provider still burns the time of a hash check
+                None => provider.name() == PasswordProvider.name() || provider.provisions(),
+            };
+            if !eligible {
+                continue;
+            }
+
+            match provider.authenticate(ctx, email, password).await {
+                Ok(Some(user)) => {
+                    // the provider may resolve another account than the login names
+                    let pinned = get_user_auth_provider(&ctx.db, &user.email).await?;
+                    if pinned.is_some_and(|name| name != provider.name()) {
+                        tracing::warn!(
+                            "Auth provider {} resolved user {} pinned to another provider",
+                            provider.name(),
+                            user.id
+                        );
+                        rejected = true;
+                        continue;
+                    }
+                    return Ok(Some(user));
+                }
+                Ok(None) => rejected = true,
+                Err(err) => {
+                    tracing::error!("Auth provider {} failed: {:?}", provider.name(), err);
+                    error = Some(err);
+                }
+            }
+        }
+
+        match error {
+            Some(err) if !rejected => Err(err),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Argon2 hashes in the `users` table.
+pub struct PasswordProvider;
+
+#[async_trait]
+impl AuthProvider for PasswordProvider {
+    fn name(&self) -> &str {
+        "password"
+    }
+
+    async fn authenticate(
+        &self,
+        ctx: &AuthContext,
+        email: &str,
+        password: &str,
+    ) -> Result<Option<UserEntity>, AppError> {
+        let user = get_user_by_email(&ctx.db, email).await?;
+
+        // unknown users and users without a local password are verified against
+        // a dummy hash so that all failures take the same time and return the
+        // same error
+        let candidate = user
+            .clone()
+            .filter(UserEntity::has_password)
+            .unwrap_or_else(|| UserEntity {
+                password: ctx.dummy_password_hash.clone(),
+                ..Default::default()
+            });
+        let is_verified = candidate
+            .verify_password(password.as_bytes())
+            .unwrap_or(false);
+
+        let user = match user {
+            Some(user) if is_verified && user.has_password() => user,
+            _ => return Ok(None),
+        };
+
+        if ctx.hash_params.needs_rehash(&user.password) {
+            tracing::debug!("Upgrading password hash of user {}", user.id);
+            let password_hash = ctx.hash_params.hash(password.as_bytes())?;
+            update_user_password(&ctx.db, user.id, &password_hash).await?;
+        }
+
+        Ok(Some(user))
+    }
+}
@@ -0,0 +1,47 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    error::AppError,
+    repository::{BackupRepository, Database},
+    utils::generate_token,
+};
+
+pub async fn backup_database(db: &Database, path: &Path) -> Result<(), AppError> {
+    db.backup(path).await
+}
+
+/// Temporary directory of a backup taken for download, removed with the
+/// backup once dropped.
+pub struct BackupDir(PathBuf);
+
+impl Drop for BackupDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Takes a backup into a temporary file and opens it, for streaming it out
+/// over HTTP. The file holds every password hash, so it is written to a
+/// directory only the owner can enter, which goes away with the returned
+/// [`BackupDir`].
+pub async fn backup_database_to_file(
+    db: &Database,
+) -> Result<(tokio::fs::File, BackupDir), AppError> {
+    let dir = std::env::temp_dir().join(format!("scytale-backup-{}", generate_token(16)));
+    let mut builder = tokio::fs::DirBuilder::new();
+    #[cfg(unix)]
+    builder.mode(0o700);
+    builder
+        .create(&dir)
+        .await
+        .map_err(|err| AppError::Backup(format!("unable to create {}: {}", dir.display(), err)))?;
+    let dir = BackupDir(dir);
+
+    let path = dir.0.join("backup");
+    backup_database(db, &path).await?;
+    let file = tokio::fs::File::open(&path)
+        .await
+        .map_err(|err| AppError::Backup(format!("unable to read the backup: {}", err)))?;
+
+    Ok((file, dir))
+}
@@ -0,0 +1,125 @@
+//! Clips relayed over the WebSocket are kept as history and queued for the
+//! offline devices of the user. Their content is sealed with the user's data
+//! key, without a master key nothing is kept.
+
+use chrono::Utc;
+
+use crate::{
+    error::AppError,
+    models::{
+        clip::{ClipEntity, ClipExport},
+        encryption::MasterKeys,
+    },
+    repository::{ClipRepository, Database, DeviceRepository},
+    service::encryption::{open_for_user, seal_for_user},
+    utils::generate_token,
+};
+
+/// Stores the clip `client_id` sent and queues it for every device of the
+/// user that could receive it but is not among the `online` ones. Nothing is
+/// stored when no master key is configured.
+pub async fn store_clip(
+    db: &Database,
+    keys: Option<&MasterKeys>,
+    user_id: i64,
+    client_id: &str,
+    text: &str,
+    online: &[&str],
+) -> Result<(), AppError> {
+    let Some(keys) = keys else {
+        return Ok(());
+    };
+
+    let id = generate_token(16);
+    let content = seal_for_user(db, keys, user_id, text.as_bytes(), id.as_bytes()).await?;
+    let clip = ClipEntity {
+        id,
+        user_id,
+        client_id: client_id.to_string(),
+        content,
+        created_at: Utc::now(),
+    };
+    db.insert_clip(&clip).await?;
+
+    let offline = db
+        .list_devices(user_id)
+        .await?
+        .into_iter()
+        .filter(|device| {
+            device.client_id != client_id
+                && !online.contains(&device.client_id.as_str())
+                && device.revoked_at.is_none()
+                && device.access.can_receive()
+                && device
+                    .expires_at
+                    .is_none_or(|expires_at| expires_at > clip.created_at)
+        });
+    for device in offline {
+        db.queue_clip(&clip.id, user_id, &device.client_id).await?;
+    }
+
+    Ok(())
+}
+
+/// Text of the clips queued for the device, oldest first. Only the clips
+/// that could be opened are removed from the queue, the others stay queued.
+pub async fn take_queued_clips(
+    db: &Database,
+    keys: Option<&MasterKeys>,
+    user_id: i64,
+    client_id: &str,
+) -> Result<Vec<String>, AppError> {
+    let mut texts = Vec::new();
+    for clip in db.list_queued_clips(user_id, client_id).await? {
+        match open_clip(db, keys, &clip).await {
+            Ok(text) => {
+                db.dequeue_clip(&clip.id, client_id).await?;
+                texts.push(text);
+            }
+            Err(err) => tracing::error!("Unable to open clip {}: {:?}", clip.id, err),
+        }
+    }
+
+    Ok(texts)
+}
+
+/// The user's clip history with the text opened, oldest first.
+pub async fn export_clips(
+    db: &Database,
+    keys: Option<&MasterKeys>,
+    user_id: i64,
+) -> Result<Vec<ClipExport>, AppError> {
+    let mut clips = Vec::new();
+    for clip in db.list_clips(user_id).await? {
+        let text = match open_clip(db, keys, &clip).await {
+            Ok(text) => Some(text),
+            Err(err) => {
+                tracing::error!("Unable to open clip {}: {:?}", clip.id, err);
+                None
+            }
+        };
+        clips.push(ClipExport {
+            id: clip.id,
+            client_id: clip.client_id,
+            text,
+            created_at: clip.created_at,
+        });
+    }
+
+    Ok(clips)
+}
+
+async fn open_clip(
+    db: &Database,
+    keys: Option<&MasterKeys>,
+    clip: &ClipEntity,
+) -> Result<String, AppError> {
+    let Some(keys) = keys else {
+        return Err(AppError::Encryption(
+            "no master key is configured".to_string(),
+        ));
+    };
+    let content = open_for_user(db, keys, clip.user_id, &clip.content, clip.id.as_bytes()).await?;
+
+    String::from_utf8(content).map_err(|_| AppError::Encryption("clip is not text".to_string()))
+}
@@ -0,0 +1,121 @@
+use std::net::IpAddr;
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    error::AppError,
+    models::{
+        device::{ClientCert, DeviceAccess, DeviceEntity, DeviceRegistration},
+        user::UserEntity,
+    },
+    repository::{Database, DeviceRepository},
+    service::user::{get_user_by_email, is_user_disabled},
+};
+
+/// Records a connecting client, creating the device on its first connection.
+/// The name is only taken from the client the first time, afterwards the user
+/// renames the device through the API.
+pub async fn register_device(
+    db: &Database,
+    user_id: i64,
+    param: &DeviceRegistration,
+    ip: Option<IpAddr>,
+) -> Result<DeviceEntity, AppError> {
+    if is_device_revoked(db, user_id, &param.id).await? {
+        return Err(AppError::DeviceRevoked);
+    }
+
+    db.upsert_device(user_id, param, ip).await
+}
+
+/// Registers a device that has never connected before. Binding tokens to an
+/// existing client id would let any plain login impersonate that device.
+pub async fn register_new_device(
+    db: &Database,
+    user_id: i64,
+    param: &DeviceRegistration,
+    ip: Option<IpAddr>,
+) -> Result<DeviceEntity, AppError> {
+    check_new_device(db, user_id, &param.id).await?;
+    db.upsert_device(user_id, param, ip).await
+}
+
+/// Fails unless `client_id` is still free, see [`register_new_device`].
+pub async fn check_new_device(
+    db: &Database,
+    user_id: i64,
+    client_id: &str,
+) -> Result<(), AppError> {
+    if is_device_revoked(db, user_id, client_id).await? {
+        return Err(AppError::DeviceRevoked);
+    }
+    if db.get_device(user_id, client_id).await?.is_some() {
+        return Err(AppError::DeviceAlreadyRegistered);
+    }
+    Ok(())
+}
+
+/// Guests are created ahead of their first connection with a generated client id.
+pub async fn create_guest_device(
+    db: &Database,
+    user_id: i64,
+    client_id: &str,
+    name: &str,
+    access: DeviceAccess,
+    expires_at: DateTime<Utc>,
+) -> Result<DeviceEntity, AppError> {
+    db.create_guest_device(user_id, client_id, name, access, expires_at)
+        .await
+}
+
+pub async fn is_device_revoked(
+    db: &Database,
+    user_id: i64,
+    client_id: &str,
+) -> Result<bool, AppError> {
+    db.is_device_revoked(user_id, client_id).await
+}
+
+/// Owner of the device a client certificate was issued to.
+pub async fn authenticate_client_cert(
+    db: &Database,
+    cert: &ClientCert,
+) -> Result<UserEntity, AppError> {
+    let user = get_user_by_email(db, &cert.email)
+        .await?
+        .ok_or(AppError::UnknownClientCert)?;
+    if is_user_disabled(db, user.id).await? {
+        return Err(AppError::UserDisabled);
+    }
+    if is_device_revoked(db, user.id, &cert.client_id).await? {
+        return Err(AppError::DeviceRevoked);
+    }
+    Ok(user)
+}
+
+/// Called when the client disconnects.
+pub async fn touch_device(db: &Database, user_id: i64, client_id: &str) -> Result<(), AppError> {
+    db.touch_device(user_id, client_id).await
+}
+
+pub async fn list_devices(db: &Database, user_id: i64) -> Result<Vec<DeviceEntity>, AppError> {
+    db.list_devices(user_id).await
+}
+
+pub async fn rename_device(
+    db: &Database,
+    user_id: i64,
+    client_id: &str,
+    name: &str,
+) -> Result<DeviceEntity, AppError> {
+    db.rename_device(user_id, client_id, name).await
+}
+
+/// Revoking an already revoked device keeps the original revocation time.
+pub async fn revoke_device(
+    db: &Database,
+    user_id: i64,
+    client_id: &str,
+) -> Result<DeviceEntity, AppError> {
+    db.revoke_device(user_id, client_id).await
+}
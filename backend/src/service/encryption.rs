@@ -0,0 +1,175 @@
+//! Envelope encryption of stored content. Every user has a random data key
+//! that encrypts their content, the data key is stored wrapped by the server
+//! master key, so rotating the master key only rewraps the data keys.
+
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN},
+    rand::{SecureRandom, SystemRandom},
+};
+
+use crate::{
+    error::AppError,
+    models::encryption::{DataKeyEntity, MasterKeys},
+    repository::{DataKeyRepository, Database},
+};
+
+/// Data keys rewrapped per round trip to the database.
+const ROTATION_BATCH: i64 = 100;
+
+fn random<const N: usize>() -> Result<[u8; N], AppError> {
+    let mut bytes = [0; N];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| AppError::Encryption("no randomness available".to_string()))?;
+
+    Ok(bytes)
+}
+
+/// ChaCha20-Poly1305 with a random nonce, returns the nonce followed by the
+/// ciphertext and tag.
+fn aead_seal(key: &[u8], plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, AppError> {
+    let key = LessSafeKey::new(
+        UnboundKey::new(&CHACHA20_POLY1305, key)
+            .map_err(|_| AppError::Encryption("invalid key".to_string()))?,
+    );
+    let nonce = random::<NONCE_LEN>()?;
+
+    let mut sealed = nonce.to_vec();
+    let mut in_out = plaintext.to_vec();
+    key.seal_in_place_append_tag(
+        Nonce::assume_unique_for_key(nonce),
+        Aad::from(aad),
+        &mut in_out,
+    )
+    .map_err(|_| AppError::Encryption("unable to encrypt".to_string()))?;
+    sealed.append(&mut in_out);
+
+    Ok(sealed)
+}
+
+/// Fails for tampered data, the wrong key and the wrong `aad` alike.
+fn aead_open(key: &[u8], sealed: &[u8], aad: &[u8]) -> Result<Vec<u8>, AppError> {
+    let failed = || AppError::Encryption("unable to decrypt".to_string());
+    let key = LessSafeKey::new(UnboundKey::new(&CHACHA20_POLY1305, key).map_err(|_| failed())?);
+    if sealed.len() < NONCE_LEN {
+        return Err(failed());
+    }
+
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| failed())?;
+    let mut in_out = ciphertext.to_vec();
+    let plaintext_len = key
+        .open_in_place(nonce, Aad::from(aad), &mut in_out)
+        .map_err(|_| failed())?
+        .len();
+    in_out.truncate(plaintext_len);
+
+    Ok(in_out)
+}
+
+/// Binds a wrapped data key to its user, so rows cannot be swapped around.
+fn wrap_aad(user_id: i64) -> Vec<u8> {
+    format!("scytale data key {}", user_id).into_bytes()
+}
+
+fn unwrap_data_key(keys: &MasterKeys, data_key: &DataKeyEntity) -> Result<Vec<u8>, AppError> {
+    let master_key = keys.get(&data_key.master_key_id).ok_or_else(|| {
+        AppError::Encryption(format!("unknown master key {}", data_key.master_key_id))
+    })?;
+
+    aead_open(
+        &master_key.key,
+        &data_key.wrapped_key,
+        &wrap_aad(data_key.user_id),
+    )
+}
+
+/// The user's data key, created on first use.
+async fn get_or_create_data_key(
+    db: &Database,
+    keys: &MasterKeys,
+    user_id: i64,
+) -> Result<Vec<u8>, AppError> {
+    if let Some(data_key) = db.get_data_key(user_id).await? {
+        return unwrap_data_key(keys, &data_key);
+    }
+
+    let key = random::<32>()?;
+    let wrapped = aead_seal(&keys.current.key, &key, &wrap_aad(user_id))?;
+    // a concurrent first use may have won, everyone uses the stored key
+    let data_key = db
+        .insert_data_key(user_id, &keys.current.id, &wrapped)
+        .await?;
+
+    unwrap_data_key(keys, &data_key)
+}
+
+/// Encrypts content of the user before it is stored. `aad` is not encrypted
+/// but must be passed to [`open_for_user`] unchanged, e.g. the id of the row.
+pub async fn seal_for_user(
+    db: &Database,
+    keys: &MasterKeys,
+    user_id: i64,
+    plaintext: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>, AppError> {
+    let key = get_or_create_data_key(db, keys, user_id).await?;
+    aead_seal(&key, plaintext, aad)
+}
+
+pub async fn open_for_user(
+    db: &Database,
+    keys: &MasterKeys,
+    user_id: i64,
+    sealed: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>, AppError> {
+    let data_key = db
+        .get_data_key(user_id)
+        .await?
+        .ok_or_else(|| AppError::Encryption(format!("user {} has no data key", user_id)))?;
+    let key = unwrap_data_key(keys, &data_key)?;
+
+    aead_open(&key, sealed, aad)
+}
+
+/// Rewraps every data key that is still wrapped by one of `keys.previous`
+/// with `keys.current`, returns how many were rewrapped. Content is not
+/// touched, it stays encrypted with the same data keys.
+pub async fn rotate_master_key(db: &Database, keys: &MasterKeys) -> Result<u64, AppError> {
+    let mut rotated = 0;
+    // rewrapping to the same id would list the same keys forever
+    let previous_keys = keys.previous.iter().filter(|key| key.id != keys.current.id);
+    for previous in previous_keys {
+        loop {
+            let batch = db
+                .list_data_keys_wrapped_by(&previous.id, ROTATION_BATCH)
+                .await?;
+            if batch.is_empty() {
+                break;
+            }
+
+            for data_key in batch {
+                let key = unwrap_data_key(keys, &data_key)?;
+                let wrapped = aead_seal(&keys.current.key, &key, &wrap_aad(data_key.user_id))?;
+                // skipped if the row changed since it was listed
+                if db
+                    .rewrap_data_key(data_key.user_id, &previous.id, &keys.current.id, &wrapped)
+                    .await?
+                {
+                    rotated += 1;
+                }
+            }
+        }
+    }
+
+    let unknown = db.count_data_keys_not_wrapped_by(&keys.current.id).await?;
+    if unknown > 0 {
+        tracing::warn!(
+            "{} data keys are wrapped by master keys that are not configured",
+            unknown
+        );
+    }
+
+    Ok(rotated)
+}
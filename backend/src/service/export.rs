@@ -0,0 +1,38 @@
+use chrono::Utc;
+
+use crate::{
+    error::AppError,
+    models::{audit::AuditQuery, encryption::MasterKeys, export::UserExport},
+    repository::{ClipRepository, Database},
+    service::{
+        audit::list_audit, clip::export_clips, device::list_devices,
+        identity::list_user_identities, invite::list_invites_created_by,
+        retention::get_user_retention, session::list_user_sessions, user::get_user_details,
+    },
+};
+
+/// Clips are opened with the master keys, see [`export_clips`].
+pub async fn export_user_data(
+    db: &Database,
+    keys: Option<&MasterKeys>,
+    user_id: i64,
+) -> Result<UserExport, AppError> {
+    let audit_filter = AuditQuery {
+        actor_id: Some(user_id),
+        ..Default::default()
+    };
+
+    Ok(UserExport {
+        exported_at: Utc::now(),
+        profile: get_user_details(db, user_id).await?,
+        sessions: list_user_sessions(db, user_id).await?,
+        devices: list_devices(db, user_id).await?,
+        identities: list_user_identities(db, user_id).await?,
+        invites_created: list_invites_created_by(db, user_id).await?,
+        // a negative limit lists everything
+        audit_log: list_audit(db, &audit_filter, None, -1, 0).await?,
+        clips: export_clips(db, keys, user_id).await?,
+        clip_queue: db.list_clip_queue(user_id).await?,
+        retention: get_user_retention(db, user_id).await?,
+    })
+}
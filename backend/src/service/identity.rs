@@ -0,0 +1,41 @@
+use crate::{
+    error::AppError,
+    models::oidc::IdentityEntity,
+    repository::{Database, IdentityRepository},
+};
+
+/// Local user the external identity is linked to.
+pub async fn get_identity_user(
+    db: &Database,
+    provider: &str,
+    subject: &str,
+) -> Result<Option<i64>, AppError> {
+    db.get_identity_user(provider, subject).await
+}
+
+pub async fn link_identity(
+    db: &Database,
+    provider: &str,
+    subject: &str,
+    user_id: i64,
+    email: Option<&str>,
+) -> Result<(), AppError> {
+    db.link_identity(provider, subject, user_id, email).await
+}
+
+/// Records a login through the identity, keeping the last known email.
+pub async fn touch_identity(
+    db: &Database,
+    provider: &str,
+    subject: &str,
+    email: Option<&str>,
+) -> Result<(), AppError> {
+    db.touch_identity(provider, subject, email).await
+}
+
+pub async fn list_user_identities(
+    db: &Database,
+    user_id: i64,
+) -> Result<Vec<IdentityEntity>, AppError> {
+    db.list_user_identities(user_id).await
+}
@@ -0,0 +1,52 @@
+use chrono::{DateTime, Utc};
+
+use crate::{
+    error::AppError,
+    models::{invite::InviteEntity, user::Role},
+    repository::{Database, InviteRepository},
+};
+
+pub async fn create_invite(
+    db: &Database,
+    code_hash: &str,
+    role: &Role,
+    max_uses: i64,
+    created_by: i64,
+    expires_at: DateTime<Utc>,
+) -> Result<InviteEntity, AppError> {
+    db.create_invite(code_hash, role, max_uses, created_by, expires_at)
+        .await
+}
+
+/// Invites that are neither revoked, expired nor used up.
+pub async fn list_outstanding_invites(db: &Database) -> Result<Vec<InviteEntity>, AppError> {
+    db.list_outstanding_invites().await
+}
+
+pub async fn revoke_invite(db: &Database, id: i64) -> Result<(), AppError> {
+    db.revoke_invite(id).await
+}
+
+/// Role of a still usable invite, without using it.
+pub async fn get_invite_role(db: &Database, code_hash: &str) -> Result<Role, AppError> {
+    db.get_invite_role(code_hash).await
+}
+
+/// Counts one use of the invite, failing if it is no longer usable. The check
+/// and the increment are one statement so concurrent uses cannot exceed
+/// `max_uses`.
+pub async fn use_invite(db: &Database, code_hash: &str) -> Result<Role, AppError> {
+    db.use_invite(code_hash).await
+}
+
+/// Takes back a use counted by [`use_invite`] whose registration failed.
+pub async fn release_invite(db: &Database, code_hash: &str) -> Result<(), AppError> {
+    db.release_invite(code_hash).await
+}
+
+pub async fn list_invites_created_by(
+    db: &Database,
+    user_id: i64,
+) -> Result<Vec<InviteEntity>, AppError> {
+    db.list_invites_created_by(user_id).await
+}
@@ -0,0 +1,175 @@
+use std::time::Duration;
+
+use axum::async_trait;
+use ldap3::{ldap_escape, Ldap, LdapConnAsync, LdapConnSettings, Scope, SearchEntry};
+
+use crate::{
+    error::AppError,
+    models::{
+        ldap::LdapConfig,
+        user::{UserCreate, UserEntity},
+    },
+    service::{
+        auth_provider::{AuthContext, AuthProvider},
+        role::role_exists,
+        user::{create_user, get_user_auth_provider, get_user_by_email, set_user_auth_provider},
+    },
+    utils::generate_token,
+};
+
+const LDAP_TIMEOUT: Duration = Duration::from_secs(10);
+
+struct DirectoryUser {
+    dn: String,
+    email: Option<String>,
+    name: Option<String>,
+}
+
+/// Authenticates by binding to the directory as the user.
+pub struct LdapProvider {
+    pub config: LdapConfig,
+}
+
+impl LdapProvider {
+    pub fn new(config: LdapConfig) -> Self {
+        Self { config }
+    }
+
+    async fn connect(&self) -> Result<Ldap, AppError> {
+        let settings = LdapConnSettings::new().set_conn_timeout(LDAP_TIMEOUT);
+        let (conn, ldap) = LdapConnAsync::with_settings(settings, &self.config.url)
+            .await
+            .map_err(ldap_error)?;
+        ldap3::drive!(conn);
+        Ok(ldap)
+    }
+
+    async fn find_user(
+        &self,
+        ldap: &mut Ldap,
+        login: &str,
+    ) -> Result<Option<DirectoryUser>, AppError> {
+        let config = &self.config;
+        if let (Some(dn), Some(password)) = (&config.bind_dn, &config.bind_password) {
+            ldap.simple_bind(dn, password)
+                .await
+                .and_then(|result| result.success())
+                .map_err(ldap_error)?;
+        }
+
+        let filter = config.user_filter.replace("{login}", &ldap_escape(login));
+        let attributes = [
+            config.email_attribute.as_str(),
+            config.name_attribute.as_str(),
+        ];
+        let (entries, _) = ldap
+            .search(&config.base_dn, Scope::Subtree, &filter, attributes)
+            .await
+            .and_then(|result| result.success())
+            .map_err(ldap_error)?;
+
+        // an ambiguous filter must not pick one of several accounts
+        if entries.len() != 1 {
+            return Ok(None);
+        }
+        let mut entry = SearchEntry::construct(entries.into_iter().next().unwrap());
+        let mut attribute = |name: &str| {
+            entry
+                .attrs
+                .remove(name)
+                .and_then(|values| values.into_iter().next())
+        };
+
+        Ok(Some(DirectoryUser {
+            email: attribute(&config.email_attribute),
+            name: attribute(&config.name_attribute),
+            dn: entry.dn,
+        }))
+    }
+
+    async fn bind(&self, login: &str, password: &str) -> Result<Option<DirectoryUser>, AppError> {
+        let mut ldap = self.connect().await?;
+        let user = match self.find_user(&mut ldap, login).await? {
+            Some(user) => user,
+            None => return Ok(None),
+        };
+
+        let bound = ldap
+            .simple_bind(&user.dn, password)
+            .await
+            .map_err(ldap_error)?
+            .success()
+            .is_ok();
+        let _ = ldap.unbind().await;
+
+        Ok(bound.then_some(user))
+    }
+}
+
+#[async_trait]
+impl AuthProvider for LdapProvider {
+    fn name(&self) -> &str {
+        "ldap"
+    }
+
+    fn provisions(&self) -> bool {
+        self.config.provision
+    }
+
+    async fn authenticate(
+        &self,
+        ctx: &AuthContext,
+        email: &str,
+        password: &str,
+    ) -> Result<Option<UserEntity>, AppError> {
+        // an empty password is an unauthenticated bind, which most servers accept
+        if password.is_empty() {
+            return Ok(None);
+        }
+
+        let directory_user = match self.bind(email, password).await? {
+            Some(user) => user,
+            None => return Ok(None),
+        };
+
+        let email = directory_user.email.unwrap_or_else(|| email.to_string());
+        if let Some(user) = get_user_by_email(&ctx.db, &email).await? {
+            // a directory entry claiming the email of a local account must not
+            // take it over, only accounts handed to the directory are its own
+            let pinned = get_user_auth_provider(&ctx.db, &user.email).await?;
+            if pinned.as_deref() != Some(self.name()) {
+                tracing::debug!(
+                    "Directory user {} matches user {} not pinned to it",
+                    directory_user.dn,
+                    user.id
+                );
+                return Ok(None);
+            }
+            return Ok(Some(user));
+        }
+        if !self.config.provision {
+            tracing::debug!("Directory user {} has no local account", directory_user.dn);
+            return Ok(None);
+        }
+        if !role_exists(&ctx.db, &self.config.default_role).await? {
+            return Err(AppError::RoleDoesNotExist);
+        }
+
+        // the random password is never handed out, the directory stays the only way in
+        let mut user = UserCreate {
+            name: directory_user.name.unwrap_or_else(|| email.clone()),
+            email,
+            password: generate_token(32),
+            role: self.config.default_role.clone(),
+        };
+        let user = create_user(&ctx.db, &mut user, &ctx.hash_params).await?;
+        set_user_auth_provider(&ctx.db, user.id, Some(self.name())).await?;
+
+        Ok(Some(user))
+    }
+}
+
+fn ldap_error(err: ldap3::LdapError) -> AppError {
+    tracing::error!("Error talking to directory server: {:?}", err);
+    AppError::AuthProviderUnavailable
+}
@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::{jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use reqwest::Url;
+use sha2::{Digest, Sha256};
+use tokio::sync::{Mutex, RwLock};
+
+use crate::{
+    error::AppError,
+    models::oidc::{IdTokenClaims, OidcConfig, OidcTokenResponse, ProviderMetadata},
+    utils::generate_token,
+};
+
+/// How long the user has to complete the login at the provider.
+const PENDING_LOGIN_MINUTES: i64 = 10;
+/// Pending logins above which new ones are refused.
+pub(crate) const MAX_PENDING_LOGINS: usize = 1024;
+
+struct PendingLogin {
+    nonce: String,
+    code_verifier: String,
+    expires_at: DateTime<Utc>,
+}
+
+/// Authorization code flow with PKCE against one provider. Discovery and keys
+/// are fetched lazily and cached, the keys are refetched when a token is
+/// signed with an unknown key id.
+pub struct OidcClient {
+    pub config: OidcConfig,
+    http: reqwest::Client,
+    metadata: RwLock<Option<ProviderMetadata>>,
+    jwks: RwLock<Option<JwkSet>>,
+    /// Keyed by the `state` parameter.
+    pending: Mutex<HashMap<String, PendingLogin>>,
+}
+
+impl OidcClient {
+    pub fn new(config: OidcConfig) -> Self {
+        Self {
+            config,
+            http: reqwest::Client::new(),
+            metadata: RwLock::new(None),
+            jwks: RwLock::new(None),
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Identities are stored per issuer, with or without a trailing slash.
+    pub fn provider(&self) -> &str {
+        self.config.issuer_url.trim_end_matches('/')
+    }
+
+    pub async fn metadata(&self) -> Result<ProviderMetadata, AppError> {
+        if let Some(metadata) = self.metadata.read().await.as_ref() {
+            return Ok(metadata.clone());
+        }
+
+        let issuer = self.provider();
+        let url = format!("{}/.well-known/openid-configuration", issuer);
+        let metadata: ProviderMetadata = self.get_json(&url).await?;
+        if metadata.issuer.trim_end_matches('/') != issuer {
+            return Err(AppError::OidcProvider(format!(
+                "discovery issuer {} does not match {}",
+                metadata.issuer, self.config.issuer_url
+            )));
+        }
+
+        *self.metadata.write().await = Some(metadata.clone());
+        Ok(metadata)
+    }
+
+    /// Where to send the browser, remembers the nonce and PKCE verifier until
+    /// the provider redirects back.
+    pub async fn authorization_url(&self) -> Result<String, AppError> {
+        let metadata = self.metadata().await?;
+
+        let state = generate_token(32);
+        let nonce = generate_token(32);
+        let code_verifier = generate_token(64);
+        let code_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()));
+
+        let mut url = Url::parse(&metadata.authorization_endpoint)
+            .map_err(|err| AppError::OidcProvider(err.to_string()))?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.config.client_id)
+            .append_pair("redirect_uri", &self.config.redirect_url)
+            .append_pair("scope", &self.config.scopes.join(" "))
+            .append_pair("state", &state)
+            .append_pair("nonce", &nonce)
+            .append_pair("code_challenge", &code_challenge)
+            .append_pair("code_challenge_method", "S256");
+
+        let now = Utc::now();
+        let mut pending = self.pending.lock().await;
+        pending.retain(|_, login| login.expires_at > now);
+        if pending.len() >= MAX_PENDING_LOGINS {
+            return Err(AppError::TooManyAttempts(PENDING_LOGIN_MINUTES * 60));
+        }
+        pending.insert(
+            state,
+            PendingLogin {
+                nonce,
+                code_verifier,
+                expires_at: now + Duration::minutes(PENDING_LOGIN_MINUTES),
+            },
+        );
+
+        Ok(url.into())
+    }
+
+    /// Redeems the authorization code and returns the validated ID token claims.
+    /// Each `state` can only be used once.
+    pub async fn exchange(&self, code: &str, state: &str) -> Result<IdTokenClaims, AppError> {
+        let login = self
+            .pending
+            .lock()
+            .await
+            .remove(state)
+            .filter(|login| login.expires_at > Utc::now())
+            .ok_or(AppError::OidcLoginFailed)?;
+
+        let metadata = self.metadata().await?;
+        let mut request = self.http.post(&metadata.token_endpoint).form(&[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &self.config.redirect_url),
+            ("client_id", &self.config.client_id),
+            ("code_verifier", &login.code_verifier),
+        ]);
+        if let Some(secret) = &self.config.client_secret {
+            request = request.basic_auth(&self.config.client_id, Some(secret));
+        }
+
+        let response = request.send().await.map_err(provider_error)?;
+        if !response.status().is_success() {
+            tracing::debug!(
+                "Token endpoint returned {}: {:?}",
+                response.status(),
+                response.text().await
+            );
+            return Err(AppError::OidcLoginFailed);
+        }
+        let tokens: OidcTokenResponse = response.json().await.map_err(provider_error)?;
+
+        self.validate_id_token(&tokens.id_token, &login.nonce).await
+    }
+
+    pub async fn validate_id_token(
+        &self,
+        id_token: &str,
+        nonce: &str,
+    ) -> Result<IdTokenClaims, AppError> {
+        let header = jsonwebtoken::decode_header(id_token).map_err(|err| {
+            tracing::debug!("Invalid id token header: {:?}", err);
+            AppError::OidcLoginFailed
+        })?;
+        // symmetric algorithms would let anyone holding the client secret mint tokens
+        if matches!(
+            header.alg,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        ) {
+            return Err(AppError::OidcLoginFailed);
+        }
+
+        let key = self.decoding_key(header.kid.as_deref()).await?;
+        let mut validation = Validation::new(header.alg);
+        // exactly as discovered, trailing slash included
+        validation.set_issuer(&[&self.metadata().await?.issuer]);
+        validation.set_audience(&[&self.config.client_id]);
+
+        let claims = jsonwebtoken::decode::<IdTokenClaims>(id_token, &key, &validation)
+            .map_err(|err| {
+                tracing::debug!("Invalid id token: {:?}", err);
+                AppError::OidcLoginFailed
+            })?
+            .claims;
+
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err(AppError::OidcLoginFailed);
+        }
+
+        Ok(claims)
+    }
+
+    async fn decoding_key(&self, kid: Option<&str>) -> Result<DecodingKey, AppError> {
+        let cached = self.jwks.read().await.clone();
+        let jwks = match cached {
+            Some(jwks) if kid.is_none_or(|kid| jwks.find(kid).is_some()) => jwks,
+            _ => {
+                let metadata = self.metadata().await?;
+                let jwks: JwkSet = self.get_json(&metadata.jwks_uri).await?;
+                *self.jwks.write().await = Some(jwks.clone());
+                jwks
+            }
+        };
+
+        let jwk = match kid {
+            Some(kid) => jwks.find(kid),
+            None => jwks.keys.first(),
+        }
+        .ok_or(AppError::OidcLoginFailed)?;
+
+        DecodingKey::from_jwk(jwk).map_err(|err| AppError::OidcProvider(err.to_string()))
+    }
+
+    async fn get_json<T: serde::de::DeserializeOwned>(&self, url: &str) -> Result<T, AppError> {
+        self.http
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(provider_error)?
+            .json()
+            .await
+            .map_err(provider_error)
+    }
+}
+
+fn provider_error(err: reqwest::Error) -> AppError {
+    tracing::error!("Error talking to identity provider: {:?}", err);
+    AppError::OidcProvider(err.to_string())
+}
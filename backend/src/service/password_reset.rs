@@ -0,0 +1,34 @@
+use chrono::{DateTime, Utc};
+
+use crate::{
+    error::AppError,
+    repository::{Database, PasswordResetRepository},
+};
+
+/// Stores a new reset token for the user, dropping any unused earlier ones.
+pub async fn create_password_reset(
+    db: &Database,
+    user_id: i64,
+    created_by: i64,
+    token_hash: &str,
+    expires_at: DateTime<Utc>,
+) -> Result<(), AppError> {
+    db.create_password_reset(user_id, created_by, token_hash, expires_at)
+        .await
+}
+
+/// User of an unused and unexpired token, without consuming it.
+pub async fn get_password_reset_user(db: &Database, token_hash: &str) -> Result<i64, AppError> {
+    db.get_password_reset_user(token_hash).await
+}
+
+/// Marks an unused, unexpired token as used, sets the new password hash and
+/// revokes every session of its user, returning the user. All of it happens
+/// in one transaction so the token can only be consumed once.
+pub async fn consume_password_reset(
+    db: &Database,
+    token_hash: &str,
+    password_hash: &str,
+) -> Result<i64, AppError> {
+    db.consume_password_reset(token_hash, password_hash).await
+}
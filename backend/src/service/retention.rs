@@ -0,0 +1,72 @@
+use chrono::{Duration, Utc};
+
+use crate::{
+    error::AppError,
+    models::retention::{RetentionPolicy, UserRetention},
+    repository::{Database, RetentionRepository},
+};
+
+pub async fn get_user_retention(db: &Database, user_id: i64) -> Result<UserRetention, AppError> {
+    let days = db.get_user_retention(user_id).await?;
+    Ok(UserRetention { days })
+}
+
+pub async fn set_user_retention(
+    db: &Database,
+    user_id: i64,
+    retention: &UserRetention,
+) -> Result<(), AppError> {
+    if retention
+        .days
+        .is_some_and(|days| !(0..=UserRetention::MAX_DAYS).contains(&days))
+    {
+        return Err(AppError::InvalidRetention);
+    }
+
+    db.set_user_retention(user_id, retention.days).await
+}
+
+/// Which of the per-user tables a purge covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserRows {
+    Sessions,
+    PasswordResets,
+    GuestDevices,
+    History,
+    Queue,
+}
+
+/// Purges the rows of every user with their own retention, then those of all
+/// other users with the policy's. Returns the number of deleted rows.
+pub async fn purge_user_rows(
+    db: &Database,
+    policy: &RetentionPolicy,
+    rows: UserRows,
+) -> Result<u64, AppError> {
+    let now = Utc::now();
+    let retentions = db
+        .list_user_retention()
+        .await?
+        .into_iter()
+        .map(|(user_id, days)| (Some(user_id), days))
+        .chain(std::iter::once((None, policy.days)));
+
+    let mut removed = 0;
+    for (user_id, days) in retentions {
+        let before = now - Duration::days(days);
+        removed += match rows {
+            UserRows::Sessions => db.purge_sessions(user_id, before).await?,
+            UserRows::PasswordResets => db.purge_password_resets(user_id, before).await?,
+            UserRows::GuestDevices => db.purge_guest_devices(user_id, before).await?,
+            UserRows::History => db.purge_clips(user_id, before).await?,
+            UserRows::Queue => db.purge_clip_queue(user_id, before).await?,
+        };
+    }
+
+    Ok(removed)
+}
+
+pub async fn purge_invites(db: &Database, policy: &RetentionPolicy) -> Result<u64, AppError> {
+    db.purge_invites(Utc::now() - Duration::days(policy.days))
+        .await
+}
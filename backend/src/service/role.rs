@@ -0,0 +1,105 @@
+use crate::{
+    error::AppError,
+    models::{
+        role::{Permission, RoleEntity, RoleUpsert},
+        user::{Role, UserDetails, UserListQuery},
+    },
+    repository::{Database, RoleRepository, UserRepository},
+};
+
+pub async fn role_exists(db: &Database, role: &Role) -> Result<bool, AppError> {
+    db.role_exists(role).await
+}
+
+pub async fn has_permission(
+    db: &Database,
+    role: &Role,
+    permission: Permission,
+) -> Result<bool, AppError> {
+    db.has_permission(role, permission).await
+}
+
+pub async fn get_role(db: &Database, role: &Role) -> Result<RoleEntity, AppError> {
+    db.get_role(role).await
+}
+
+pub async fn list_roles(db: &Database) -> Result<Vec<RoleEntity>, AppError> {
+    db.list_roles().await
+}
+
+/// Fails with [`AppError::InsufficientPermission`] unless `assigner` may hand
+/// out `role`, that is it grants `manage_roles` or every permission of `role`.
+pub async fn check_assignable(db: &Database, assigner: &Role, role: &Role) -> Result<(), AppError> {
+    let granted = db.get_role(assigner).await?.permissions;
+    if granted.contains(&Permission::ManageRoles) {
+        return Ok(());
+    }
+    let required = db.get_role(role).await?.permissions;
+    match required
+        .iter()
+        .all(|permission| granted.contains(permission))
+    {
+        true => Ok(()),
+        false => Err(AppError::InsufficientPermission),
+    }
+}
+
+/// Whether `role` is the only one granting `manage_roles`, without it nobody
+/// could change the roles anymore.
+async fn is_last_role_manager(db: &Database, role: &Role) -> Result<bool, AppError> {
+    let managers: Vec<_> = db
+        .list_roles()
+        .await?
+        .into_iter()
+        .filter(|entity| entity.permissions.contains(&Permission::ManageRoles))
+        .collect();
+    Ok(managers.len() == 1 && managers[0].name == *role)
+}
+
+/// Whether `user` is the only enabled user whose role grants `manage_roles`,
+/// demoting, disabling or deleting them would lock everyone out of the roles.
+pub async fn is_last_role_manager_user(
+    db: &Database,
+    user: &UserDetails,
+) -> Result<bool, AppError> {
+    if user.disabled_at.is_some()
+        || !has_permission(db, &user.role, Permission::ManageRoles).await?
+    {
+        return Ok(false);
+    }
+
+    let mut managers = 0;
+    for role in db.list_roles().await? {
+        if !role.permissions.contains(&Permission::ManageRoles) {
+            continue;
+        }
+        let filter = UserListQuery {
+            role: Some(role.name),
+            disabled: Some(false),
+            ..Default::default()
+        };
+        managers += db.count_users(&filter).await?;
+    }
+    Ok(managers == 1)
+}
+
+/// Creates the role or replaces its description and whole permission set.
+pub async fn upsert_role(
+    db: &Database,
+    role: &Role,
+    payload: &RoleUpsert,
+) -> Result<RoleEntity, AppError> {
+    if !payload.permissions.contains(&Permission::ManageRoles)
+        && is_last_role_manager(db, role).await?
+    {
+        return Err(AppError::LastRoleManager);
+    }
+    db.upsert_role(role, payload).await
+}
+
+pub async fn delete_role(db: &Database, role: &Role) -> Result<(), AppError> {
+    if is_last_role_manager(db, role).await? {
+        return Err(AppError::LastRoleManager);
+    }
+    db.delete_role(role).await
+}
@@ -0,0 +1,177 @@
+//! Background jobs started with the server, each in its own task so that a
+//! slow vacuum does not hold back the purges.
+
+use std::sync::{Arc, Mutex};
+
+use chrono::{Duration, Utc};
+use tokio::{task::JoinHandle, time::MissedTickBehavior};
+
+use crate::{
+    error::AppError,
+    models::retention::{JobStatus, RetentionPolicy},
+    repository::{Database, RetentionRepository},
+};
+
+use super::retention::{purge_invites, purge_user_rows, UserRows};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Job {
+    PurgeSessions,
+    PurgePasswordResets,
+    PurgeGuestDevices,
+    PurgeInvites,
+    PurgeHistory,
+    PurgeQueue,
+    Vacuum,
+}
+
+impl Job {
+    pub const ALL: [Job; 7] = [
+        Job::PurgeSessions,
+        Job::PurgePasswordResets,
+        Job::PurgeGuestDevices,
+        Job::PurgeInvites,
+        Job::PurgeHistory,
+        Job::PurgeQueue,
+        Job::Vacuum,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::PurgeSessions => "purge_sessions",
+            Self::PurgePasswordResets => "purge_password_resets",
+            Self::PurgeGuestDevices => "purge_guest_devices",
+            Self::PurgeInvites => "purge_invites",
+            Self::PurgeHistory => "purge_history",
+            Self::PurgeQueue => "purge_queue",
+            Self::Vacuum => "vacuum",
+        }
+    }
+
+    fn interval(self, policy: &RetentionPolicy) -> Duration {
+        match self {
+            Self::Vacuum => policy.vacuum_interval,
+            _ => policy.purge_interval,
+        }
+    }
+
+    /// Returns the number of deleted rows.
+    async fn run(self, db: &Database, policy: &RetentionPolicy) -> Result<u64, AppError> {
+        match self {
+            Self::PurgeSessions => purge_user_rows(db, policy, UserRows::Sessions).await,
+            Self::PurgePasswordResets => {
+                purge_user_rows(db, policy, UserRows::PasswordResets).await
+            }
+            Self::PurgeGuestDevices => purge_user_rows(db, policy, UserRows::GuestDevices).await,
+            Self::PurgeInvites => purge_invites(db, policy).await,
+            Self::PurgeHistory => purge_user_rows(db, policy, UserRows::History).await,
+            Self::PurgeQueue => purge_user_rows(db, policy, UserRows::Queue).await,
+            Self::Vacuum => db.vacuum().await.map(|_| 0),
+        }
+    }
+}
+
+/// Status of every job, shared by the job tasks and the admin API.
+#[derive(Debug, Clone, Default)]
+pub struct Scheduler {
+    jobs: Arc<Mutex<Vec<JobStatus>>>,
+    tasks: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl Scheduler {
+    pub fn jobs(&self) -> Vec<JobStatus> {
+        self.jobs.lock().expect("scheduler poisoned").clone()
+    }
+
+    /// Spawns a task per job, every job runs right away and then once per
+    /// interval of the policy.
+    pub fn start(&self, db: Database, policy: RetentionPolicy) {
+        for job in Job::ALL {
+            let interval = job.interval(&policy);
+            self.update(job, &policy, |status| status.next_run_at = Some(Utc::now()));
+
+            let scheduler = self.clone();
+            let db = db.clone();
+            let policy = policy.clone();
+            let task = tokio::spawn(async move {
+                let period = interval
+                    .to_std()
+                    .unwrap_or(std::time::Duration::from_secs(60))
+                    .max(std::time::Duration::from_secs(1));
+                let mut ticker = tokio::time::interval(period);
+                ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                loop {
+                    ticker.tick().await;
+                    let _ = scheduler.run(job, &db, &policy).await;
+                }
+            });
+            self.tasks.lock().expect("scheduler poisoned").push(task);
+        }
+    }
+
+    /// Cancels the job tasks, a running job is dropped mid-way.
+    pub fn stop(&self) {
+        for task in self.tasks.lock().expect("scheduler poisoned").drain(..) {
+            task.abort();
+        }
+    }
+
+    /// Runs the job once now and records the outcome.
+    pub async fn run(
+        &self,
+        job: Job,
+        db: &Database,
+        policy: &RetentionPolicy,
+    ) -> Result<u64, AppError> {
+        self.update(job, policy, |status| {
+            status.running = true;
+            status.last_started_at = Some(Utc::now());
+        });
+
+        let result = job.run(db, policy).await;
+        match &result {
+            Ok(removed) => tracing::debug!("Job {} removed {} rows", job.name(), removed),
+            Err(err) => tracing::error!("Job {} failed: {:?}", job.name(), err),
+        }
+
+        let now = Utc::now();
+        self.update(job, policy, |status| {
+            status.running = false;
+            status.runs += 1;
+            status.last_finished_at = Some(now);
+            status.next_run_at = Some(now + job.interval(policy));
+            match &result {
+                Ok(removed) => {
+                    status.last_removed = Some(*removed);
+                    status.last_error = None;
+                }
+                Err(err) => status.last_error = Some(format!("{:?}", err)),
+            }
+        });
+
+        result
+    }
+
+    fn update(&self, job: Job, policy: &RetentionPolicy, update: impl FnOnce(&mut JobStatus)) {
+        let mut jobs = self.jobs.lock().expect("scheduler poisoned");
+        let index = match jobs.iter().position(|status| status.name == job.name()) {
+            Some(index) => index,
+            None => {
+                jobs.push(JobStatus {
+                    name: job.name().to_string(),
+                    interval_seconds: job.interval(policy).num_seconds(),
+                    running: false,
+                    runs: 0,
+                    last_started_at: None,
+                    last_finished_at: None,
+                    last_removed: None,
+                    last_error: None,
+                    next_run_at: None,
+                });
+                jobs.len() - 1
+            }
+        };
+
+        update(&mut jobs[index]);
+    }
+}
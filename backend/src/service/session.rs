@@ -0,0 +1,79 @@
+use chrono::{DateTime, Duration, Utc};
+
+use crate::{
+    error::AppError,
+    models::session::SessionEntity,
+    repository::{Database, SessionRepository},
+};
+
+/// `client_id` binds the session to a device, see [`revoke_device_sessions`].
+/// Sessions live as long as the refresh token issued with them, `lifetime`
+/// is the one of the token.
+pub async fn create_session(
+    db: &Database,
+    user_id: i64,
+    client_id: Option<&str>,
+    lifetime: Duration,
+) -> Result<String, AppError> {
+    create_session_until(db, user_id, client_id, Utc::now() + lifetime).await
+}
+
+/// Session with a fixed end, used for guest devices.
+pub async fn create_session_until(
+    db: &Database,
+    user_id: i64,
+    client_id: Option<&str>,
+    expires_at: DateTime<Utc>,
+) -> Result<String, AppError> {
+    db.create_session_until(user_id, client_id, expires_at)
+        .await
+}
+
+pub async fn is_session_active(db: &Database, id: &str, user_id: i64) -> Result<bool, AppError> {
+    db.is_session_active(id, user_id).await
+}
+
+/// Pushes the expiry of a still active session forward, called on token refresh.
+pub async fn extend_session(db: &Database, id: &str, lifetime: Duration) -> Result<(), AppError> {
+    db.extend_session(id, Utc::now() + lifetime).await
+}
+
+pub async fn revoke_user_sessions(db: &Database, user_id: i64) -> Result<u64, AppError> {
+    db.revoke_user_sessions(user_id).await
+}
+
+/// Ends every session of the user but the one of the current request.
+pub async fn revoke_other_sessions(
+    db: &Database,
+    user_id: i64,
+    keep: &str,
+) -> Result<u64, AppError> {
+    db.revoke_other_sessions(user_id, keep).await
+}
+
+/// Ends the sessions of the tokens issued to one device of the user.
+pub async fn revoke_device_sessions(
+    db: &Database,
+    user_id: i64,
+    client_id: &str,
+) -> Result<u64, AppError> {
+    db.revoke_device_sessions(user_id, client_id).await
+}
+
+/// Ties the session of a plain login to the device that first uses it, its
+/// tokens then only speak for that device.
+pub async fn bind_session_client(
+    db: &Database,
+    id: &str,
+    user_id: i64,
+    client_id: &str,
+) -> Result<bool, AppError> {
+    db.bind_session_client(id, user_id, client_id).await
+}
+
+pub async fn list_user_sessions(
+    db: &Database,
+    user_id: i64,
+) -> Result<Vec<SessionEntity>, AppError> {
+    db.list_user_sessions(user_id).await
+}
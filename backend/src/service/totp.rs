@@ -0,0 +1,69 @@
+use chrono::Utc;
+
+use crate::{
+    error::AppError,
+    models::totp::{hash_recovery_code, TotpEntity},
+    repository::{Database, TotpRepository},
+};
+
+pub async fn get_totp(db: &Database, user_id: i64) -> Result<Option<TotpEntity>, AppError> {
+    db.get_totp(user_id).await
+}
+
+pub async fn is_totp_enabled(db: &Database, user_id: i64) -> Result<bool, AppError> {
+    Ok(get_totp(db, user_id)
+        .await?
+        .is_some_and(|totp| totp.enabled))
+}
+
+/// Stores a not yet confirmed secret, replacing any earlier pending enrollment.
+pub async fn set_pending_totp(db: &Database, user_id: i64, secret: &str) -> Result<(), AppError> {
+    db.set_pending_totp(user_id, secret).await
+}
+
+/// Enables the pending secret and replaces all recovery codes with `code_hashes`.
+pub async fn enable_totp(
+    db: &Database,
+    user_id: i64,
+    step: i64,
+    code_hashes: &[String],
+) -> Result<(), AppError> {
+    db.enable_totp(user_id, step, code_hashes).await
+}
+
+pub async fn set_totp_last_step(db: &Database, user_id: i64, step: i64) -> Result<(), AppError> {
+    db.set_totp_last_step(user_id, step).await
+}
+
+/// Marks the recovery code as used, returns false if it is unknown or already used.
+pub async fn use_recovery_code(
+    db: &Database,
+    user_id: i64,
+    code_hash: &str,
+) -> Result<bool, AppError> {
+    db.use_recovery_code(user_id, code_hash).await
+}
+
+pub async fn delete_totp(db: &Database, user_id: i64) -> Result<(), AppError> {
+    db.delete_totp(user_id).await
+}
+
+/// Checks `code` against the enabled TOTP secret first, then against the unused
+/// recovery codes. A matching code is consumed so it cannot be replayed.
+pub async fn verify_second_factor(
+    db: &Database,
+    user_id: i64,
+    code: &str,
+) -> Result<bool, AppError> {
+    let totp = match get_totp(db, user_id).await? {
+        Some(totp) if totp.enabled => totp,
+        _ => return Err(AppError::MfaNotEnabled),
+    };
+
+    if let Some(step) = totp.verify(code, Utc::now().timestamp() as u64)? {
+        set_totp_last_step(db, user_id, step).await?;
+        return Ok(true);
+    }
+
+    use_recovery_code(db, user_id, &hash_recovery_code(code)).await
+}
@@ -1,94 +1,82 @@
 #![allow(dead_code)]
-use chrono::Utc;
-use sqlx::SqlitePool;
-
 use crate::{
     error::AppError,
-    models::user::{Role, UserCreate, UserEntity},
+    models::{
+        password::{HashParams, PasswordPolicy},
+        role::Permission,
+        user::{Role, UserCreate, UserDetails, UserEntity, UserListQuery, UserUpdate},
+    },
+    repository::{Database, UserRepository},
+    service::role::{has_permission, is_last_role_manager_user, role_exists},
 };
-use sqlx::{query, query_as};
+
+/// Checks a new account before [`create_user`]: required fields, unique email,
+/// existing role and the password policy.
+pub async fn validate_new_user(
+    db: &Database,
+    policy: &PasswordPolicy,
+    user: &UserCreate,
+) -> Result<(), AppError> {
+    if user.password.is_empty() || user.email.is_empty() || user.name.is_empty() {
+        return Err(AppError::EmptyPayload);
+    }
+
+    if get_user_by_email(db, user.email.as_str()).await?.is_some() {
+        return Err(AppError::UserAlreadyExits);
+    }
+
+    if !role_exists(db, &user.role).await? {
+        return Err(AppError::RoleDoesNotExist);
+    }
+
+    policy.validate(&user.password, &user.email)
+}
 
 pub async fn create_user(
-    pool: &SqlitePool,
-    user: &mut UserCreate
+    db: &Database,
+    user: &mut UserCreate,
+    params: &HashParams,
+) -> Result<UserEntity, AppError> {
+    user.hash_password(params)?;
+    db.create_user(user).await
+}
+
+/// Creates the account without a local password, see
+/// [`UserEntity::has_password`].
+pub async fn create_user_without_password(
+    db: &Database,
+    user: &UserCreate,
 ) -> Result<UserEntity, AppError> {
-    user.hash_password()?;
-    let email = &user.email;
-    let name = &user.name;
-    let password = &user.password;
-    let role = &user.role;
-    let record = query_as!(
-        UserEntity,
-        r#"INSERT INTO users (email, name, password, role) 
-            VALUES (?, ?, ?, ?) 
-            RETURNING id as "id!: i64", name as "name!:String", email as "email!: String", password as "password!: String", role as "role!: Role" "#,
-        email,
-        name,
-        password,
-        role
-    ).fetch_one(pool)
-    .await?;
-
-    Ok(record)
-}
-
-pub async fn get_user_by_id(pool: &SqlitePool, id: i64) -> Result<UserEntity, AppError> {
-    let user = query_as!(
-        UserEntity,
-        r#"SELECT id, name, email, password , role as "role!: Role" FROM users WHERE id = ?"#,
-        id
-    )
-    .fetch_one(pool)
-    .await
-    .map_err(|err| AppError::UserDoesNotExist)?;
-
-    Ok(user)
+    let user = UserCreate {
+        password: String::new(),
+        ..user.clone()
+    };
+    db.create_user(&user).await
+}
+
+pub async fn get_user_by_id(db: &Database, id: i64) -> Result<UserEntity, AppError> {
+    db.get_user_by_id(id).await
 }
 
 pub async fn get_user_by_id_email(
-    pool: &SqlitePool,
+    db: &Database,
     id: i64,
     email: &str,
 ) -> Result<UserEntity, AppError> {
-    let user = query_as!(
-        UserEntity,
-        r#"SELECT id, name, email, password, role as "role!: Role" FROM users WHERE id = ? AND email = ?"#,
-        id, email
-    )
-    .fetch_one(pool)
-    .await
-    .map_err(|err| AppError::UserDoesNotExist)?;
-
-    Ok(user)
+    db.get_user_by_id_email(id, email).await
 }
 
-pub async fn get_user_by_email(
-    pool: &SqlitePool,
-    email: &str,
-) -> Result<Option<UserEntity>, sqlx::Error> {
-    let user = query_as!(
-        UserEntity,
-        r#"SELECT 
-            id as "id!: i64", 
-            name as "name!:String", 
-            email as "email!: String", 
-            password as "password!: String", 
-            role as "role!: Role"
-        FROM users WHERE email = ?"#,
-        email
-    )
-    .fetch_optional(pool)
-    .await?;
-    Ok(user)
+pub async fn get_user_by_email(db: &Database, email: &str) -> Result<Option<UserEntity>, AppError> {
+    db.get_user_by_email(email).await
 }
 
 pub async fn chech_or_add_admin(
-    pool: &SqlitePool,
+    db: &Database,
     admin_email: &str,
     admin_password: &str,
     admin_name: &str,
 ) {
-    let admin = get_user_by_email(pool, admin_email).await;
+    let admin = get_user_by_email(db, admin_email).await;
 
     if let Ok(Some(admin)) = admin {
         tracing::debug!("User already exists");
@@ -96,7 +84,7 @@ pub async fn chech_or_add_admin(
     }
 
     if let Err(err) = admin {
-        tracing::error!("Unable to get user : {}", err);
+        tracing::error!("Unable to get user : {:?}", err);
         return;
     }
 
@@ -105,13 +93,119 @@ pub async fn chech_or_add_admin(
             email: admin_email.to_string(),
             password: admin_password.to_string(),
             name: admin_name.to_string(),
-            role: Role::ADMIN,
+            role: Role::admin(),
         };
 
-        if let Err(_) = create_user(pool, &mut admin).await {
+        // hashed with the defaults, upgraded on the first login if configured otherwise
+        if create_user(db, &mut admin, &HashParams::default())
+            .await
+            .is_err()
+        {
             tracing::error!("Unable to create admin user");
         }
     }
 }
 
+pub async fn update_user_password(
+    db: &Database,
+    id: i64,
+    password_hash: &str,
+) -> Result<(), AppError> {
+    db.update_user_password(id, password_hash).await
+}
+
+pub async fn update_user_role(db: &Database, id: i64, role: &Role) -> Result<(), AppError> {
+    db.update_user_role(id, role).await
+}
+
+/// Provider the user is pinned to, `None` for unknown users and users that
+/// may log in through any provider.
+pub async fn get_user_auth_provider(
+    db: &Database,
+    email: &str,
+) -> Result<Option<String>, AppError> {
+    db.get_user_auth_provider(email).await
+}
 
+pub async fn set_user_auth_provider(
+    db: &Database,
+    id: i64,
+    provider: Option<&str>,
+) -> Result<(), AppError> {
+    db.set_user_auth_provider(id, provider).await
+}
+
+pub async fn is_user_disabled(db: &Database, id: i64) -> Result<bool, AppError> {
+    db.is_user_disabled(id).await
+}
+
+pub async fn count_users(db: &Database, filter: &UserListQuery) -> Result<i64, AppError> {
+    db.count_users(filter).await
+}
+
+pub async fn list_users(
+    db: &Database,
+    filter: &UserListQuery,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<UserDetails>, AppError> {
+    db.list_users(filter, limit, offset).await
+}
+
+pub async fn get_user_details(db: &Database, id: i64) -> Result<UserDetails, AppError> {
+    db.get_user_details(id).await
+}
+
+/// Applies the present fields of `update`, checking that the new email is free,
+/// the new role exists and the last user managing roles keeps doing so.
+pub async fn update_user(
+    db: &Database,
+    id: i64,
+    update: &UserUpdate,
+) -> Result<UserDetails, AppError> {
+    let user = get_user_details(db, id).await?;
+
+    let email = update
+        .email
+        .as_deref()
+        .map(str::trim)
+        .unwrap_or(&user.email);
+    let name = update.name.as_deref().map(str::trim).unwrap_or(&user.name);
+    let role = update.role.as_ref().unwrap_or(&user.role);
+    if email.is_empty() || name.is_empty() {
+        return Err(AppError::EmptyPayload);
+    }
+    if email != user.email && get_user_by_email(db, email).await?.is_some() {
+        return Err(AppError::UserAlreadyExits);
+    }
+    if !role_exists(db, role).await? {
+        return Err(AppError::RoleDoesNotExist);
+    }
+    if *role != user.role
+        && !has_permission(db, role, Permission::ManageRoles).await?
+        && is_last_role_manager_user(db, &user).await?
+    {
+        return Err(AppError::LastRoleManagerUser);
+    }
+
+    db.update_user_profile(id, email, name, role).await?;
+
+    get_user_details(db, id).await
+}
+
+/// Refuses to disable the last enabled user managing roles.
+pub async fn set_user_disabled(db: &Database, id: i64, disabled: bool) -> Result<(), AppError> {
+    if disabled && is_last_role_manager_user(db, &get_user_details(db, id).await?).await? {
+        return Err(AppError::LastRoleManagerUser);
+    }
+    db.set_user_disabled(id, disabled).await
+}
+
+/// Everything owned by the user goes with it, the audit log keeps its entries.
+/// Refuses to delete the last enabled user managing roles.
+pub async fn delete_user(db: &Database, id: i64) -> Result<(), AppError> {
+    if is_last_role_manager_user(db, &get_user_details(db, id).await?).await? {
+        return Err(AppError::LastRoleManagerUser);
+    }
+    db.delete_user(id).await
+}
@@ -0,0 +1,262 @@
+//! HTTPS and WSS for the listeners of [`ScytaleApp`](crate::ScytaleApp), see
+//! [`TlsConfig`].
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use axum::{extract::Host, http::Uri, response::Redirect, Router};
+use axum_server::{
+    accept::Accept,
+    tls_rustls::{RustlsAcceptor, RustlsConfig},
+};
+use futures::future::BoxFuture;
+use rcgen::{CertificateParams, DistinguishedName, DnType};
+use rustls::{
+    server::{
+        AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient, ClientCertVerifier,
+        NoClientAuth,
+    },
+    Certificate, PrivateKey, RootCertStore, ServerConfig,
+};
+use rustls_pemfile::Item;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    task::JoinHandle,
+};
+use tokio_rustls::server::TlsStream;
+use tower_http::add_extension::AddExtension;
+
+use crate::{
+    error::AppError,
+    middleware::PeerCert,
+    models::{config::TlsConfig, device::ClientCert},
+};
+
+fn tls_error(path: &Path, err: impl std::fmt::Display) -> AppError {
+    AppError::Tls(format!("{}: {}", path.display(), err))
+}
+
+fn files(config: &TlsConfig) -> Result<(&Path, &Path), AppError> {
+    match (&config.cert_file, &config.key_file) {
+        (Some(cert_file), Some(key_file)) => Ok((cert_file, key_file)),
+        _ => Err(AppError::Tls("cert_file and key_file must be set".into())),
+    }
+}
+
+/// The certificate of `config`, generated first when it is self-signed and
+/// does not exist yet.
+pub(crate) fn load(config: &TlsConfig) -> Result<RustlsConfig, AppError> {
+    let (cert_file, key_file) = files(config)?;
+    if config.self_signed && !cert_file.exists() && !key_file.exists() {
+        generate_self_signed(&config.self_signed_names, cert_file, key_file)?;
+        tracing::info!(
+            "Generated a self-signed certificate for {} at {}",
+            config.self_signed_names.join(", "),
+            cert_file.display()
+        );
+    }
+    Ok(RustlsConfig::from_config(server_config(config)?))
+}
+
+fn generate_self_signed(
+    names: &[String],
+    cert_file: &Path,
+    key_file: &Path,
+) -> Result<(), AppError> {
+    let mut params = CertificateParams::new(names.to_vec());
+    let mut subject = DistinguishedName::new();
+    subject.push(DnType::CommonName, "scytale self-signed");
+    params.distinguished_name = subject;
+    let cert = rcgen::Certificate::from_params(params)
+        .map_err(|err| AppError::Tls(format!("unable to generate certificate: {}", err)))?;
+    let cert_pem = cert
+        .serialize_pem()
+        .map_err(|err| AppError::Tls(format!("unable to generate certificate: {}", err)))?;
+
+    for path in [cert_file, key_file] {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).map_err(|err| tls_error(dir, err))?;
+        }
+    }
+    write_private(key_file, cert.serialize_private_key_pem().as_bytes())
+        .map_err(|err| tls_error(key_file, err))?;
+    fs::write(cert_file, cert_pem).map_err(|err| tls_error(cert_file, err))
+}
+
+/// Writes a file only the owner can read.
+fn write_private(path: &Path, content: &[u8]) -> io::Result<()> {
+    use std::io::Write;
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(content)
+}
+
+fn read_pem(path: &Path) -> Result<Vec<Item>, AppError> {
+    let content = fs::read(path).map_err(|err| tls_error(path, err))?;
+    rustls_pemfile::read_all(&mut content.as_slice()).map_err(|err| tls_error(path, err))
+}
+
+fn read_certs(path: &Path) -> Result<Vec<Certificate>, AppError> {
+    let certs: Vec<_> = read_pem(path)?
+        .into_iter()
+        .filter_map(|item| match item {
+            Item::X509Certificate(der) => Some(Certificate(der)),
+            _ => None,
+        })
+        .collect();
+    if certs.is_empty() {
+        return Err(tls_error(path, "no certificate found"));
+    }
+    Ok(certs)
+}
+
+fn client_verifier(config: &TlsConfig) -> Result<Arc<dyn ClientCertVerifier>, AppError> {
+    let Some(ca_file) = &config.client_ca_file else {
+        return Ok(NoClientAuth::boxed());
+    };
+    let mut roots = RootCertStore::empty();
+    for cert in read_certs(ca_file)? {
+        roots.add(&cert).map_err(|err| tls_error(ca_file, err))?;
+    }
+    Ok(match config.client_cert_required {
+        true => AllowAnyAuthenticatedClient::new(roots).boxed(),
+        false => AllowAnyAnonymousOrAuthenticatedClient::new(roots).boxed(),
+    })
+}
+
+fn server_config(config: &TlsConfig) -> Result<Arc<ServerConfig>, AppError> {
+    let (cert_file, key_file) = files(config)?;
+    let certs = read_certs(cert_file)?;
+    let key = read_pem(key_file)?
+        .into_iter()
+        .find_map(|item| match item {
+            Item::PKCS8Key(der) | Item::RSAKey(der) | Item::ECKey(der) => Some(PrivateKey(der)),
+            _ => None,
+        })
+        .ok_or_else(|| tls_error(key_file, "no private key found"))?;
+
+    let mut server_config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_client_cert_verifier(client_verifier(config)?)
+        .with_single_cert(certs, key)
+        .map_err(|err| tls_error(key_file, err))?;
+    // WebSockets are upgraded from HTTP/1.1 only
+    server_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(Arc::new(server_config))
+}
+
+fn modified(paths: &[PathBuf]) -> Option<Vec<SystemTime>> {
+    paths
+        .iter()
+        .map(|path| fs::metadata(path).and_then(|meta| meta.modified()).ok())
+        .collect()
+}
+
+/// Swaps in the certificate and client CAs whenever their files change, e.g.
+/// after a renewal. Connections keep the certificate they started with and a
+/// broken replacement keeps the previous one.
+pub(crate) fn spawn_reload(rustls: RustlsConfig, config: &TlsConfig) -> Option<JoinHandle<()>> {
+    if config.reload_interval_seconds == 0 {
+        return None;
+    }
+    let (cert_file, key_file) = files(config).ok()?;
+    let paths: Vec<_> = [
+        Some(cert_file),
+        Some(key_file),
+        config.client_ca_file.as_deref(),
+    ]
+    .into_iter()
+    .flatten()
+    .map(Path::to_path_buf)
+    .collect();
+    let interval = Duration::from_secs(config.reload_interval_seconds);
+    let config = config.clone();
+
+    Some(tokio::spawn(async move {
+        let mut last = modified(&paths);
+        loop {
+            tokio::time::sleep(interval).await;
+            let current = modified(&paths);
+            if current.is_none() || current == last {
+                continue;
+            }
+            last = current;
+            match server_config(&config) {
+                Ok(server_config) => {
+                    rustls.reload_from_config(server_config);
+                    tracing::info!("Reloaded the certificate from {}", paths[0].display());
+                }
+                Err(err) => tracing::warn!("Keeping the previous certificate: {}", err),
+            }
+        }
+    }))
+}
+
+/// Hands the [`ClientCert`] of every connection to the extractors as a
+/// [`PeerCert`] extension.
+#[derive(Clone)]
+pub(crate) struct ClientCertAcceptor {
+    inner: RustlsAcceptor,
+}
+
+impl ClientCertAcceptor {
+    pub(crate) fn new(rustls: RustlsConfig) -> Self {
+        Self {
+            inner: RustlsAcceptor::new(rustls),
+        }
+    }
+}
+
+impl<I, S> Accept<I, S> for ClientCertAcceptor
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    S: Send + 'static,
+{
+    type Stream = TlsStream<I>;
+    type Service = AddExtension<S, PeerCert>;
+    type Future = BoxFuture<'static, io::Result<(Self::Stream, Self::Service)>>;
+
+    fn accept(&self, stream: I, service: S) -> Self::Future {
+        let inner = self.inner.clone();
+        Box::pin(async move {
+            let (stream, service) = inner.accept(stream, service).await?;
+            // verified by rustls already, the first one is the client's own
+            let cert = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .and_then(|cert| ClientCert::from_der(&cert.0));
+            Ok((stream, AddExtension::new(service, PeerCert(cert))))
+        })
+    }
+}
+
+/// Answers every request with a permanent redirect to the same url on the
+/// HTTPS port.
+pub(crate) fn redirect_router(https_port: u16) -> Router {
+    Router::new().fallback(move |Host(host): Host, uri: Uri| async move {
+        let host = strip_port(&host);
+        let authority = match https_port {
+            443 => host.to_string(),
+            port => format!("{}:{}", host, port),
+        };
+        let path = uri.path_and_query().map_or("/", |path| path.as_str());
+        Redirect::permanent(&format!("https://{}{}", authority, path))
+    })
+}
+
+/// `host` of `host:port`, IPv6 addresses keep their brackets.
+fn strip_port(host: &str) -> &str {
+    match host.rsplit_once(':') {
+        Some((name, port)) if port.bytes().all(|byte| byte.is_ascii_digit()) => name,
+        _ => host,
+    }
+}
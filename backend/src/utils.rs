@@ -1,30 +1,42 @@
 use std::sync::Arc;
 
 use axum::{
-    routing::{get, post},
+    extract::DefaultBodyLimit,
+    http::HeaderValue,
+    routing::{delete, get, patch, post, put},
     Router,
 };
 use bcrypt::{BcryptError, DEFAULT_COST};
 use jsonwebtoken::{Header, Validation};
-use sqlx::{migrate::MigrateDatabase, Sqlite, SqlitePool};
+use rand::{distributions::Alphanumeric, Rng};
+use sha2::{Digest, Sha256};
 use tokio::sync::Mutex;
-use tower_http::cors::{Any, CorsLayer};
+use tower_http::{
+    cors::{Any, CorsLayer},
+    timeout::TimeoutLayer,
+};
 
 #[cfg(feature = "webapp")]
 use crate::controllers::spa::SpaController;
 
 use crate::{
     controllers::{
-        admin::AdminController, auth::AuthController, token::TokenController, user::UserController,
-        websocket::WebsocketController,
+        admin::AdminController, audit::AuditController, auth::AuthController,
+        backup::BackupController, invite::InviteController, oidc::OidcController,
+        pairing::PairingController, role::RoleController, token::TokenController,
+        totp::TotpController, user::UserController, websocket::WebsocketController,
     },
     error::AppError,
-    middleware::is_admin,
+    middleware::{permission, require_permission},
     models::{
+        auth::LoginResponse,
+        config::{CorsConfig, LimitsConfig},
         jwt::{Claims, Keys, TokenType},
         state::AppState,
         user::UserEntity,
     },
+    repository::Database,
+    service::{session::create_session, user::is_user_disabled},
 };
 
 // consume password value to make it unusable
@@ -32,20 +44,94 @@ pub async fn encode_token(
     user: &UserEntity,
     keys: &Keys,
     token_type: TokenType,
+    session_id: Option<&str>,
 ) -> Result<String, AppError> {
-    let token = jsonwebtoken::encode::<Claims>(
-        &Header::default(),
-        &Claims::from(user, token_type),
-        &keys.encoding,
-    )
-    .map_err(|err| {
-        tracing::error!("Error encoding token: {:?}", err);
-        AppError::InternalServerError
-    })?;
+    encode_device_token(user, keys, token_type, session_id, None).await
+}
+
+/// Same as [`encode_token`], bound to the device with `client_id`.
+pub async fn encode_device_token(
+    user: &UserEntity,
+    keys: &Keys,
+    token_type: TokenType,
+    session_id: Option<&str>,
+    client_id: Option<&str>,
+) -> Result<String, AppError> {
+    let claims =
+        Claims::from(user, token_type, session_id, keys.token_lifetime).bound_to(client_id);
+    encode_claims(&claims, keys)
+}
+
+pub fn encode_claims(claims: &Claims, keys: &Keys) -> Result<String, AppError> {
+    let token = jsonwebtoken::encode::<Claims>(&Header::default(), claims, &keys.encoding)
+        .map_err(|err| {
+            tracing::error!("Error encoding token: {:?}", err);
+            AppError::InternalServerError
+        })?;
 
     Ok(token)
 }
 
+/// Starts a new session and issues the access and refresh token pair handed
+/// out after a successful login, refused for disabled users.
+pub async fn login_response(
+    db: &Database,
+    user: &UserEntity,
+    keys: &Keys,
+    message: &str,
+) -> Result<LoginResponse, AppError> {
+    issue_tokens(db, user, keys, None, message).await
+}
+
+/// Same as [`login_response`] with tokens bound to the device `client_id`,
+/// revoking the device ends their session.
+pub async fn device_login_response(
+    db: &Database,
+    user: &UserEntity,
+    keys: &Keys,
+    client_id: &str,
+    message: &str,
+) -> Result<LoginResponse, AppError> {
+    issue_tokens(db, user, keys, Some(client_id), message).await
+}
+
+async fn issue_tokens(
+    db: &Database,
+    user: &UserEntity,
+    keys: &Keys,
+    client_id: Option<&str>,
+    message: &str,
+) -> Result<LoginResponse, AppError> {
+    if is_user_disabled(db, user.id).await? {
+        return Err(AppError::UserDisabled);
+    }
+    let session_id = create_session(db, user.id, client_id, keys.token_lifetime).await?;
+    let access_token = encode_device_token(
+        user,
+        keys,
+        TokenType::AccessToken,
+        Some(&session_id),
+        client_id,
+    )
+    .await?;
+    let refresh_token = encode_device_token(
+        user,
+        keys,
+        TokenType::RefreshToken,
+        Some(&session_id),
+        client_id,
+    )
+    .await?;
+
+    Ok(LoginResponse {
+        message: message.to_string(),
+        id: user.id,
+        role: user.role.clone(),
+        access_token,
+        refresh_token,
+    })
+}
+
 pub async fn decode_token(token: &str, keys: &Keys) -> Result<Claims, AppError> {
     let claims = jsonwebtoken::decode::<Claims>(token, &keys.decoding, &Validation::default())
         .map_err(|err| {
@@ -56,37 +142,37 @@ pub async fn decode_token(token: &str, keys: &Keys) -> Result<Claims, AppError>
     Ok(claims.claims)
 }
 
-pub async fn setup_db(db_url: &str) -> SqlitePool {
-    if Sqlite::database_exists(&db_url)
-        .await
-        .expect("unable to check if database exists")
-    {
-        tracing::debug!("Database exists");
-    } else {
-        tracing::debug!("Database does not exist");
-        Sqlite::create_database(&db_url)
-            .await
-            .expect("unable to create database");
-    }
-
-    let pool = sqlx::SqlitePool::connect(&db_url)
-        .await
-        .expect("unable to connect to database");
-    sqlx::migrate!()
-        .run(&pool)
-        .await
-        .expect("unable to run migrations");
+/// Random alphanumeric token for session ids, reset links and the like.
+pub fn generate_token(len: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
 
-    pool
+/// Tokens are random enough that a plain SHA-256 is sufficient for storage.
+pub fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
 }
 
-pub fn get_state(pool: SqlitePool, secret: &str) -> Arc<Mutex<AppState>> {
-    let app_state = AppState::new(pool, secret);
-    let app_state = Arc::new(Mutex::new(app_state));
-    app_state
+pub fn get_state(db: Database, secret: &str) -> Arc<Mutex<AppState>> {
+    let app_state = AppState::new(db, secret);
+    Arc::new(Mutex::new(app_state))
 }
 
+/// Router with the defaults of the server config, used by the tests.
+#[cfg(test)]
 pub fn get_default_router(state: Arc<Mutex<AppState>>) -> Router {
+    get_router(state, &CorsConfig::default(), &LimitsConfig::default())
+}
+
+/// Same as [`get_default_router`] with the CORS policy and request limits of
+/// the server config.
+pub fn get_router(state: Arc<Mutex<AppState>>, cors: &CorsConfig, limits: &LimitsConfig) -> Router {
     let token_routes = Router::new()
         .route("/token/authenticate", get(TokenController::authenticated))
         .route("/token", post(TokenController::refresh));
@@ -94,21 +180,121 @@ pub fn get_default_router(state: Arc<Mutex<AppState>>) -> Router {
     let admin_routes = Router::new()
         .route("/admin", get(AdminController::admin))
         .route("/admin/register", post(AdminController::register))
+        .route("/admin/users", get(AdminController::list_users))
+        .route(
+            "/admin/user/:id",
+            get(AdminController::get_user)
+                .patch(AdminController::update_user)
+                .delete(AdminController::delete_user),
+        )
+        .route("/admin/user/:id/disable", post(AdminController::disable))
+        .route("/admin/user/:id/enable", post(AdminController::enable))
+        .route("/admin/user/:id/logout", post(AdminController::logout))
+        .route("/admin/user/:id/totp", delete(AdminController::reset_totp))
+        .route("/admin/user/:id/unlock", post(AdminController::unlock))
+        .route(
+            "/admin/user/:id/auth-provider",
+            put(AdminController::set_auth_provider),
+        )
+        .route(
+            "/admin/user/:id/password-reset",
+            post(AdminController::create_password_reset),
+        )
+        .route(
+            "/admin/user/:id/retention",
+            put(AdminController::set_retention),
+        )
+        .route(
+            "/admin/invites",
+            get(InviteController::list).post(InviteController::create),
+        )
+        .route("/admin/invites/:id", delete(InviteController::revoke))
+        .route_layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            require_permission::<permission::ManageUsers, _>,
+        ));
+
+    let role_routes = Router::new()
+        .route("/admin/roles", get(RoleController::list))
+        .route(
+            "/admin/roles/:name",
+            get(RoleController::get)
+                .put(RoleController::upsert)
+                .delete(RoleController::delete),
+        )
         .route_layer(axum::middleware::from_fn_with_state(
             state.clone(),
-            is_admin,
+            require_permission::<permission::ManageRoles, _>,
         ));
 
-    let user_route = Router::new().route("/user/client", get(UserController::get_clients));
+    let backup_routes = Router::new()
+        .route("/admin/backup", get(BackupController::backup))
+        .route_layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            require_permission::<permission::ManageBackups, _>,
+        ));
+
+    let job_routes = Router::new()
+        .route("/admin/jobs", get(AdminController::jobs))
+        .route_layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            require_permission::<permission::ReadMetrics, _>,
+        ));
+
+    let audit_routes = Router::new()
+        .route("/admin/audit", get(AuditController::list))
+        .route("/admin/audit/export", get(AuditController::export))
+        .route_layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            require_permission::<permission::ViewAuditLog, _>,
+        ));
+
+    let user_route = Router::new()
+        .route(
+            "/user/client",
+            get(UserController::get_clients).post(UserController::register_client),
+        )
+        .route("/user/guest", post(UserController::create_guest))
+        .route(
+            "/user/client/:id",
+            patch(UserController::rename_client).delete(UserController::revoke_client),
+        )
+        .route(
+            "/user",
+            patch(UserController::update_profile).delete(UserController::delete_account),
+        )
+        .route("/user/export", get(UserController::export))
+        .route("/user/password", post(UserController::change_password))
+        .route(
+            "/user/retention",
+            get(UserController::get_retention).put(UserController::set_retention),
+        )
+        .route(
+            "/user/totp",
+            post(TotpController::enroll).delete(TotpController::disable),
+        )
+        .route("/user/totp/confirm", post(TotpController::confirm));
 
     let auth_routes = Router::new()
         .route("/authenticated", get(AuthController::authenticated))
-        .route("/login", post(AuthController::login));
+        .route("/login", post(AuthController::login))
+        .route("/login/mfa", post(AuthController::login_mfa))
+        .route("/password-reset", post(AuthController::reset_password))
+        .route("/register", post(InviteController::register))
+        .route("/oidc/login", get(OidcController::login))
+        .route("/oidc/callback", get(OidcController::callback))
+        .route("/pair", post(PairingController::start))
+        .route("/pair/token", post(PairingController::token))
+        .route("/pair/approve", post(PairingController::approve));
 
     let websocket_routes = Router::new().route("/ws", get(WebsocketController::ws_handler));
 
     let app_router = Router::new()
         .merge(admin_routes)
+        .merge(role_routes)
+        .merge(audit_routes)
+        .merge(backup_routes)
+        .merge(job_routes)
         .merge(auth_routes)
         .merge(token_routes)
         .merge(websocket_routes)
@@ -120,24 +306,54 @@ pub fn get_default_router(state: Arc<Mutex<AppState>>) -> Router {
         .route("/index.html", get(SpaController::index_handler))
         .route("/*path", get(SpaController::static_handler))
         .fallback_service(get(SpaController::index_handler))
-        .layer(
-            CorsLayer::new()
-                .allow_origin(Any)
-                .allow_methods(Any)
-                .allow_headers(Any),
-        )
+        .layer(cors_layer(cors))
         .with_state(state.clone());
 
     #[cfg(not(feature = "webapp"))]
     let app = Router::new()
         .nest("/api", app_router)
-        .layer(
-            CorsLayer::new()
-                .allow_origin(Any)
-                .allow_methods(Any)
-                .allow_headers(Any),
-        )
+        .layer(cors_layer(cors))
         .with_state(state.clone());
 
-    app
+    app.layer(DefaultBodyLimit::max(limits.max_body_bytes))
+        .layer(TimeoutLayer::new(std::time::Duration::from_secs(
+            limits.request_timeout_seconds,
+        )))
+}
+
+fn cors_layer(cors: &CorsConfig) -> CorsLayer {
+    let layer = CorsLayer::new().allow_methods(Any).allow_headers(Any);
+    if cors.allows_any() {
+        return layer.allow_origin(Any);
+    }
+    // checked by `Config::validate`
+    let origins = cors
+        .allowed_origins
+        .iter()
+        .filter_map(|origin| origin.parse::<HeaderValue>().ok())
+        .collect::<Vec<_>>();
+    layer.allow_origin(origins)
+}
+
+/// Packs `(name, contents)` pairs into an in-memory zip archive.
+pub fn zip_archive(files: &[(&str, Vec<u8>)]) -> Result<Vec<u8>, AppError> {
+    let mut archive = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    let options =
+        zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+
+    for (name, contents) in files {
+        archive
+            .start_file(*name, options)
+            .and_then(|_| std::io::Write::write_all(&mut archive, contents).map_err(Into::into))
+            .map_err(|err| {
+                tracing::error!("Error writing zip archive: {:?}", err);
+                AppError::InternalServerError
+            })?;
+    }
+
+    let archive = archive.finish().map_err(|err| {
+        tracing::error!("Error writing zip archive: {:?}", err);
+        AppError::InternalServerError
+    })?;
+    Ok(archive.into_inner())
 }